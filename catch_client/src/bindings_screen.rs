@@ -0,0 +1,131 @@
+use glium::glutin::VirtualKeyCode;
+
+use player_input::{InputMap, Binding, Trigger, keys_name};
+
+/// In-game screen for changing the bindings of an `InputMap`.
+///
+/// Up/Down select a binding, Return starts rebinding it, Insert adds another binding for the
+/// same input and Delete removes it. When rebinding, the first trigger that is pressed becomes
+/// the binding. If another trigger is pressed while the first one is still held, the first one
+/// becomes a modifier, which allows binding chords.
+pub struct BindingsScreen {
+    selected: usize,
+    capturing: bool,
+
+    // The first trigger pressed while capturing
+    pending: Option<Trigger>,
+}
+
+impl BindingsScreen {
+    pub fn new() -> BindingsScreen {
+        BindingsScreen {
+            selected: 0,
+            capturing: false,
+            pending: None,
+        }
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capturing
+    }
+
+    pub fn cancel_capture(&mut self) {
+        self.capturing = false;
+        self.pending = None;
+    }
+
+    pub fn on_trigger(&mut self, pressed: bool, trigger: Trigger, input_map: &mut InputMap) {
+        if self.capturing {
+            self.on_capture_trigger(pressed, trigger, input_map);
+            return;
+        }
+
+        if !pressed {
+            return;
+        }
+
+        let num_bindings = input_map.bindings().len();
+        match trigger {
+            Trigger::Key(VirtualKeyCode::Up) => {
+                if self.selected > 0 {
+                    self.selected -= 1;
+                }
+            }
+            Trigger::Key(VirtualKeyCode::Down) => {
+                if self.selected + 1 < num_bindings {
+                    self.selected += 1;
+                }
+            }
+            Trigger::Key(VirtualKeyCode::Return) => {
+                if self.selected < num_bindings {
+                    self.capturing = true;
+                }
+            }
+            Trigger::Key(VirtualKeyCode::Insert) => {
+                if self.selected < num_bindings {
+                    input_map.duplicate_binding(self.selected);
+                    self.selected += 1;
+                    self.capturing = true;
+                }
+            }
+            Trigger::Key(VirtualKeyCode::Delete) => {
+                if self.selected < num_bindings {
+                    input_map.remove_binding(self.selected);
+                    if self.selected > 0 && self.selected == num_bindings - 1 {
+                        self.selected -= 1;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn on_capture_trigger(&mut self, pressed: bool, trigger: Trigger,
+                          input_map: &mut InputMap) {
+        // Keys without a name in the config file would make the saved bindings unloadable
+        if !trigger.is_bindable() {
+            return;
+        }
+
+        match (pressed, self.pending) {
+            (true, None) => {
+                self.pending = Some(trigger);
+            }
+            (true, Some(modifier)) => {
+                if modifier != trigger {
+                    input_map.set_binding(self.selected, Binding::chord(modifier, trigger));
+                    self.cancel_capture();
+                }
+            }
+            (false, Some(pending)) => {
+                if pending == trigger {
+                    input_map.set_binding(self.selected, Binding::new(trigger));
+                    self.cancel_capture();
+                }
+            }
+            (false, None) => {
+                // Release of something that was held before capturing started
+            }
+        }
+    }
+
+    /// Text lines to display, together with whether the line is selected
+    pub fn lines(&self, input_map: &InputMap) -> Vec<(String, bool)> {
+        let mut lines = Vec::new();
+
+        for (i, &(ref keys, binding)) in input_map.bindings().iter().enumerate() {
+            let binding_name =
+                if self.capturing && i == self.selected {
+                    match self.pending {
+                        Some(trigger) => format!("{}+...", trigger.name()),
+                        None => "press a key or button...".to_string(),
+                    }
+                } else {
+                    binding.name()
+                };
+            lines.push((format!("{:<24} {}", keys_name(keys), binding_name), i == self.selected));
+        }
+
+        lines
+    }
+}
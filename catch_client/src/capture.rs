@@ -0,0 +1,166 @@
+//! Screenshots and recordings of what is shown in the window
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+
+use image::{self, ImageBuffer, Rgba};
+
+use glium::Display;
+use glium::texture::RawImage2d;
+
+pub type Image = ImageBuffer<Rgba<u8>, Vec<u8>>;
+
+/// Frames per second of recordings
+pub const RECORDING_FPS: f32 = 30.0;
+
+// Frames waiting for the writer thread. Each one holds a full copy of the window.
+const MAX_QUEUED_FRAMES: usize = 4;
+
+/// Reads back what was shown by the last finished frame
+pub fn read_window(display: &Display) -> Result<Image, String> {
+    let pixels: RawImage2d<u8> = display.read_front_buffer();
+    let image = try!(ImageBuffer::from_raw(pixels.width, pixels.height, pixels.data.into_owned())
+                         .ok_or("window contents have the wrong size".to_string()));
+
+    // OpenGL stores the bottom row first
+    Ok(image::imageops::flip_vertical(&image))
+}
+
+/// Returns the first path of the form `{prefix}-{n}{suffix}` that does not exist yet
+fn unused_path(prefix: &str, suffix: &str) -> PathBuf {
+    let mut n = 1;
+    loop {
+        let path = PathBuf::from(format!("{}-{}{}", prefix, n, suffix));
+        if !path.exists() {
+            return path;
+        }
+        n += 1;
+    }
+}
+
+/// Saves the contents of the window to a new PNG file in the working directory
+pub fn save_screenshot(display: &Display) -> Result<PathBuf, String> {
+    let image = try!(read_window(display));
+    let path = unused_path("screenshot", ".png");
+
+    try!(image.save(&path).map_err(|error| {
+        format!("could not save screenshot {:?}: {}", path, error)
+    }));
+    Ok(path)
+}
+
+/// Writes the window contents at a fixed framerate as numbered PNG files into a new directory.
+/// Frames are repeated or skipped as needed, so the files can be turned into a video that plays
+/// in real time, e.g. with `ffmpeg -framerate 30 -i frame-%06d.png video.mp4`.
+pub struct Recording {
+    dir: PathBuf,
+    frame_interval_s: f32,
+
+    // Time until the next frame is to be written, can be negative if the game runs slower
+    time_until_frame_s: f32,
+    num_frames: usize,
+
+    // Encoding PNGs is too slow to do in the game loop, so it happens on a separate thread.
+    // Each image is sent once, with the paths of all frames that repeat it. None only while
+    // dropping.
+    frame_sender: Option<SyncSender<(Image, Vec<PathBuf>)>>,
+
+    // Frame that did not fit into the queue. Its paths are merged into the next frame.
+    pending_frame: Option<(Image, Vec<PathBuf>)>,
+    writer_thread: Option<JoinHandle<()>>,
+}
+
+impl Recording {
+    pub fn start(fps: f32) -> Result<Recording, String> {
+        let dir = unused_path("recording", "");
+        try!(fs::create_dir(&dir).map_err(|error| {
+            format!("could not create recording directory {:?}: {}", dir, error)
+        }));
+
+        let (frame_sender, frame_receiver) =
+            sync_channel::<(Image, Vec<PathBuf>)>(MAX_QUEUED_FRAMES);
+        let writer_thread = thread::spawn(move || {
+            for (image, paths) in frame_receiver.iter() {
+                if let Err(error) = image.save(&paths[0]) {
+                    warn!("could not save frame {:?}: {}", paths[0], error);
+                    continue;
+                }
+
+                // Repeated frames link to the first file instead of encoding it again
+                for path in &paths[1..] {
+                    let result = fs::hard_link(&paths[0], path)
+                                     .or_else(|_| fs::copy(&paths[0], path).map(|_| ()));
+                    if let Err(error) = result {
+                        warn!("could not save frame {:?}: {}", path, error);
+                    }
+                }
+            }
+        });
+
+        Ok(Recording {
+            dir: dir,
+            frame_interval_s: 1.0 / fps,
+            time_until_frame_s: 0.0,
+            num_frames: 0,
+            frame_sender: Some(frame_sender),
+            pending_frame: None,
+            writer_thread: Some(writer_thread),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.num_frames
+    }
+
+    /// Should be called after each finished frame, with the time it took
+    pub fn on_frame(&mut self, display: &Display, frame_s: f32) -> Result<(), String> {
+        self.time_until_frame_s -= frame_s;
+        if self.time_until_frame_s > 0.0 {
+            return Ok(());
+        }
+
+        let image = try!(read_window(display));
+
+        // If the writer could not keep up, the frames that were not queued repeat this one
+        let mut paths = self.pending_frame.take().map_or(Vec::new(), |(_, paths)| paths);
+        while self.time_until_frame_s <= 0.0 {
+            paths.push(self.dir.join(format!("frame-{:06}.png", self.num_frames)));
+
+            self.num_frames += 1;
+            self.time_until_frame_s += self.frame_interval_s;
+        }
+
+        match self.frame_sender.as_ref().unwrap().try_send((image, paths)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(frame)) => {
+                self.pending_frame = Some(frame);
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                Err("the recording writer thread has stopped".to_string())
+            }
+        }
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        if let (Some(frame_sender), Some(frame)) = (self.frame_sender.as_ref(),
+                                                    self.pending_frame.take()) {
+            frame_sender.send(frame).ok();
+        }
+
+        // Closing the channel lets the thread finish writing the frames and stop
+        self.frame_sender = None;
+
+        if let Some(writer_thread) = self.writer_thread.take() {
+            writer_thread.join().ok();
+        }
+    }
+}
@@ -1,20 +1,22 @@
 use std::collections::VecDeque;
 use time;
 
-use enet;
 use bincode::SizeLimit;
 use bincode::rustc_serialize::{encode, decode, decode_from};
 
 use shared::net;
 use shared::net::{ClientMessage, ServerMessage};
 use shared::{GameInfo, PlayerId, Tick, TickNumber};
+use shared::transport::{Transport, TransportEvent, PeerId, Reliability, EnetTransport,
+                        MemoryTransport, MemoryAddress};
 
 pub struct Client {
-    host: enet::Host,
-    server_peer: enet::Peer,
+    transport: Box<Transport>,
+    server_peer: PeerId,
     connected: bool,
 
     my_name: String,
+    my_color: [f32; 3],
     my_id: Option<PlayerId>,
 
     game_info: Option<GameInfo>,
@@ -26,36 +28,70 @@ pub struct Client {
     tick_deque: VecDeque<(time::Timespec, Tick)>,
 
     last_tick: Option<Tick>,
+
+    // Bytes of messages and ticks received so far, for statistics
+    num_received_bytes: usize,
 }
 
 impl Client {
+    /// Connects to a server over the network
     pub fn connect(timeout_ms: u32,
                    host_name: String,
                    port: u16,
-                   my_name: String) -> Result<Client, String> {
-        let (host, server_peer) =
-            try!(enet::Host::connect(timeout_ms,
-                                     host_name,
-                                     port,
-                                     net::NUM_CHANNELS as u32,
-                                     0, 0));
-
-        Ok(Client {
-            host: host,
+                   my_name: String,
+                   my_color: [f32; 3]) -> Result<Client, String> {
+        let (transport, server_peer) =
+            try!(EnetTransport::connect(timeout_ms,
+                                        host_name,
+                                        port,
+                                        net::NUM_CHANNELS));
+
+        Ok(Client::new(Box::new(transport), server_peer, my_name, my_color))
+    }
+
+    /// Connects to a server in the same process
+    pub fn connect_local(address: &MemoryAddress,
+                         my_name: String,
+                         my_color: [f32; 3]) -> Result<Client, String> {
+        let mut transport = MemoryTransport::new();
+        let server_peer = try!(transport.connect(address));
+
+        Ok(Client::new(Box::new(transport), server_peer, my_name, my_color))
+    }
+
+    /// Uses a transport that is connected to the server already
+    pub fn new(transport: Box<Transport>,
+               server_peer: PeerId,
+               my_name: String,
+               my_color: [f32; 3]) -> Client {
+        Client {
+            transport: transport,
             server_peer: server_peer,
             connected: false,
             my_name: my_name,
+            my_color: my_color,
             my_id: None,
             game_info: None,
             message_deque: VecDeque::new(),
             tick_deque: VecDeque::new(),
             last_tick: None,
-        })
+            num_received_bytes: 0,
+        }
     }
 
-    pub fn send(&self, message: &ClientMessage) {
+    /// Takes the transport, e.g. to wrap it in another one
+    pub fn map_transport<F>(self, f: F) -> Client
+        where F: FnOnce(Box<Transport>) -> Box<Transport> {
+        Client {
+            transport: f(self.transport),
+            .. self
+        }
+    }
+
+    pub fn send(&mut self, message: &ClientMessage) {
         let data: Vec<u8> = encode(message, SizeLimit::Infinite).unwrap();
-        self.server_peer.send(&data, enet::ffi::ENET_PACKET_FLAG_RELIABLE, 0);
+        self.transport.send(self.server_peer, net::Channel::Messages as u8,
+                            Reliability::Reliable, &data);
     }
 
     pub fn my_id(&self) -> PlayerId {
@@ -70,6 +106,10 @@ impl Client {
         self.message_deque.pop_front()
     }
 
+    pub fn num_received_bytes(&self) -> usize {
+        self.num_received_bytes
+    }
+
     pub fn num_ticks(&self) -> usize {
         self.tick_deque.len()         
     }
@@ -90,25 +130,26 @@ impl Client {
         assert!(!self.connected);
 
         self.send(&ClientMessage::WishConnect {
-            name: self.my_name.clone()
+            name: self.my_name.clone(),
+            color: self.my_color,
         });
 
         // Wait for an AcceptConnect reply to our WishConnect
-        match self.host.service(timeout_ms) {
+        match self.transport.service(timeout_ms) {
             Err(error) =>
                 Err(error),
-            Ok(enet::Event::None) =>
+            Ok(None) =>
                 Err("Server did not reply to our connection wish".to_string()),
-            Ok(enet::Event::Connect(_)) =>
-                Err("Unexpected enet connect event (already connected)".to_string()),
-            Ok(enet::Event::Disconnect(_)) =>
+            Ok(Some(TransportEvent::Connect(_))) =>
+                Err("Unexpected connect event (already connected)".to_string()),
+            Ok(Some(TransportEvent::Disconnect(_))) =>
                 Err("Got disconnected".to_string()),
-            Ok(enet::Event::Receive(_, channel_id, packet)) => {
+            Ok(Some(TransportEvent::Receive(_, channel_id, data))) => {
                 if channel_id != net::Channel::Messages as u8 {
                     return Err("Received tick data while not yet fully connected".to_string());
                 }
 
-                match decode(&packet.data()) {
+                match decode(&data) {
                     Ok(ServerMessage::AcceptConnect { your_id: my_id, game_info }) => {
                         self.connected = true;
                         self.my_id = Some(my_id);
@@ -129,19 +170,26 @@ impl Client {
         assert!(self.connected);
 
         'service: loop {
-            match self.host.service(0) {
+            match self.transport.service(0) {
                 Err(error) => return Err(error),
-                Ok(enet::Event::None) => break 'service,
-                Ok(enet::Event::Connect(_)) =>
-                    return Err("Unexpected enet connect event (already connected)".to_string()),
-                Ok(enet::Event::Disconnect(_)) => {
+                Ok(None) => break 'service,
+                Ok(Some(TransportEvent::Connect(_))) =>
+                    return Err("Unexpected connect event (already connected)".to_string()),
+                Ok(Some(TransportEvent::Disconnect(_))) => {
                     self.connected = false;
                     return Err("Got disconnected".to_string())
                 }
-                Ok(enet::Event::Receive(_, channel_id, packet)) => {
+                Ok(Some(TransportEvent::Receive(_, channel_id, data))) => {
+                    self.num_received_bytes += data.len();
+
                     if channel_id == net::Channel::Messages as u8 {
-                        let message: Result<ServerMessage, _> = decode(&packet.data());
+                        let message: Result<ServerMessage, _> = decode(&data);
                         match message {
+                            Ok(ServerMessage::Ping) => {
+                                // The server measures our round trip time
+                                self.send(&ClientMessage::Pong);
+                                continue 'service;
+                            }
                             Ok(message) => {
                                 self.message_deque.push_back(message.clone());
                                 continue 'service;
@@ -152,7 +200,7 @@ impl Client {
                     } else if channel_id == net::Channel::Ticks as u8 {
                         //println!("Received tick of size {}: {:?}", data.len(), &data);
 
-                        let mut data = packet.data().clone(); // TODO: clone
+                        let mut data = &data[..];
                         let delta_tick: Option<TickNumber> =
                             decode_from(&mut data, SizeLimit::Infinite).unwrap();
 
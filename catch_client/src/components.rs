@@ -4,9 +4,10 @@ use ecs::ComponentList;
 use shared::util::PeriodicTimer;
 use shared::components::{HasPosition, HasOrientation, HasLinearVelocity, HasShape, HasPlayerState,
                          HasFullPlayerState, HasWallPosition, HasAngularVelocity, HasWall,
-                         HasProjectile};
+                         HasProjectile, HasItemSpawnState};
 pub use shared::components::{NetEntity, Position, Orientation, LinearVelocity, Shape, PlayerState,
-                             Projectile, FullPlayerState, WallPosition, AngularVelocity, Wall};
+                             Projectile, FullPlayerState, WallPosition, AngularVelocity, Wall,
+                             ItemSpawnState};
 
 pub struct DrawPlayer {
     pub scale_x: f32,
@@ -55,6 +56,9 @@ impl Default for DrawItem {
 #[derive(Default)]
 pub struct DrawWall;
 
+#[derive(Default)]
+pub struct DrawItemSpawn;
+
 pub trait Interpolatable {
     fn interpolate(&Self, &Self, t: f32) -> Self; 
 }
@@ -90,6 +94,7 @@ components! {
         #[hot] player_state: PlayerState,
         #[hot] full_player_state: FullPlayerState,
         #[hot] wall_position: WallPosition,
+        #[hot] item_spawn_state: ItemSpawnState,
 
         // Shared, constant components
         #[hot] wall: Wall,
@@ -108,6 +113,7 @@ components! {
         #[cold] draw_item: DrawItem,
         #[cold] draw_projectile: DrawProjectile,
         #[cold] draw_wall: DrawWall,
+        #[cold] draw_item_spawn: DrawItemSpawn,
     }
 }
 
@@ -199,6 +205,15 @@ impl HasWallPosition for Components {
     }
 }
 
+impl HasItemSpawnState for Components {
+    fn item_spawn_state(&self) -> &ComponentList<Components, ItemSpawnState> {
+        &self.item_spawn_state
+    }
+    fn item_spawn_state_mut(&mut self) -> &mut ComponentList<Components, ItemSpawnState> {
+        &mut self.item_spawn_state
+    }
+}
+
 impl HasWall for Components {
     fn wall(&self) -> &ComponentList<Components, Wall> {
         &self.wall
@@ -9,10 +9,12 @@ use glium::index::PrimitiveType;
 mod draw_list;
 mod draw_draw_list;
 mod post;
+mod text;
 
 pub use self::draw_list::{DrawFlags, FLAG_NONE, FLAG_BLUR, DrawElement, DrawAttributes, DrawList};
 pub use self::draw_draw_list::DrawDrawList;
 pub use self::post::{Post, PostSettings};
+pub use self::text::{draw_text, pixel_proj_mat};
 
 #[derive(Copy, Clone)]
 pub struct Vertex {
@@ -1,7 +1,7 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use glium::{Surface, VertexBuffer, IndexBuffer, Program};
+use glium::{self, Surface, VertexBuffer, IndexBuffer, Program};
 use glium::backend::{Facade, Context};
 use glium::texture::{Texture2d, DepthFormat};
 use glium::index::PrimitiveType;
@@ -19,6 +19,7 @@ pub struct Post {
     target_depth: RefCell<Option<DepthRenderBuffer>>,
 }
 
+#[derive(Clone)]
 pub struct PostSettings {
     pub blur: bool,
 }
@@ -156,8 +157,20 @@ impl Post {
             resolution: (target_dimensions.0 as f32, target_dimensions.1 as f32)
         };
         
+        // The result is added on top of what is already in the target, e.g. the map
+        let parameters = glium::DrawParameters {
+            blend: glium::Blend {
+                color: glium::BlendingFunction::Addition {
+                    source: glium::LinearBlendingFactor::One,
+                    destination: glium::LinearBlendingFactor::One,
+                },
+                alpha: glium::BlendingFunction::AlwaysReplace,
+                constant_value: (0.0, 0.0, 0.0, 0.0),
+            },
+            .. Default::default()
+        };
         target.draw(&self.vertex_buffer, &self.index_buffer, &self.program, &uniforms,
-                    &Default::default()).unwrap();
+                    &parameters).unwrap();
 
         result
     }
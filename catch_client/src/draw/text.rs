@@ -0,0 +1,36 @@
+use na::Mat4;
+
+use glium::Surface;
+use glium_text::{self, TextSystem, FontTexture, TextDisplay};
+
+/// Draws a line of text with its top left corner at the given position in pixels
+pub fn draw_text<S: Surface>(text_system: &TextSystem, font: &FontTexture,
+                             color: (f32, f32, f32, f32), x: f32, y: f32, s: &str,
+                             proj_mat: &Mat4<f32>, size: f32, target: &mut S) {
+    let (w, h) = target.get_dimensions();
+    let sub_trans = Mat4::new(1.0, 0.0, 0.0, 0.0,
+                              0.0, 1.0, 0.0, -1.0,
+                              0.0, 0.0, 1.0, 0.0,
+                              0.0, 0.0, 0.0, 1.0);
+    let trans = Mat4::new(1.0, 0.0, 0.0, -(w as f32) / 2.0 + x,
+                          0.0, 1.0, 0.0, h as f32 / 2.0 - y,
+                          0.0, 0.0, 1.0, -0.5,
+                          0.0, 0.0, 0.0, 1.0);
+    let scale = Mat4::new(size, 0.0, 0.0, 0.0,
+                          0.0, size, 0.0, 0.0,
+                          0.0, 0.0, size, 0.0,
+                          0.0, 0.0, 0.0, 1.0);
+    let m = *proj_mat * trans * scale * sub_trans;
+    let text = TextDisplay::new(text_system, font, s);
+    glium_text::draw(&text, text_system, target, *m.as_array(), color);
+}
+
+/// Projection from pixels to normalized device coordinates, as used for drawing text
+pub fn pixel_proj_mat(width: u32, height: u32) -> Mat4<f32> {
+    let far = -10.0;
+    let near = 1.0;
+    Mat4::new(2.0 / (width as f32), 0.0, 0.0, 0.0,
+              0.0, 2.0 / (height as f32), 0.0, 0.0,
+              0.0, 0.0, -2.0 / (far - near), -(far + near) / (far - near),
+              0.0, 0.0, 0.0, 1.0)
+}
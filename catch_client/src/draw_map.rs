@@ -1,14 +1,177 @@
-use glium::Surface;
+use std::error::Error;
 
-use shared::map::Map;
+use glium::{self, Surface, VertexBuffer, Program};
+use glium::backend::Facade;
+use glium::index::{PrimitiveType, NoIndices};
+use glium::texture::{Texture2d, RawImage2d};
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter};
 
-pub struct DrawMap;
+use image;
+
+use shared::map::{Map, Tile, Tileset};
+
+use draw::{DrawContext, TexVertex};
+
+/// All tiles of one layer that use the same tileset, so that they can be drawn in one call
+struct Batch {
+    tileset: usize,
+    vertex_buffer: VertexBuffer<TexVertex>,
+}
+
+/// Draws the tile layers of a map. The tiles never change, so the vertices are created once
+/// when loading.
+pub struct DrawMap {
+    textures: Vec<Texture2d>,
+    batches: Vec<Batch>,
+    program: Program,
+}
 
 impl DrawMap {
-    pub fn load(_map: &Map) -> Result<DrawMap, String> {
-        Ok(DrawMap)
+    pub fn load<F: Facade + Clone>(facade: &F, map: &Map) -> Result<DrawMap, String> {
+        let vertex_shader_src = r#"
+            #version 140
+
+            in vec2 position;
+            in vec2 tex_coords;
+
+            uniform mat4 proj_mat;
+            uniform mat4 camera_mat;
+
+            out vec2 tex_coords_v;
+
+            void main() {
+                tex_coords_v = tex_coords;
+                gl_Position = proj_mat * camera_mat * vec4(position, 0.0, 1.0);
+            }
+        "#;
+
+        let fragment_shader_src = r#"
+            #version 140
+
+            uniform sampler2D tex;
+
+            in vec2 tex_coords_v;
+
+            out vec4 color_f;
+
+            void main() {
+                color_f = texture(tex, tex_coords_v);
+            }
+        "#;
+
+        let mut textures = Vec::new();
+        for tileset in map.tilesets().iter() {
+            textures.push(try!(DrawMap::load_texture(facade, tileset)));
+        }
+
+        let mut batches = Vec::new();
+        for layer in map.layers().iter() {
+            // Layers are drawn in order, so keep them separate even if they share tilesets
+            for (tileset_index, tileset) in map.tilesets().iter().enumerate() {
+                let vertices = DrawMap::layer_vertices(map, &layer.tiles, tileset_index,
+                                                       tileset);
+                if vertices.is_empty() {
+                    continue;
+                }
+
+                batches.push(Batch {
+                    tileset: tileset_index,
+                    vertex_buffer: VertexBuffer::new(facade, &vertices).unwrap(),
+                });
+            }
+        }
+
+        info!("created {} tile batches for {} layers", batches.len(), map.layers().len());
+
+        let program = Program::from_source(facade, vertex_shader_src, fragment_shader_src,
+                                           None).unwrap();
+
+        Ok(DrawMap {
+            textures: textures,
+            batches: batches,
+            program: program,
+        })
     }
 
-    pub fn draw<S: Surface>(&self, _map: &Map, _surface: &mut S) {
+    fn load_texture<F: Facade + Clone>(facade: &F, tileset: &Tileset)
+                                       -> Result<Texture2d, String> {
+        let image = match image::open(&tileset.image_path) {
+            Ok(image) => image.to_rgba(),
+            Err(error) => {
+                return Err(format!("failed to load tileset {:?}: {}", tileset.image_path,
+                                   error.description()));
+            }
+        };
+
+        let dimensions = image.dimensions();
+        if dimensions != (tileset.image_width as u32, tileset.image_height as u32) {
+            warn!("tileset {:?} has size {:?}, but the map says {:?}",
+                  tileset.image_path, dimensions, (tileset.image_width, tileset.image_height));
+        }
+
+        info!("loaded tileset {:?} with dimensions {:?}", tileset.image_path, dimensions);
+
+        let raw_image = RawImage2d::from_raw_rgba(image.into_raw(), dimensions);
+        Ok(Texture2d::new(facade, raw_image).unwrap())
+    }
+
+    /// Two triangles for each tile of the layer that is in the given tileset
+    fn layer_vertices(map: &Map, tiles: &Vec<Vec<Option<Tile>>>,
+                      tileset_index: usize, tileset: &Tileset) -> Vec<TexVertex> {
+        let w = map.tile_width() as f32;
+        let h = map.tile_height() as f32;
+
+        // Size of one tile in texture coordinates
+        let tw = tileset.tile_width as f32 / tileset.image_width as f32;
+        let th = tileset.tile_height as f32 / tileset.image_height as f32;
+
+        let mut vertices = Vec::new();
+        for (y, row) in tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                let tile = match *tile {
+                    Some(tile) if tile.tileset == tileset_index => tile,
+                    _ => continue,
+                };
+
+                // The first row of the image has texture coordinate 0, and so does the first
+                // row of the map in world coordinates
+                let (x1, y1) = (x as f32 * w, y as f32 * h);
+                let (x2, y2) = (x1 + w, y1 + h);
+                let (u1, v1) = (tile.x as f32 * tw, tile.y as f32 * th);
+                let (u2, v2) = (u1 + tw, v1 + th);
+
+                let a = TexVertex { position: [x1, y1], tex_coords: [u1, v1] };
+                let b = TexVertex { position: [x2, y1], tex_coords: [u2, v1] };
+                let c = TexVertex { position: [x2, y2], tex_coords: [u2, v2] };
+                let d = TexVertex { position: [x1, y2], tex_coords: [u1, v2] };
+
+                vertices.extend([a, b, c, a, c, d].iter().cloned());
+            }
+        }
+        vertices
+    }
+
+    /// Draws the layers without depth testing, so anything drawn afterwards is on top
+    pub fn draw<S: Surface>(&self, context: &DrawContext, surface: &mut S) {
+        let parameters = glium::DrawParameters {
+            blend: glium::Blend::alpha_blending(),
+            .. Default::default()
+        };
+        let indices = NoIndices(PrimitiveType::TrianglesList);
+
+        for batch in self.batches.iter() {
+            // Sample exact texels, so that neighbouring tiles in the atlas don't bleed in
+            let texture = self.textures[batch.tileset].sampled()
+                              .magnify_filter(MagnifySamplerFilter::Nearest)
+                              .minify_filter(MinifySamplerFilter::Nearest);
+            let uniforms = uniform! {
+                proj_mat: context.proj_mat,
+                camera_mat: context.camera_mat,
+                tex: texture,
+            };
+
+            surface.draw(&batch.vertex_buffer, &indices, &self.program, &uniforms,
+                         &parameters).unwrap();
+        }
     }
 }
@@ -1,4 +1,5 @@
 use std::thread;
+use std::sync::atomic::{AtomicBool, Ordering};
 use time;
 use rand;
 
@@ -28,10 +29,18 @@ impl DummyClient {
     }
 
     pub fn run(&mut self) {
-        loop {
+        self.run_until(&AtomicBool::new(false));
+    }
+
+    /// Plays until the given flag is set or the connection to the server is lost
+    pub fn run_until(&mut self, stop: &AtomicBool) {
+        while !stop.load(Ordering::Relaxed) {
             let frame_start_s = time::precise_time_s() as f32;
 
-            self.client.service().unwrap();
+            if let Err(error) = self.client.service() {
+                info!("dummy client stopped: {}", error);
+                return;
+            }
             while let Some(_) = self.client.pop_message() {
             }
             while self.client.num_ticks() > 0 {
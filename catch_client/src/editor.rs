@@ -0,0 +1,591 @@
+use std::mem;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use clock_ticks;
+use na::{Vec2, Vec4, Mat4, Norm};
+
+use glium::{self, glutin, Display, Surface};
+use glium::glutin::{VirtualKeyCode, ElementState, MouseButton, MouseScrollDelta};
+use glium_text;
+
+use shared::map::{Map, MapObject, MapLine, ObjectShape, ObjectSource, Properties};
+use shared::player::PLAYER_COLORS;
+use shared::entities::{self, ENTITY_TYPES_PATH};
+
+use client::Client;
+use player_input::InputMap;
+use gamepad::Gamepad;
+use game::{Game, GameExit};
+use local_server::LocalServer;
+use draw_map::DrawMap;
+use draw::{FLAG_NONE, DrawList, DrawDrawList, DrawContext, PostSettings, draw_text};
+
+// Objects and lines placed in the editor are put into these object groups
+const OBJECT_GROUP: &'static str = "objects";
+const WALL_GROUP: &'static str = "walls";
+
+// Size of the areas that new player spawns get
+const PLAYER_SPAWN_SIZE: f32 = 64.0;
+
+// Distance in pixels at which clicking selects an object or line for deletion
+const PICK_DISTANCE: f32 = 10.0;
+
+const PAN_SPEED: f32 = 600.0;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Tool {
+    Wall,
+    Object(&'static str),
+}
+
+impl Tool {
+    fn name(&self) -> &'static str {
+        match *self {
+            Tool::Wall => "wall",
+            Tool::Object(type_str) => type_str,
+        }
+    }
+}
+
+const TOOLS: &'static [(VirtualKeyCode, Tool)] = &[
+    (VirtualKeyCode::Key1, Tool::Wall),
+    (VirtualKeyCode::Key2, Tool::Object("player_spawn")),
+    (VirtualKeyCode::Key3, Tool::Object("item_spawn")),
+    (VirtualKeyCode::Key4, Tool::Object("bouncy_enemy")),
+];
+
+/// Settings needed to start playing the edited map
+pub struct TestPlaySettings {
+    pub input_map: InputMap,
+    pub gamepad: Option<Gamepad>,
+    pub post_settings: PostSettings,
+}
+
+/// Lets the user draw and delete walls and place objects on a map with the mouse.
+///
+/// Left click adds a point to the wall being drawn or places an object, depending on the tool
+/// selected with the number keys. Right click finishes the wall being drawn, or deletes what is
+/// under the cursor. F2 saves the map and F5 saves it and starts a local server to test play it.
+pub struct Editor {
+    quit: bool,
+
+    path: PathBuf,
+    map: Map,
+
+    tool: Tool,
+    wall_type: &'static str,
+    snap: bool,
+
+    // Points of the wall that is being drawn
+    wall_points: Vec<Vec2<f32>>,
+
+    // Shown in the status line, e.g. after saving
+    message: String,
+
+    test_play: TestPlaySettings,
+
+    display: Display,
+    draw_map: DrawMap,
+    draw_draw_list: DrawDrawList,
+
+    text_system: glium_text::TextSystem,
+    font: glium_text::FontTexture,
+
+    cam_pos: Vec2<f32>,
+    zoom: f32,
+    // Arrow keys that are held for moving the camera
+    pan_keys: Vec<VirtualKeyCode>,
+    mouse_pos: (i32, i32),
+}
+
+impl Editor {
+    pub fn new(path: PathBuf, map: Map, test_play: TestPlaySettings, display: Display)
+               -> Editor {
+        let draw_map = DrawMap::load(&display, &map).unwrap();
+        let draw_draw_list = DrawDrawList::new(&display).unwrap();
+        let text_system = glium_text::TextSystem::new(&display);
+        let font_file = File::open(&Path::new("data/ProggyClean.ttf"));
+        let font = glium_text::FontTexture::new(&display, font_file.unwrap(), 70).unwrap();
+        let cam_pos = Vec2::new(map.width_pixels() as f32 / 2.0,
+                                map.height_pixels() as f32 / 2.0);
+
+        Editor {
+            quit: false,
+
+            path: path,
+            map: map,
+
+            tool: Tool::Wall,
+            wall_type: "wood",
+            snap: true,
+
+            wall_points: Vec::new(),
+
+            message: String::new(),
+
+            test_play: test_play,
+
+            display: display,
+            draw_map: draw_map,
+            draw_draw_list: draw_draw_list,
+
+            text_system: text_system,
+            font: font,
+
+            cam_pos: cam_pos,
+            zoom: 1.0,
+            pan_keys: Vec::new(),
+            mouse_pos: (0, 0),
+        }
+    }
+
+    pub fn run(&mut self) {
+        let mut frame_start_ns = clock_ticks::precise_time_ns();
+        while !self.quit {
+            let new_frame_start_ns = clock_ticks::precise_time_ns();
+            let delta_s = (new_frame_start_ns - frame_start_ns) as f32 / 1000000000.0;
+            frame_start_ns = new_frame_start_ns;
+
+            self.read_input();
+            let pan = self.pan_keys.iter()
+                                   .filter_map(|&key| pan_direction(key))
+                                   .fold(Vec2::new(0.0, 0.0), |a, b| a + b);
+            self.cam_pos = self.cam_pos + pan * (PAN_SPEED * delta_s / self.zoom);
+            self.draw();
+
+            thread::sleep_ms(5);
+        }
+    }
+
+    fn read_input(&mut self) {
+        for event in self.display.poll_events() {
+            match event {
+                glutin::Event::Closed => {
+                    self.quit = true;
+                }
+                glutin::Event::KeyboardInput(state, _, Some(key)) => {
+                    self.on_key(state == ElementState::Pressed, key);
+                }
+                glutin::Event::MouseMoved(position) => {
+                    self.mouse_pos = position;
+                }
+                glutin::Event::MouseInput(ElementState::Pressed, MouseButton::Left) => {
+                    self.on_left_click();
+                }
+                glutin::Event::MouseInput(ElementState::Pressed, MouseButton::Right) => {
+                    if !self.wall_points.is_empty() {
+                        self.finish_wall();
+                    } else {
+                        self.delete_at_cursor();
+                    }
+                }
+                glutin::Event::MouseWheel(delta) => {
+                    let lines = match delta {
+                        MouseScrollDelta::LineDelta(_, y) => y,
+                        MouseScrollDelta::PixelDelta(_, y) => y / 20.0,
+                    };
+                    self.zoom = (self.zoom * 1.1f32.powf(lines)).max(0.1).min(8.0);
+                }
+                glutin::Event::Focused(false) => {
+                    self.pan_keys.clear();
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn on_key(&mut self, pressed: bool, key: VirtualKeyCode) {
+        // Panning keeps going while the keys are held. Held keys repeat, so don't count them
+        // twice
+        if pan_direction(key).is_some() {
+            if !pressed {
+                self.pan_keys.retain(|&pan_key| pan_key != key);
+            } else if !self.pan_keys.contains(&key) {
+                self.pan_keys.push(key);
+            }
+            return;
+        }
+
+        if !pressed {
+            return;
+        }
+
+        if let Some(&(_, tool)) = TOOLS.iter().find(|&&(tool_key, _)| tool_key == key) {
+            self.wall_points.clear();
+            self.tool = tool;
+            return;
+        }
+
+        match key {
+            VirtualKeyCode::Escape => {
+                if !self.wall_points.is_empty() {
+                    self.wall_points.clear();
+                } else {
+                    info!("got escape input, quitting editor");
+                    self.quit = true;
+                }
+            }
+            VirtualKeyCode::Return => {
+                self.finish_wall();
+            }
+            VirtualKeyCode::Back => {
+                self.wall_points.pop();
+            }
+            VirtualKeyCode::W => {
+                self.wall_type = if self.wall_type == "wood" { "iron" } else { "wood" };
+            }
+            VirtualKeyCode::G => {
+                self.snap = !self.snap;
+            }
+            VirtualKeyCode::F2 => {
+                self.save();
+            }
+            VirtualKeyCode::F5 => {
+                self.test_play();
+            }
+            _ => (),
+        }
+    }
+
+    fn on_left_click(&mut self) {
+        let p = self.cursor_world_pos();
+
+        match self.tool {
+            Tool::Wall => {
+                if self.wall_points.last() != Some(&p) {
+                    self.wall_points.push(p);
+                }
+            }
+            Tool::Object(type_str) => {
+                let (shape, size) = if type_str == "player_spawn" {
+                    (ObjectShape::Ellipse, PLAYER_SPAWN_SIZE)
+                } else {
+                    (ObjectShape::Rect, 0.0)
+                };
+                let source = self.next_source(OBJECT_GROUP);
+
+                self.map.objects.push(MapObject {
+                    x: p.x - size / 2.0,
+                    y: p.y - size / 2.0,
+                    width: size,
+                    height: size,
+                    shape: shape,
+                    type_str: type_str.to_string(),
+                    properties: Properties::new(),
+                    source: source,
+                });
+            }
+        }
+    }
+
+    fn finish_wall(&mut self) {
+        if self.wall_points.len() >= 2 {
+            let source = self.next_source(WALL_GROUP);
+            let mut properties = Properties::new();
+            properties.insert("wall_type".to_string(), self.wall_type.to_string());
+
+            for i in 0..self.wall_points.len() - 1 {
+                self.map.lines.push(MapLine {
+                    a: self.wall_points[i],
+                    b: self.wall_points[i + 1],
+                    properties: properties.clone(),
+                    source: source.clone(),
+                });
+            }
+        }
+
+        self.wall_points.clear();
+    }
+
+    /// Deletes the object closest to the cursor or, if there is none, the whole polyline of the
+    /// closest line
+    fn delete_at_cursor(&mut self) {
+        let p = self.cursor_world_pos_unsnapped();
+        let max_distance = PICK_DISTANCE / self.zoom;
+
+        let object = self.map.objects.iter().enumerate()
+            .map(|(i, object)| (i, object_distance(object, p)))
+            .filter(|&(_, distance)| distance <= max_distance)
+            .min_by_key(|&(_, distance)| (distance * 1000.0) as i64)
+            .map(|(i, _)| i);
+        if let Some(i) = object {
+            self.map.objects.remove(i);
+            return;
+        }
+
+        let line = self.map.lines.iter()
+            .map(|line| (line, segment_distance(line.a, line.b, p)))
+            .filter(|&(_, distance)| distance <= max_distance)
+            .min_by_key(|&(_, distance)| (distance * 1000.0) as i64)
+            .map(|(line, _)| line.source.clone());
+        if let Some(source) = line {
+            self.map.lines.retain(|line| line.source != source);
+        }
+    }
+
+    /// Returns a source that is not used by any other object or line in the given group
+    fn next_source(&self, group: &str) -> ObjectSource {
+        let objects = self.map.objects.iter().map(|object| &object.source);
+        let lines = self.map.lines.iter().map(|line| &line.source);
+        let index = objects.chain(lines)
+                           .filter(|source| source.group == group)
+                           .map(|source| source.index + 1)
+                           .max()
+                           .unwrap_or(0);
+
+        ObjectSource {
+            group: group.to_string(),
+            index: index,
+            name: String::new(),
+            id: None,
+        }
+    }
+
+    fn save(&mut self) -> bool {
+        self.finish_wall();
+
+        match self.map.save(&self.path) {
+            Ok(()) => {
+                self.message = format!("saved {}", self.path.display());
+                true
+            }
+            Err(error) => {
+                warn!("{}", error);
+                self.message = format!("{}", error);
+                false
+            }
+        }
+    }
+
+    fn test_play(&mut self) {
+        let entity_types = match entities::load_entity_types(ENTITY_TYPES_PATH) {
+            Ok(entity_types) => entity_types,
+            Err(error) => {
+                self.message = format!("can't test play: {}", error);
+                return;
+            }
+        };
+        let problems = self.map.check(&entity_types);
+        if let Some(problem) = problems.first() {
+            self.message = format!("can't test play: {}", problem);
+            return;
+        }
+        if !self.save() {
+            return;
+        }
+
+        match self.play() {
+            Ok(()) => self.message = "finished test play".to_string(),
+            Err(error) => {
+                warn!("{}", error);
+                self.message = error;
+            }
+        }
+    }
+
+    fn play(&mut self) -> Result<(), String> {
+        // Stopped when this function returns
+        let local_server = try!(LocalServer::start(&self.path.to_string_lossy()));
+
+        let mut client = try!(Client::connect_local(local_server.address(),
+                                                    "editor".to_string(),
+                                                    PLAYER_COLORS[0].1));
+        try!(client.finish_connecting(5000));
+
+        // The game gets the input devices for as long as it runs
+        let input_map = mem::replace(&mut self.test_play.input_map, InputMap::new());
+        let gamepad = self.test_play.gamepad.take();
+
+        let mut game = Game::new(client, input_map, gamepad, self.test_play.post_settings.clone(),
+                                 self.display.clone());
+        let exit = game.run();
+
+        let (input_map, gamepad) = game.into_input();
+        self.test_play.input_map = input_map;
+        self.test_play.gamepad = gamepad;
+
+        // Keys that were held when the game started are not held anymore
+        self.pan_keys.clear();
+
+        match exit {
+            GameExit::Left => Ok(()),
+            GameExit::Closed => {
+                self.quit = true;
+                Ok(())
+            }
+            GameExit::Disconnected(error) => Err(format!("test play ended: {}", error)),
+        }
+    }
+
+    fn cursor_world_pos_unsnapped(&self) -> Vec2<f32> {
+        let (w, h) = self.display.get_framebuffer_dimensions();
+        let (x, y) = self.mouse_pos;
+
+        // Screen y goes down, world y goes up
+        Vec2::new(self.cam_pos.x + (x as f32 - w as f32 / 2.0) / self.zoom,
+                  self.cam_pos.y + (h as f32 / 2.0 - y as f32) / self.zoom)
+    }
+
+    /// Returns the world position under the cursor, snapped to half tiles if enabled
+    fn cursor_world_pos(&self) -> Vec2<f32> {
+        let p = self.cursor_world_pos_unsnapped();
+        if !self.snap {
+            return p;
+        }
+
+        let grid_x = self.map.tile_width() as f32 / 2.0;
+        let grid_y = self.map.tile_height() as f32 / 2.0;
+        Vec2::new((p.x / grid_x).round() * grid_x, (p.y / grid_y).round() * grid_y)
+    }
+
+    fn draw(&mut self) {
+        let mut target = self.display.draw();
+        target.clear_color_and_depth((0.1, 0.1, 0.1, 1.0), -1.0);
+
+        let (draw_width, draw_height) = target.get_dimensions();
+        let far = -10.0;
+        let near = 1.0;
+        let zoom = self.zoom;
+        let draw_context = DrawContext {
+            proj_mat: Mat4::new(2.0 / (draw_width as f32), 0.0, 0.0, 0.0,
+                                0.0, 2.0 / (draw_height as f32), 0.0, 0.0,
+                                0.0, 0.0, -2.0 / (far - near), -(far + near) / (far - near),
+                                0.0, 0.0, 0.0, 1.0),
+            camera_mat: Mat4::new(zoom, 0.0, 0.0, -self.cam_pos.x * zoom,
+                                  0.0, zoom, 0.0, -self.cam_pos.y * zoom,
+                                  0.0, 0.0, zoom, 0.0,
+                                  0.0, 0.0, 0.0, 1.0),
+            parameters: glium::DrawParameters {
+                depth: glium::Depth {
+                    test: glium::draw_parameters::DepthTest::IfMore,
+                    write: true,
+                    .. Default::default()
+                },
+                .. Default::default()
+            },
+        };
+
+        self.draw_map.draw(&draw_context, &mut target);
+
+        let draw_list = self.draw_list();
+        self.draw_draw_list.draw(FLAG_NONE, draw_list, &draw_context, &mut target);
+
+        let proj_mat = draw_context.proj_mat;
+        self.draw_status_text(&proj_mat, &mut target);
+
+        target.finish().unwrap();
+    }
+
+    fn draw_list(&self) -> DrawList {
+        let mut draw_list = DrawList::new();
+        let thickness = 2.0 / self.zoom;
+
+        // Map border
+        let (w, h) = (self.map.width_pixels() as f32, self.map.height_pixels() as f32);
+        let corners = [Vec2::new(0.0, 0.0), Vec2::new(w, 0.0), Vec2::new(w, h),
+                       Vec2::new(0.0, h)];
+        for i in 0..corners.len() {
+            draw_list.push_line(FLAG_NONE, Vec4::new(0.5, 0.5, 0.5, 1.0), thickness,
+                                corners[i], corners[(i + 1) % corners.len()], 0.0);
+        }
+
+        for line in self.map.lines.iter() {
+            let color = match line.wall_type().as_ref().map(|s| &s[..]) {
+                Some("wall_iron") => Vec4::new(0.6, 0.6, 0.7, 1.0),
+                Some(_) => Vec4::new(0.6, 0.4, 0.2, 1.0),
+                None => Vec4::new(1.0, 0.0, 0.0, 1.0),
+            };
+            draw_list.push_line(FLAG_NONE, color, 2.0 * thickness, line.a, line.b, 0.1);
+        }
+
+        for object in self.map.objects.iter() {
+            let color = match &object.type_str[..] {
+                "player_spawn" => Vec4::new(0.0, 0.8, 0.0, 0.5),
+                "item_spawn" => Vec4::new(1.0, 1.0, 0.0, 1.0),
+                "bouncy_enemy" => Vec4::new(1.0, 0.3, 0.3, 1.0),
+                _ => Vec4::new(1.0, 0.0, 1.0, 1.0),
+            };
+            let center = Vec2::new(object.x + object.width / 2.0,
+                                   object.y + object.height / 2.0);
+
+            // Objects without an area are positions, so give them a fixed size on screen
+            let size = Vec2::new(object.width.max(8.0 / self.zoom),
+                                 object.height.max(8.0 / self.zoom));
+            match object.shape {
+                ObjectShape::Rect =>
+                    draw_list.push_rect(FLAG_NONE, color, size.x, size.y, center, 0.2, 0.0),
+                ObjectShape::Ellipse =>
+                    draw_list.push_ellipse(FLAG_NONE, color, size.x / 2.0, size.y / 2.0, center,
+                                           0.2, 0.0),
+            }
+        }
+
+        // The wall that is being drawn, up to the cursor
+        if !self.wall_points.is_empty() {
+            let color = Vec4::new(1.0, 1.0, 1.0, 1.0);
+            let mut points = self.wall_points.clone();
+            points.push(self.cursor_world_pos());
+            for i in 0..points.len() - 1 {
+                draw_list.push_line(FLAG_NONE, color, 2.0 * thickness, points[i], points[i + 1],
+                                    0.3);
+            }
+        }
+
+        draw_list
+    }
+
+    fn draw_status_text<S: Surface>(&mut self, proj_mat: &Mat4<f32>, target: &mut S) {
+        let color = (1.0, 1.0, 1.0, 1.0);
+        let size = 10.0;
+        let r = 20.0;
+        let p = self.cursor_world_pos();
+
+        let s = format!("{} - tool: {}, wall type: {}, snap: {}, cursor: ({}, {})",
+                        self.path.display(), self.tool.name(), self.wall_type,
+                        if self.snap { "on" } else { "off" }, p.x, p.y);
+        self.draw_text(color, 10.0, 10.0, &s, proj_mat, size, target);
+
+        self.draw_text(color, 10.0, 10.0 + r,
+                       "1-4: tool, w: wall type, g: snap, F2: save, F5: test play",
+                       proj_mat, size, target);
+
+        let message = self.message.clone();
+        self.draw_text((1.0, 1.0, 0.0, 1.0), 10.0, 10.0 + 2.0 * r, &message, proj_mat, size,
+                       target);
+    }
+
+    fn draw_text<S: Surface>(&mut self, color: (f32, f32, f32, f32), x: f32, y: f32,
+                             s: &str, proj_mat: &Mat4<f32>, size: f32, target: &mut S) {
+        draw_text(&self.text_system, &self.font, color, x, y, s, proj_mat, size, target);
+    }
+}
+
+fn pan_direction(key: VirtualKeyCode) -> Option<Vec2<f32>> {
+    match key {
+        VirtualKeyCode::Left => Some(Vec2::new(-1.0, 0.0)),
+        VirtualKeyCode::Right => Some(Vec2::new(1.0, 0.0)),
+        VirtualKeyCode::Up => Some(Vec2::new(0.0, 1.0)),
+        VirtualKeyCode::Down => Some(Vec2::new(0.0, -1.0)),
+        _ => None,
+    }
+}
+
+/// Distance from a point to an object's area, or to its position if it has no area
+fn object_distance(object: &MapObject, p: Vec2<f32>) -> f32 {
+    let dx = (object.x - p.x).max(p.x - (object.x + object.width)).max(0.0);
+    let dy = (object.y - p.y).max(p.y - (object.y + object.height)).max(0.0);
+    (dx * dx + dy * dy).sqrt()
+}
+
+fn segment_distance(a: Vec2<f32>, b: Vec2<f32>, p: Vec2<f32>) -> f32 {
+    let d = b - a;
+    let length_sq = d.sqnorm();
+    if length_sq == 0.0 {
+        return (p - a).norm();
+    }
+
+    let t = (((p - a).x * d.x + (p - a).y * d.y) / length_sq).max(0.0).min(1.0);
+    (p - (a + d * t)).norm()
+}
@@ -1,22 +1,34 @@
 use ecs::{BuildData};
 
-use components::{Components, DrawPlayer, DrawBouncyEnemy, DrawItem, DrawProjectile, DrawWall};
+use shared::EntityType;
+use shared::entities::Appearance;
+
+use components::{Components, DrawPlayer, DrawBouncyEnemy, DrawItem, DrawProjectile, DrawWall,
+                 DrawItemSpawn};
 
 /// Adds client-side components that are not synchronized over the net to an entity
-pub fn build_client(type_name: &str,
+pub fn build_client(entity_type: &EntityType,
                     entity: BuildData<Components>,
                     data: &mut Components) {
-    if type_name == "player" {
-        data.draw_player.add(&entity, DrawPlayer::default());
-    } else if type_name == "bouncy_enemy" {
-        data.draw_bouncy_enemy.add(&entity, DrawBouncyEnemy::default());
-    } else if type_name == "player_ball" {
-        data.draw_bouncy_enemy.add(&entity, DrawBouncyEnemy::default());
-    } else if type_name == "item" {
-        data.draw_item.add(&entity, DrawItem::default());
-    } else if type_name == "bullet" || type_name == "frag" || type_name == "shrapnel" {
-        data.draw_projectile.add(&entity, DrawProjectile::default());
-    } else if type_name == "wall_wood" || type_name == "wall_iron" {
-        data.draw_wall.add(&entity, DrawWall::default());
+    match entity_type.appearance {
+        Some(Appearance::Player) => {
+            data.draw_player.add(&entity, DrawPlayer::default());
+        }
+        Some(Appearance::BouncyEnemy) => {
+            data.draw_bouncy_enemy.add(&entity, DrawBouncyEnemy::default());
+        }
+        Some(Appearance::Item) => {
+            data.draw_item.add(&entity, DrawItem::default());
+        }
+        Some(Appearance::Projectile) => {
+            data.draw_projectile.add(&entity, DrawProjectile::default());
+        }
+        Some(Appearance::Wall) => {
+            data.draw_wall.add(&entity, DrawWall::default());
+        }
+        Some(Appearance::ItemSpawn) => {
+            data.draw_item_spawn.add(&entity, DrawItemSpawn::default());
+        }
+        None => (),
     }
 }
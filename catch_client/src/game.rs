@@ -1,6 +1,5 @@
 use std::f32;
 use std::thread;
-use std::cell::{RefCell, RefMut};
 use std::fs::File;
 use std::path::Path;
 use std::collections::VecDeque;
@@ -15,50 +14,57 @@ use glium::{self, glutin, Display, Surface};
 use glium_text;
 
 use shared::{NEUTRAL_PLAYER_ID, NUM_ITEM_SLOTS, Item, GameEvent, PlayerId, DeathReason};
-use shared::net::{ClientMessage, TimedPlayerInput};
+use shared::net::{self, ClientMessage, TimedPlayerInput};
 use shared::tick::Tick;
 
 use client::Client;
 use state::GameState;
-use player_input::{PlayerInput, InputMap};
-use draw_map::DrawMap;
-use particles::Particles;
+use player_input::{PlayerInput, PlayerInputKey, InputMap, Trigger};
+use gamepad::Gamepad;
+use bindings_screen::BindingsScreen;
 use sounds::Sounds;
-use draw::{FLAG_BLUR, FLAG_NONE, DrawOp, DrawList, DrawDrawList, DrawContext, Post, PostSettings};
+use jitter::JitterBuffer;
+use prof_overlay::{ProfOverlay, FrameStats, OVERLAY_WIDTH};
+use renderer::Renderer;
+use snapshot::Snapshot;
+use capture::{self, Recording, RECORDING_FPS};
+use draw::{FLAG_NONE, DrawList, DrawContext, PostSettings, draw_text, pixel_proj_mat};
 
 pub const MAX_DEATH_MESSAGES: usize = 4;
 
-struct DrawListsOp<'a, 'b: 'a> {
-    draw_draw_list: RefMut<'a, DrawDrawList>,
-    draw_list: &'a DrawList,
-    particles: &'a Particles,
-    draw_context: &'a DrawContext<'b>,
-}
+// Chat messages fade out after a while, but are shown again while typing
+pub const MAX_CHAT_MESSAGES: usize = 8;
+pub const CHAT_MESSAGE_DURATION_S: f32 = 10.0;
 
-impl<'a, 'b> DrawOp for DrawListsOp<'a, 'b> {
-    type Result = ();
+// How long the server name and message of the day are shown after joining
+pub const MOTD_DURATION_S: f32 = 8.0;
 
-    fn draw<S: Surface>(&mut self, target: &mut S) {
-        let _g = hprof::enter("draw list");
-        target.clear_color_and_depth((0.0, 0.0, 0.0, 1.0), -1.0);
-        self.draw_draw_list.draw(FLAG_BLUR, self.draw_list.clone(), &self.draw_context, target);
-        {
-            let _g = hprof::enter("draw particles");
-            self.particles.draw(&self.draw_context, target);
-        }
-    }
+/// Why a game stopped running
+#[derive(Debug, Clone)]
+pub enum GameExit {
+    // The player chose to leave the match
+    Left,
+
+    // The window was closed, so the whole client should quit
+    Closed,
+
+    Disconnected(String),
 }
 
 pub struct Game {
-    quit: bool,
+    exit: Option<GameExit>,
 
     client: Client,
     state: GameState,
 
     player_input_map: InputMap,
     player_input: PlayerInput,
+    gamepad: Option<Gamepad>,
 
-    interpolation_ticks: usize,
+    // Shown instead of passing input to the player while it is open
+    bindings_screen: Option<BindingsScreen>,
+
+    jitter_buffer: JitterBuffer,
     current_tick: Option<Tick>,
     tick_progress: f32,
     time_factor: f32,
@@ -66,13 +72,17 @@ pub struct Game {
     display: Display,
 
     death_messages: VecDeque<(String, (f32, f32, f32))>,
+
+    // Received chat messages with their color and the time they are still shown for
+    chat_messages: VecDeque<(String, (f32, f32, f32), f32)>,
+
+    // While typing a chat message, this is the text and whether it goes only to our team.
+    // Keyboard input is not passed to the input map then.
+    chat_input: Option<(String, bool)>,
     draw_player_stats: bool,
+    motd_time_s: f32,
 
-    draw_list: DrawList,
-    draw_draw_list: RefCell<DrawDrawList>,
-    draw_map: DrawMap,
-    particles: Particles,
-    draw_post: Post,
+    renderer: Renderer,
 
     sounds: Sounds,
 
@@ -83,26 +93,33 @@ pub struct Game {
     fps: f32,
 
     print_prof: bool,
+
+    // Graphs are recorded all the time, so that they are complete when the overlay is shown
+    prof_overlay: ProfOverlay,
+    show_prof_overlay: bool,
+
+    // Set by the key binding, the screenshot is taken after the next frame is drawn
+    take_screenshot: bool,
+    recording: Option<Recording>,
 }
 
 impl Game {
     // The given client is expected to be connected already
     pub fn new(connected_client: Client,
                player_input_map: InputMap,
+               gamepad: Option<Gamepad>,
                post_settings: PostSettings,
                display: Display) -> Game {
         let state = GameState::new(connected_client.my_id(), connected_client.game_info());
-        let draw_draw_list = RefCell::new(DrawDrawList::new(&display).unwrap());
-        let draw_map = DrawMap::load(&state.map).unwrap();
-        let particles = Particles::new(&display);
-        let draw_post = Post::new(post_settings, &display);
+        let renderer = Renderer::new(&display, &state.map, post_settings).unwrap();
         let sounds = Sounds::load().unwrap();
         let text_system = glium_text::TextSystem::new(&display);
         let font_file = File::open(&Path::new("data/ProggyClean.ttf"));
         let font = glium_text::FontTexture::new(&display, font_file.unwrap(), 70).unwrap();
+        let jitter_buffer = JitterBuffer::new(connected_client.game_info().ticks_per_second);
 
         Game {
-            quit: false,
+            exit: None,
 
             client: connected_client,
 
@@ -110,8 +127,10 @@ impl Game {
 
             player_input_map: player_input_map,
             player_input: PlayerInput::new(),
+            gamepad: gamepad,
+            bindings_screen: None,
 
-            interpolation_ticks: 2,
+            jitter_buffer: jitter_buffer,
             current_tick: None,
             tick_progress: 0.0,
             time_factor: 0.0,
@@ -119,13 +138,12 @@ impl Game {
             display: display,
 
             death_messages: VecDeque::new(),
+            chat_messages: VecDeque::new(),
+            chat_input: None,
             draw_player_stats: false,
+            motd_time_s: MOTD_DURATION_S,
 
-            draw_list: DrawList::new(),
-            draw_draw_list: draw_draw_list,
-            draw_map: draw_map,
-            particles: particles,
-            draw_post: draw_post,
+            renderer: renderer,
 
             sounds: sounds,
 
@@ -136,15 +154,21 @@ impl Game {
             fps: 0.0,
 
             print_prof: false,
+
+            prof_overlay: ProfOverlay::new(),
+            show_prof_overlay: false,
+
+            take_screenshot: false,
+            recording: None,
         }
     }
 
-    pub fn run(&mut self) {
+    pub fn run(&mut self) -> GameExit {
         self.wait_first_ticks();
 
         let mut simulation_time_s = 0.0;
         let mut frame_start_s = clock_ticks::precise_time_ns(); //clock_ticks::precise_time_s() as f32;
-        while !self.quit {
+        while self.exit.is_none() {
             hprof::start_frame();
 
             self.client_service();
@@ -153,6 +177,7 @@ impl Game {
             self.manage_ticks(simulation_time_s);
             self.interpolate();
             self.draw(simulation_time_s);
+            self.capture(simulation_time_s);
 
             self.fps = 1.0 / simulation_time_s;
             self.display.get_window().map(|w| w.set_title(&format!("{:.2}", self.fps)));
@@ -175,14 +200,33 @@ impl Game {
             simulation_time_s = (new_frame_start_s - frame_start_s) as f32 / 1000000000.0;
             //println!("{} = {}", new_frame_start_s - frame_start_s, simulation_time_s);
             frame_start_s = new_frame_start_s;
+
+            self.prof_overlay.record_frame(&FrameStats {
+                frame_s: simulation_time_s,
+                queued_ticks: self.client.num_ticks(),
+                jitter_s: self.jitter_buffer.jitter_s(),
+                num_received_bytes: self.client.num_received_bytes(),
+            });
         }
+
+        self.exit.take().unwrap()
+    }
+
+    /// Gives back the input devices, e.g. to use them in another game
+    pub fn into_input(mut self) -> (InputMap, Option<Gamepad>) {
+        self.player_input_map.release_all();
+        (self.player_input_map, self.gamepad)
     }
 
     fn wait_first_ticks(&mut self) {
         info!("waiting to receive first ticks from server... ");
 
-        while self.client.num_ticks() < self.interpolation_ticks {
+        while self.client.num_ticks() < self.jitter_buffer.target_ticks().max(2) {
             self.client_service();
+
+            if self.exit.is_some() {
+                return;
+            }
         }
 
         info!("done! have {} ticks", self.client.num_ticks());
@@ -200,7 +244,8 @@ impl Game {
 
         assert!(self.client.num_ticks() >= 2);
 
-        let tick = self.client.pop_next_tick().1;
+        let (arrival, tick) = self.client.pop_next_tick();
+        self.jitter_buffer.on_tick_arrival(arrival);
 
         {
             let _g = hprof::enter("events");
@@ -223,7 +268,7 @@ impl Game {
 
         if let Err(error) = self.client.service() {
             warn!("error while servicing: {}", error);
-            // TODO: handle disconnect etc.
+            self.exit = Some(GameExit::Disconnected(error));
         }
 
         while let Some(_message) = self.client.pop_message() {
@@ -234,36 +279,149 @@ impl Game {
     fn read_input(&mut self) {
         let _g = hprof::enter("read input");
 
+        let mut transitions = Vec::new();
+
         for event in self.display.poll_events() {
             match event {
+                glutin::Event::Closed => {
+                    info!("window closed, quitting game");
+                    self.exit = Some(GameExit::Closed);
+                    return;
+                }
                 glutin::Event::KeyboardInput(state, _, Some(key)) => {
-                    if state == glutin::ElementState::Pressed {
-                        if key == glutin::VirtualKeyCode::Escape {
-                            info!("got escape input, quitting game");
-                            self.quit = true;
+                    let pressed = state == glutin::ElementState::Pressed;
+
+                    if self.chat_input.is_some() {
+                        if pressed {
+                            self.on_chat_key(key);
+                        }
+                        continue;
+                    }
+
+                    let capturing = self.bindings_screen.as_ref()
+                                        .map_or(false, |screen| screen.is_capturing());
+
+                    if pressed {
+                        if key == glutin::VirtualKeyCode::Return && self.bindings_screen.is_none() {
+                            self.chat_input = Some((String::new(), false));
+                            self.player_input_map.release_all();
+                            continue;
+                        } else if key == glutin::VirtualKeyCode::Escape {
+                            if let Some(bindings_screen) = self.bindings_screen.as_mut() {
+                                if bindings_screen.is_capturing() {
+                                    bindings_screen.cancel_capture();
+                                    continue;
+                                }
+                            }
+                            if self.bindings_screen.is_some() {
+                                self.bindings_screen = None;
+                                continue;
+                            }
+
+                            info!("got escape input, leaving game");
+                            self.exit = Some(GameExit::Left);
                             return;
+                        } else if capturing {
+                            // While rebinding, keys go to the bindings screen, not to shortcuts
+                        } else if key == glutin::VirtualKeyCode::F1 {
+                            self.bindings_screen = match self.bindings_screen {
+                                Some(_) => None,
+                                None => Some(BindingsScreen::new()),
+                            };
+                            continue;
                         } else if key == glutin::VirtualKeyCode::L {
                             thread::sleep_ms(200);
                             continue;
                         } else if key == glutin::VirtualKeyCode::P {
                             self.print_prof = true;
                             continue;
+                        } else if key == glutin::VirtualKeyCode::F3 {
+                            self.show_prof_overlay = !self.show_prof_overlay;
+                            continue;
+                        } else if key == glutin::VirtualKeyCode::F9 {
+                            self.take_screenshot = true;
+                            continue;
+                        } else if key == glutin::VirtualKeyCode::F10 {
+                            self.toggle_recording();
+                            continue;
+                        } else if key == glutin::VirtualKeyCode::F12 {
+                            self.save_snapshot();
+                            continue;
                         } else if key == glutin::VirtualKeyCode::Tab {
                             self.draw_player_stats = true;
                             continue;
                         }
-                    } else if state == glutin::ElementState::Released {
-                        if key == glutin::VirtualKeyCode::Tab {
-                            self.draw_player_stats = false;
-                            continue;
-                        }
+                    } else if key == glutin::VirtualKeyCode::Tab {
+                        self.draw_player_stats = false;
+                        continue;
                     }
 
-                    self.player_input_map.update_player_input(state, key, &mut self.player_input);
+                    transitions.push((pressed, Trigger::Key(key)));
+                }
+                glutin::Event::ReceivedCharacter(c) => {
+                    if let Some((ref mut text, _)) = self.chat_input {
+                        if !c.is_control() && text.chars().count() < net::MAX_CHAT_LEN {
+                            text.push(c);
+                        }
+                    }
+                }
+                glutin::Event::Focused(false) => {
+                    // We won't see the release of keys that are held while unfocused
+                    self.player_input_map.release_all();
                 }
                 _ => (),
             };
         }
+
+        if let Some(gamepad) = self.gamepad.as_ref() {
+            for event in gamepad.poll_events() {
+                // Transitions depend on what is held, so update for each event in turn
+                for (pressed, trigger) in self.player_input_map.gamepad_transitions(event) {
+                    self.player_input_map.on_trigger(pressed, trigger);
+                    transitions.push((pressed, trigger));
+                }
+            }
+        }
+
+        for &(pressed, trigger) in transitions.iter() {
+            if let Some(bindings_screen) = self.bindings_screen.as_mut() {
+                bindings_screen.on_trigger(pressed, trigger, &mut self.player_input_map);
+            }
+            if let Trigger::Key(_) = trigger {
+                self.player_input_map.on_trigger(pressed, trigger);
+            }
+        }
+
+        self.player_input = if self.bindings_screen.is_some() || self.chat_input.is_some() {
+            PlayerInput::new()
+        } else {
+            self.player_input_map.player_input()
+        };
+    }
+
+    fn on_chat_key(&mut self, key: glutin::VirtualKeyCode) {
+        match key {
+            glutin::VirtualKeyCode::Escape => {
+                self.chat_input = None;
+            }
+            glutin::VirtualKeyCode::Return => {
+                let (text, team_only) = self.chat_input.take().unwrap();
+                if !text.trim().is_empty() {
+                    self.client.send(&ClientMessage::Chat {
+                        text: text,
+                        team_only: team_only,
+                    });
+                }
+            }
+            glutin::VirtualKeyCode::Back => {
+                self.chat_input.as_mut().unwrap().0.pop();
+            }
+            glutin::VirtualKeyCode::Tab => {
+                let team_only = &mut self.chat_input.as_mut().unwrap().1;
+                *team_only = !*team_only;
+            }
+            _ => ()
+        }
     }
 
     fn send_input(&mut self, simulation_time_s: f32) {
@@ -282,26 +440,13 @@ impl Game {
 
         assert!(self.current_tick.is_some());
 
-        if self.tick_progress < 1.0 {
-            self.time_factor = {
-                if self.client.num_ticks() > 2 {
-                    1.05 + (1.0 - (self.client.num_ticks() as f32 / -20.0).exp())
-                } else if self.client.num_ticks() < 2 && self.tick_progress > 0.5 {
-                    0.75 // Is this a stupid idea?
-                } else {
-                    1.0
-                }
-            };
-
-            if self.time_factor != 1.0 {
-                debug!("time factor {}, queued {} ticks, progress {}",
-                       self.time_factor, self.client.num_ticks(), self.tick_progress);
-            }
+        // Speed up playback if we have more ticks queued than the measured jitter requires,
+        // slow it down if we are about to run dry
+        self.time_factor = self.jitter_buffer.update_time_factor(self.client.num_ticks());
 
-            self.tick_progress += self.time_factor * 
-                                  simulation_time_s *
-                                  self.client.game_info().ticks_per_second as f32;
-        }
+        self.tick_progress += self.time_factor * 
+                              simulation_time_s *
+                              self.client.game_info().ticks_per_second as f32;
 
         while self.tick_progress >= 1.0 {
             // Load the next tick state if we can interpolate into the following tick
@@ -314,6 +459,87 @@ impl Game {
                 break;
             }
         }
+
+        // If the next tick is late, we extrapolate, but only for a bounded time
+        let max_tick_progress = self.jitter_buffer.max_tick_progress();
+        if self.tick_progress > max_tick_progress {
+            self.tick_progress = max_tick_progress;
+        }
+    }
+
+    /// Loads the map again after the server reloaded it. The walls are entities and arrive by
+    /// themselves, this is about what we draw.
+    fn reload_map(&mut self) {
+        let map = match self.state.game_info.load_map() {
+            Ok(map) => map,
+            Err(error) => {
+                warn!("could not reload map: {}", error);
+                return;
+            }
+        };
+
+        match self.renderer.load_map(&self.display, &map) {
+            Ok(()) => {
+                info!("reloaded map {}", self.state.game_info.map_name);
+                self.state.map = map;
+            }
+            Err(error) => warn!("could not reload map: {}", error),
+        }
+    }
+
+    /// Saves the current tick so that it can be rendered again with `--render-snapshot`
+    fn save_snapshot(&mut self) {
+        let tick = match self.current_tick {
+            Some(ref tick) => tick.clone(),
+            None => return,
+        };
+
+        let path = format!("snapshot-{}.tick", tick.tick_number);
+        let snapshot = Snapshot::new(&mut self.state, self.client.my_id(), &tick);
+        match snapshot.save(&path) {
+            Ok(()) => info!("saved snapshot {}", path),
+            Err(error) => warn!("{}", error),
+        }
+    }
+
+    fn toggle_recording(&mut self) {
+        match self.recording.take() {
+            Some(recording) => {
+                info!("stopped recording, {} frames in {:?}", recording.num_frames(),
+                      recording.dir());
+            }
+            None => {
+                match Recording::start(RECORDING_FPS) {
+                    Ok(recording) => {
+                        info!("recording to {:?}", recording.dir());
+                        self.recording = Some(recording);
+                    }
+                    Err(error) => warn!("{}", error),
+                }
+            }
+        }
+    }
+
+    /// Saves what was drawn in the last frame if a screenshot was requested or we are recording
+    fn capture(&mut self, frame_s: f32) {
+        let _g = hprof::enter("capture");
+
+        if self.take_screenshot {
+            self.take_screenshot = false;
+            match capture::save_screenshot(&self.display) {
+                Ok(path) => info!("saved screenshot {:?}", path),
+                Err(error) => warn!("{}", error),
+            }
+        }
+
+        let result = match self.recording {
+            Some(ref mut recording) => recording.on_frame(&self.display, frame_s),
+            None => Ok(()),
+        };
+        if let Err(error) = result {
+            warn!("{}, stopping recording", error);
+            self.recording = None;
+        }
     }
 
     /// Produce graphics such as particles and audio from game events
@@ -336,11 +562,12 @@ impl Game {
 
                 let num = 100;
                 for _ in 0..num {
-                    self.particles.spawn_cone(0.45 + rand::random::<f32>() * 0.4,
-                                              color, color, 3.5 * rand::random::<f32>() + 2.0,
-                                              position, 0.0, f32::consts::PI * 2.0,
-                                              70.0 + rand::random::<f32>() * 40.0,
-                                              rand::random::<f32>() * 24.0, 1.0);
+                    self.renderer.particles.spawn_cone(0.45 + rand::random::<f32>() * 0.4,
+                                                       color, color,
+                                                       3.5 * rand::random::<f32>() + 2.0,
+                                                       position, 0.0, f32::consts::PI * 2.0,
+                                                       70.0 + rand::random::<f32>() * 40.0,
+                                                       rand::random::<f32>() * 24.0, 1.0);
                 }
             }
             &GameEvent::PlayerDash {
@@ -359,18 +586,21 @@ impl Game {
             } => {
                 let num = (3.0 * speed.sqrt()) as usize;
                 for _ in 0..num {
-                    self.particles.spawn_cone(0.5,
-                                              [0.0, 0.0, 0.0],
-                                              [0.0, 0.0, 0.0],
-                                              1.5,
-                                              position,
-                                              orientation_wall + f32::consts::PI,
-                                              f32::consts::PI,
-                                              20.0 + rand::random::<f32>() * 20.0,
-                                              0.0,
-                                              1.0);
+                    self.renderer.particles.spawn_cone(0.5,
+                                                       [0.0, 0.0, 0.0],
+                                                       [0.0, 0.0, 0.0],
+                                                       1.5,
+                                                       position,
+                                                       orientation_wall + f32::consts::PI,
+                                                       f32::consts::PI,
+                                                       20.0 + rand::random::<f32>() * 20.0,
+                                                       0.0,
+                                                       1.0);
                 }
             }
+            &GameEvent::ReloadMap => {
+                self.reload_map();
+            }
             &GameEvent::PlayerTakeItem {
                 player_id: _,
                 position,
@@ -381,9 +611,10 @@ impl Game {
                 let color = [0.0, 1.0, 0.0];
                 for _ in 0..num {
                     let t = rand::random::<f32>() * 0.25 + 0.25;
-                    self.particles.spawn_cone(t, color, color, 2.0, position, 0.0,
-                                              f32::consts::PI * 2.0,
-                                              45.0 + rand::random::<f32>() * 40.0, 2.0, 1.0);
+                    self.renderer.particles.spawn_cone(t, color, color, 2.0, position, 0.0,
+                                                       f32::consts::PI * 2.0,
+                                                       45.0 + rand::random::<f32>() * 40.0,
+                                                       2.0, 1.0);
                 }
             }
             &GameEvent::PlayerEquipItem {
@@ -396,9 +627,10 @@ impl Game {
                 /*let num = 100;
                 let color = [0.05, 0.5, 1.0];
                 for i in 0..num {
-                    self.particles.spawn_cone(0.4, color, color, 1.5, position, 0.0,
-                                              f32::consts::PI * 2.0,
-                                              200.0 + rand::random::<f32>() * 20.0, 0.0, 1.0);
+                    self.renderer.particles.spawn_cone(0.4, color, color, 1.5, position, 0.0,
+                                                       f32::consts::PI * 2.0,
+                                                       200.0 + rand::random::<f32>() * 20.0,
+                                                       0.0, 1.0);
                 }*/
             }
             &GameEvent::EnemyDied {
@@ -407,11 +639,12 @@ impl Game {
                 let num = 100;
                 let color = [1.0, 0.0, 0.0];
                 for _ in 0..num {
-                    self.particles.spawn_cone(rand::random::<f32>() * 0.4 + 0.2,
-                                              color, color, 2.5 * rand::random::<f32>() + 1.0,
-                                              position, 0.0, f32::consts::PI * 2.0,
-                                              70.0 + rand::random::<f32>() * 20.0,
-                                              rand::random::<f32>() * 5.0, 1.0);
+                    self.renderer.particles.spawn_cone(rand::random::<f32>() * 0.4 + 0.2,
+                                                       color, color,
+                                                       2.5 * rand::random::<f32>() + 1.0,
+                                                       position, 0.0, f32::consts::PI * 2.0,
+                                                       70.0 + rand::random::<f32>() * 20.0,
+                                                       rand::random::<f32>() * 5.0, 1.0);
                 }
             }
             &GameEvent::ProjectileImpact {
@@ -427,14 +660,22 @@ impl Game {
                     };
 
                 for _ in 0..num + (strength as usize * 4)  {
-                    self.particles.spawn_cone(0.25 + strength / 5.0, color, color,
-                                              strength * (1.0 * rand::random::<f32>() + 0.5),
-                                              position, 0.0,
-                                              f32::consts::PI * 2.0,
-                                              30.0 + rand::random::<f32>() * 15.0,
-                                              rand::random::<f32>() * 5.0, 1.0);
+                    self.renderer.particles.spawn_cone(0.25 + strength / 5.0, color, color,
+                                                       strength *
+                                                       (1.0 * rand::random::<f32>() + 0.5),
+                                                       position, 0.0,
+                                                       f32::consts::PI * 2.0,
+                                                       30.0 + rand::random::<f32>() * 15.0,
+                                                       rand::random::<f32>() * 5.0, 1.0);
                 }
             }
+            &GameEvent::Chat {
+                player_id,
+                ref text,
+                team_only,
+            } => {
+                self.add_chat_message(player_id, text, team_only);
+            }
             _ => ()
         };
     }
@@ -442,7 +683,8 @@ impl Game {
     fn interpolate(&mut self) {
         let _g = hprof::enter("interpolate");
 
-        let t = if self.tick_progress >= 1.0 { 1.0 } else { self.tick_progress };
+        // Values of t above 1 extrapolate past the latest tick
+        let t = self.tick_progress;
 
         self.state.world.systems.interpolation_system
             .interpolate(t, &mut self.state.world.data);
@@ -461,116 +703,39 @@ impl Game {
         }
 
         self.cam_pos = self.get_my_player_position().unwrap_or(self.cam_pos);
-        //self.cam_pos = self.cam_pos + (pos - self.cam_pos) * 0.15;
-
-        let (draw_width, draw_height) = target.get_dimensions();
-        let half_width = draw_width as f32 / 2.0;
-        let half_height = draw_height as f32 / 2.0;
-        let zoom = 3.0;
-
-        // Clip camera position to map size in pixels
-        if self.cam_pos[0] < half_width / zoom {
-            self.cam_pos[0] = half_width / zoom; 
-        } else if self.cam_pos[0] + half_width / zoom >
-                  self.state.map.width_pixels() as f32 {
-            self.cam_pos[0] = self.state.map.width_pixels() as f32 - half_width / zoom;
-        }
-        if self.cam_pos[1] < half_height / zoom {
-            self.cam_pos[1] = half_height / zoom; 
-        } else if self.cam_pos[1] + half_height / zoom >
-                  self.state.map.height_pixels() as f32 {
-            self.cam_pos[1] = self.state.map.height_pixels() as f32 - half_height / zoom;
-        }
-
-        let draw_parameters = glium::DrawParameters {
-            depth: glium::Depth {
-                test: glium::draw_parameters::DepthTest::IfMore,
-                write: true,
-                .. Default::default()
-            },
-            .. Default::default()
-        };
+        self.cam_pos = Renderer::clip_camera(&self.state.map, self.cam_pos,
+                                             target.get_dimensions());
 
-        let far = -10.0;
-        let near = 1.0;
-        let draw_context = DrawContext {
-            proj_mat: Mat4::new(2.0 / (draw_width as f32), 0.0, 0.0, 0.0,
-                                0.0, 2.0 / (draw_height as f32), 0.0, 0.0,
-                                0.0, 0.0, -2.0 / (far - near), -(far + near) / (far - near),
-                                0.0, 0.0, 0.0, 1.0),
-                //OrthoMat3::new(draw_width as f32, draw_height as f32, 10.0, 0.0).to_mat(),
-            camera_mat: Mat4::new(zoom, 0.0, 0.0, -self.cam_pos.x * zoom,
-                                  0.0, zoom, 0.0, -self.cam_pos.y * zoom,
-                                  0.0, 0.0, zoom, 0.0, 
-                                  0.0, 0.0, 0.0, 1.0),
-            parameters: draw_parameters,
-        };
+        let draw_context = Renderer::draw_context(self.cam_pos, target.get_dimensions());
         drop(_g);
 
-        let mut draw_list = DrawList::new();
-        {
-            let _g = hprof::enter("entities");
-
-            // TODO: particle emitters
-            self.state.world.systems.draw_player_system
-                .spawn_particles(&mut self.state.world.data, simulation_time_s,
-                                 &mut self.particles);
-            self.state.world.systems.draw_item_system
-                .spawn_particles(&mut self.state.world.data, simulation_time_s,
-                                 &mut self.particles);
-
-            self.state.world.systems.draw_player_system
-                .draw(&mut self.state.world.data, &mut draw_list);
-            self.state.world.systems.draw_wall_system
-                .draw(&mut self.state.world.data, &mut draw_list);
-            self.state.world.systems.draw_projectile_system
-                .draw(&mut self.state.world.data, &mut draw_list);
-            self.state.world.systems.draw_bouncy_enemy_system
-                .draw(&mut self.state.world.data, &mut draw_list);
-            self.state.world.systems.draw_item_system
-                .draw(&mut self.state.world.data, &mut draw_list);
-        }
-        {
-            let _g = hprof::enter("update particles");
-            self.particles.update(simulation_time_s);
-        }
-
-        {
-            let mut draw_draw_list = self.draw_draw_list.borrow_mut();
-            let mut draw_lists_op = DrawListsOp {
-                draw_draw_list: draw_draw_list,
-                draw_list: &draw_list,
-                particles: &self.particles,
-                draw_context: &draw_context,
-            };
-            self.draw_post.draw(&mut target, &mut draw_lists_op);
-            /*self.draw_post.draw(&mut target, |target| {
-                let _g = hprof::enter("draw list");
-                target.clear_color_and_depth((0.1, 0.1, 0.1, 1.0), -1.0);
-                draw_draw_list.draw(draw_list.clone(), &draw_context, target);
-                {
-                    let _g = hprof::enter("draw particles");
-                    self.particles.draw(&draw_context, target);
-                }
-            });*/
-        }
-
-        target.clear_depth(-1.0);
-        self.draw_draw_list.borrow_mut().draw(FLAG_NONE, draw_list.clone(), &draw_context, &mut target);
-        {
-            let _g = hprof::enter("draw particles");
-            self.particles.draw(&draw_context, &mut target);
-        }
+        let tick_time = self.current_tick.as_ref()
+                            .map_or(0.0, |tick| tick.tick_number as f32 + self.tick_progress);
+        self.renderer.draw_world(&mut self.state, tick_time, simulation_time_s, &draw_context,
+                                 &mut target);
 
         {
             let _g = hprof::enter("text");
             self.draw_debug_text(&draw_context.proj_mat, &mut target);
             self.draw_player_text(&draw_context.proj_mat, &mut target);
             self.draw_death_messages(&draw_context.proj_mat, &mut target);
+            self.draw_chat(simulation_time_s, &draw_context.proj_mat, &mut target);
             self.draw_player_names(&draw_context.proj_mat, &mut target);
+            if self.motd_time_s > 0.0 {
+                self.motd_time_s -= simulation_time_s;
+                self.draw_motd(&draw_context.proj_mat, &mut target);
+            }
             if self.draw_player_stats {
                 self.draw_player_stats(&draw_context.proj_mat, &mut target);
             }
+            if self.bindings_screen.is_some() {
+                self.draw_bindings_screen(&draw_context.proj_mat, &mut target);
+            }
+        }
+
+        if self.show_prof_overlay {
+            let _g = hprof::enter("prof overlay");
+            self.draw_prof_overlay(&mut target);
         }
 
         {
@@ -625,6 +790,49 @@ impl Game {
         }
     }
 
+    fn add_chat_message(&mut self, player_id: PlayerId, text: &str, team_only: bool) {
+        if self.chat_messages.len() == MAX_CHAT_MESSAGES {
+            self.chat_messages.pop_front();
+        }
+
+        // The sender may have joined in this very tick, so that we don't know them yet
+        let (name, color) = match self.state.players().get(&player_id) {
+            Some(info) => (info.name.clone(), (info.color[0], info.color[1], info.color[2])),
+            None => ("?".to_string(), (1.0, 1.0, 1.0)),
+        };
+
+        let message = format!("{}{}: {}", if team_only { "[team] " } else { "" }, name, text);
+        debug!("chat message: {}", message);
+        self.chat_messages.push_back((message, color, CHAT_MESSAGE_DURATION_S));
+    }
+
+    fn draw_chat<S: Surface>(&mut self, simulation_time_s: f32, proj_mat: &Mat4<f32>,
+                             target: &mut S) {
+        let (_, h) = target.get_dimensions();
+        let size = 12.0;
+        let x = 10.0;
+        let mut y = h as f32 - 35.0;
+
+        if let Some((text, team_only)) = self.chat_input.clone() {
+            let prompt = if team_only { "say (team)" } else { "say" };
+            self.draw_text((1.0, 1.0, 0.0, 1.0), x, y, &format!("{}: {}_", prompt, text),
+                           proj_mat, size, target);
+        }
+        y -= 30.0;
+
+        // Newest message at the bottom
+        for i in (0..self.chat_messages.len()).rev() {
+            self.chat_messages[i].2 -= simulation_time_s;
+
+            let (s, (r, g, b), time_s) = self.chat_messages[i].clone();
+            let alpha = if self.chat_input.is_some() { 1.0 } else { time_s.max(0.0).min(1.0) };
+            if alpha > 0.0 {
+                self.draw_text((r, g, b, alpha), x, y, &s, proj_mat, size, target);
+            }
+            y -= 25.0;
+        }
+    }
+
     fn draw_player_names<S: Surface>(&mut self, proj_mat: &Mat4<f32>, target: &mut S) {
         let color = (1.0, 1.0, 1.0, 1.0);
         let size = 11.0;
@@ -660,7 +868,8 @@ impl Game {
 
         let players = self.state.players().clone();
         for (_, info) in players.iter() {
-            self.draw_text(color, x1, y, &info.name, proj_mat, size, target);
+            let name_color = (info.color[0], info.color[1], info.color[2], 1.0);
+            self.draw_text(name_color, x1, y, &info.name, proj_mat, size, target);
             self.draw_text(color, x2, y, &format!("{}", info.stats.score), proj_mat, size,
                            target);
             self.draw_text(color, x3, y, &format!("{}", info.stats.deaths), proj_mat, size,
@@ -669,6 +878,46 @@ impl Game {
         }
     }
 
+    fn draw_motd<S: Surface>(&mut self, proj_mat: &Mat4<f32>, target: &mut S) {
+        let (w, h) = target.get_dimensions();
+        let x = w as f32 / 2.0 - 200.0;
+        let y = h as f32 / 2.0 - 100.0;
+
+        let server_name = self.client.game_info().server_name.clone();
+        let motd = self.client.game_info().motd.clone();
+
+        // Fade out during the last second
+        let alpha = self.motd_time_s.min(1.0);
+        self.draw_text((1.0, 1.0, 1.0, alpha), x, y, &server_name, proj_mat, 14.0, target);
+        if !motd.is_empty() {
+            self.draw_text((0.8, 0.8, 0.8, alpha), x, y + 35.0, &motd, proj_mat, 10.0, target);
+        }
+    }
+
+    fn draw_bindings_screen<S: Surface>(&mut self, proj_mat: &Mat4<f32>, target: &mut S) {
+        let lines = match self.bindings_screen {
+            Some(ref bindings_screen) => bindings_screen.lines(&self.player_input_map),
+            None => return,
+        };
+
+        let (w, _) = target.get_dimensions();
+        let x = w as f32 / 2.0 - 200.0;
+        let mut y = 60.0;
+        let size = 10.0;
+        let r = 20.0;
+
+        self.draw_text((1.0, 1.0, 1.0, 1.0), x, y,
+                       "bindings - return: rebind, insert: add, delete: remove, F1: close",
+                       proj_mat, size, target);
+        y += 1.5 * r;
+
+        for &(ref line, selected) in lines.iter() {
+            let color = if selected { (1.0, 1.0, 0.0, 1.0) } else { (0.8, 0.8, 0.8, 1.0) };
+            self.draw_text(color, x, y, line, proj_mat, size, target);
+            y += r;
+        }
+    }
+
     fn draw_debug_text<S: Surface>(&mut self, proj_mat: &Mat4<f32>, target: &mut S) {
         let color = (1.0, 0.0, 1.0, 1.0);
 
@@ -686,9 +935,14 @@ impl Game {
         let s = &format!("time factor: {:.1}", self.time_factor);
         self.draw_text(color, 10.0, 10.0 + 3.0*r, s, proj_mat, size, target);
 
-        let s = &format!("num particles: {}", self.particles.num());
+        let s = &format!("jitter: {:.1}ms, target ticks: {}",
+                         self.jitter_buffer.jitter_s() * 1000.0,
+                         self.jitter_buffer.target_ticks());
         self.draw_text(color, 10.0, 10.0 + 4.0*r, s, proj_mat, size, target);
 
+        let s = &format!("num particles: {}", self.renderer.particles.num());
+        self.draw_text(color, 10.0, 10.0 + 5.0*r, s, proj_mat, size, target);
+
         if let Some(entity) = self.get_my_player_entity() {
             let speed =
                 self.state.world.with_entity_data(&entity, |e, c| {
@@ -696,7 +950,40 @@ impl Game {
                 }).unwrap();
 
             let s = &format!("player speed: {:.1}", speed);
-            self.draw_text(color, 10.0, 10.0 + 5.0*r, s, proj_mat, size, target);
+            self.draw_text(color, 10.0, 10.0 + 6.0*r, s, proj_mat, size, target);
+        }
+    }
+
+    fn draw_prof_overlay<S: Surface>(&mut self, target: &mut S) {
+        let (w, h) = target.get_dimensions();
+
+        let mut draw_list = DrawList::new();
+        let labels = self.prof_overlay.draw(w as f32 - OVERLAY_WIDTH - 10.0, 10.0,
+                                            &mut draw_list);
+
+        let draw_context = DrawContext {
+            proj_mat: pixel_proj_mat(w, h),
+            // Pixels with the origin in the top left corner, like text
+            camera_mat: Mat4::new(1.0, 0.0, 0.0, -(w as f32) / 2.0,
+                                  0.0, -1.0, 0.0, h as f32 / 2.0,
+                                  0.0, 0.0, 1.0, 0.0,
+                                  0.0, 0.0, 0.0, 1.0),
+            parameters: glium::DrawParameters {
+                depth: glium::Depth {
+                    test: glium::draw_parameters::DepthTest::IfMore,
+                    write: true,
+                    .. Default::default()
+                },
+                .. Default::default()
+            },
+        };
+
+        target.clear_depth(-1.0);
+        self.renderer.draw_draw_list.draw(FLAG_NONE, draw_list, &draw_context, target);
+
+        let color = (1.0, 1.0, 1.0, 1.0);
+        for (x, y, text) in labels {
+            self.draw_text(color, x, y, &text, &draw_context.proj_mat, 10.0, target);
         }
     }
 
@@ -723,7 +1010,10 @@ impl Game {
                 self.draw_text(color1, 25.0, y2, &format!("{:.1}", t), proj_mat, size, target);
             }
 
-            let slot_names = vec!["Q", "W", "E"]; // TODO
+            let slot_names = [PlayerInputKey::Item1, PlayerInputKey::Item2, PlayerInputKey::Item3]
+                .iter()
+                .map(|&key| self.player_input_map.binding_name(key).unwrap_or("-".to_string()))
+                .collect::<Vec<_>>();
             let mut cursor_x = 150.0;
 
             for (item_slot, slot_name) in (0..NUM_ITEM_SLOTS).zip(slot_names.iter()) {
@@ -738,8 +1028,12 @@ impl Game {
                     self.draw_text(color, cursor_x, y2, &text, proj_mat, size, target);
 
                     if let Some(t) = equipped_item.cooldown_s {
-                        self.draw_text(color1, cursor_x, y3, &format!("{:.1}", t),
-                                       proj_mat, size, target);
+                        let text = match self.state.game_info.balance
+                                                   .cooldown_s(&equipped_item.item) {
+                            Some(max_t) => format!("{:.1}/{:.1}", t, max_t),
+                            None => format!("{:.1}", t),
+                        };
+                        self.draw_text(color1, cursor_x, y3, &text, proj_mat, size, target);
                     }
                 } else {
                     self.draw_text(color2, cursor_x, y1, slot_name, proj_mat, size, target);
@@ -758,22 +1052,7 @@ impl Game {
 
     fn draw_text<S: Surface>(&mut self, color: (f32, f32, f32, f32), x: f32, y: f32,
                              s: &str, proj_mat: &Mat4<f32>, size: f32, target: &mut S) {
-        let (w, h) = target.get_dimensions();
-        let sub_trans = Mat4::new(1.0, 0.0, 0.0, 0.0,
-                                  0.0, 1.0, 0.0, -1.0,
-                                  0.0, 0.0, 1.0, 0.0,
-                                  0.0, 0.0, 0.0, 1.0);
-        let trans = Mat4::new(1.0, 0.0, 0.0, -(w as f32) / 2.0 + x,
-                              0.0, 1.0, 0.0, h as f32 / 2.0 - y,
-                              0.0, 0.0, 1.0, -0.5,
-                              0.0, 0.0, 0.0, 1.0);
-        let scale = Mat4::new(size, 0.0, 0.0, 0.0,
-                              0.0, size, 0.0, 0.0,
-                              0.0, 0.0, size, 0.0,
-                              0.0, 0.0, 0.0, 1.0);
-        let m = *proj_mat * trans * scale * sub_trans;
-        let text = glium_text::TextDisplay::new(&self.text_system, &self.font, s); // TODO
-        glium_text::draw(&text, &self.text_system, target, *m.as_array(), color);
+        draw_text(&self.text_system, &self.font, color, x, y, s, proj_mat, size, target);
     }
 
     fn draw_text_sub_width<S: Surface>(&mut self, color: (f32, f32, f32, f32), x: f32, y: f32,
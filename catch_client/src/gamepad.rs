@@ -0,0 +1,99 @@
+//! Gamepad input. glutin, which creates our window, only reports keyboard and mouse events, so
+//! we read joystick devices ourselves. This is only implemented for the Linux joystick API
+//! (/dev/input/js*); on other systems opening a gamepad fails and the game continues without.
+
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum GamepadEvent {
+    Button(u8, bool),
+
+    // Axis values are in [-1, 1]
+    Axis(u8, f32),
+}
+
+/// Reads events from a joystick device such as /dev/input/js0 in a background thread and hands
+/// them to the game loop.
+pub struct Gamepad {
+    receiver: Receiver<GamepadEvent>,
+}
+
+impl Gamepad {
+    #[cfg(target_os = "linux")]
+    pub fn open(path: &str) -> Result<Gamepad, String> {
+        Ok(Gamepad {
+            receiver: try!(linux::spawn_reader(path)),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn open(path: &str) -> Result<Gamepad, String> {
+        Err(format!("could not open gamepad {}: gamepads are only supported on Linux", path))
+    }
+
+    /// Returns the events received since the last call
+    pub fn poll_events(&self) -> Vec<GamepadEvent> {
+        let mut events = Vec::new();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(event) => events.push(event),
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+            }
+        }
+        events
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::io::Read;
+    use std::fs::File;
+    use std::thread;
+    use std::sync::mpsc::{channel, Receiver};
+
+    use super::GamepadEvent;
+
+    // Event types of the Linux joystick API (see linux/joystick.h)
+    const JS_EVENT_BUTTON: u8 = 0x01;
+    const JS_EVENT_AXIS: u8 = 0x02;
+    const JS_EVENT_INIT: u8 = 0x80;
+
+    /// Opens the device and starts a thread that sends its events until the receiver is dropped
+    pub fn spawn_reader(path: &str) -> Result<Receiver<GamepadEvent>, String> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(error) => return Err(format!("could not open gamepad {}: {}", path, error)),
+        };
+
+        let (sender, receiver) = channel();
+        let path = path.to_string();
+
+        thread::spawn(move || {
+            let mut buffer = [0u8; 8];
+            loop {
+                if let Err(error) = file.read_exact(&mut buffer) {
+                    warn!("stopped reading gamepad {}: {}", path, error);
+                    return;
+                }
+
+                // struct js_event { u32 time; i16 value; u8 type; u8 number; }
+                let value = (buffer[4] as u16 | (buffer[5] as u16) << 8) as i16;
+                let event_type = buffer[6] & !JS_EVENT_INIT;
+                let number = buffer[7];
+
+                let event = match event_type {
+                    JS_EVENT_BUTTON => GamepadEvent::Button(number, value != 0),
+                    JS_EVENT_AXIS => GamepadEvent::Axis(number, value as f32 / 32767.0),
+                    _ => continue,
+                };
+
+                if sender.send(event).is_err() {
+                    // The game has been closed
+                    return;
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+}
@@ -0,0 +1,171 @@
+use time::Timespec;
+
+// Smoothing factor for the jitter estimate, as in RFC 3550
+const JITTER_GAIN: f32 = 1.0 / 16.0;
+
+// How many multiples of the measured jitter we want to have buffered
+const JITTER_SAFETY: f32 = 2.0;
+
+// Bounds on the number of ticks we keep queued for interpolation
+pub const MIN_BUFFER_TICKS: f32 = 1.0;
+pub const MAX_BUFFER_TICKS: f32 = 6.0;
+
+// How quickly the target buffer depth follows the measured jitter
+const TARGET_GAIN: f32 = 0.05;
+
+// Playback speed adjustment per tick of difference between queued and target depth
+const SPEED_GAIN: f32 = 0.1;
+const MIN_TIME_FACTOR: f32 = 0.8;
+const MAX_TIME_FACTOR: f32 = 1.25;
+const TIME_FACTOR_SMOOTHING: f32 = 0.1;
+
+// For how long we are allowed to extrapolate when the next tick is late
+pub const MAX_EXTRAPOLATION_S: f32 = 0.1;
+
+/// Measures the jitter in tick arrival times and derives the interpolation buffer depth and
+/// playback speed from it
+pub struct JitterBuffer {
+    tick_period_s: f32,
+
+    last_arrival: Option<Timespec>,
+
+    // Smoothed mean deviation of tick inter-arrival times from the tick period
+    jitter_s: f32,
+
+    // Smoothed number of ticks we want to have queued
+    target_ticks: f32,
+
+    time_factor: f32,
+}
+
+impl JitterBuffer {
+    pub fn new(ticks_per_second: u32) -> JitterBuffer {
+        JitterBuffer {
+            tick_period_s: 1.0 / ticks_per_second as f32,
+            last_arrival: None,
+            jitter_s: 0.0,
+            target_ticks: 2.0,
+            time_factor: 1.0,
+        }
+    }
+
+    /// Should be called with the arrival time of every tick, in the order they were received
+    pub fn on_tick_arrival(&mut self, arrival: Timespec) {
+        if let Some(last_arrival) = self.last_arrival {
+            let interarrival_s = (arrival - last_arrival).num_microseconds().unwrap() as f32 /
+                                 1000000.0;
+            let deviation_s = (interarrival_s - self.tick_period_s).abs();
+            self.jitter_s += (deviation_s - self.jitter_s) * JITTER_GAIN;
+        }
+        self.last_arrival = Some(arrival);
+
+        let wanted_ticks = (MIN_BUFFER_TICKS +
+                            JITTER_SAFETY * self.jitter_s / self.tick_period_s)
+                           .max(MIN_BUFFER_TICKS).min(MAX_BUFFER_TICKS);
+        self.target_ticks += (wanted_ticks - self.target_ticks) * TARGET_GAIN;
+    }
+
+    /// Returns the factor by which playback should be sped up (> 1) or slowed down (< 1), given
+    /// the number of ticks that are currently queued
+    pub fn update_time_factor(&mut self, num_queued_ticks: usize) -> f32 {
+        let error = num_queued_ticks as f32 - self.target_ticks;
+        let wanted = (1.0 + SPEED_GAIN * error).max(MIN_TIME_FACTOR).min(MAX_TIME_FACTOR);
+        self.time_factor += (wanted - self.time_factor) * TIME_FACTOR_SMOOTHING;
+        self.time_factor
+    }
+
+    /// Number of ticks that we should wait for before starting playback
+    pub fn target_ticks(&self) -> usize {
+        self.target_ticks.ceil() as usize
+    }
+
+    pub fn jitter_s(&self) -> f32 {
+        self.jitter_s
+    }
+
+    /// Maximal tick progress we can reach by extrapolating past the latest tick
+    pub fn max_tick_progress(&self) -> f32 {
+        1.0 + MAX_EXTRAPOLATION_S / self.tick_period_s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, Timespec};
+
+    use super::{JitterBuffer, MIN_BUFFER_TICKS, MAX_BUFFER_TICKS, MIN_TIME_FACTOR,
+                MAX_TIME_FACTOR, MAX_EXTRAPOLATION_S};
+
+    const TICKS_PER_SECOND: u32 = 30;
+    const TICK_PERIOD_MS: i64 = 1000 / TICKS_PER_SECOND as i64;
+
+    /// Feeds ticks that arrive one period apart, each shifted by up to `jitter_ms` in either
+    /// direction, and checks the bounds after every tick
+    fn feed(buffer: &mut JitterBuffer, num_ticks: usize, jitter_ms: i64) {
+        let mut x: u32 = 12345;
+        let start = Timespec::new(1000, 0);
+
+        for i in 0..num_ticks {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            let offset_ms = if jitter_ms > 0 {
+                (x >> 8) as i64 % (2 * jitter_ms + 1) - jitter_ms
+            } else {
+                0
+            };
+            let arrival_ms = i as i64 * TICK_PERIOD_MS + offset_ms;
+            buffer.on_tick_arrival(start + Duration::milliseconds(arrival_ms));
+
+            assert!(buffer.target_ticks >= MIN_BUFFER_TICKS &&
+                    buffer.target_ticks <= MAX_BUFFER_TICKS,
+                    "target ticks {} after tick {}", buffer.target_ticks, i);
+            assert!(buffer.target_ticks() >= 1 && buffer.target_ticks() <= 6);
+        }
+    }
+
+    #[test]
+    fn target_ticks_follow_jitter_within_bounds() {
+        let mut steady = JitterBuffer::new(TICKS_PER_SECOND);
+        feed(&mut steady, 500, 0);
+        assert!(steady.jitter_s() < 0.001);
+
+        let mut jittery = JitterBuffer::new(TICKS_PER_SECOND);
+        feed(&mut jittery, 500, 40);
+        assert!(jittery.jitter_s() > 0.01);
+        assert!(jittery.target_ticks > steady.target_ticks);
+
+        // Way more jitter than the buffer can cover
+        let mut terrible = JitterBuffer::new(TICKS_PER_SECOND);
+        feed(&mut terrible, 500, 1000);
+        assert!(terrible.target_ticks > MAX_BUFFER_TICKS - 0.1);
+    }
+
+    #[test]
+    fn time_factor_stays_within_bounds() {
+        let mut buffer = JitterBuffer::new(TICKS_PER_SECOND);
+        feed(&mut buffer, 100, 20);
+
+        for &num_queued_ticks in &[0, 1, 2, 3, 10, 100] {
+            let mut time_factor = 1.0;
+            for _ in 0..200 {
+                time_factor = buffer.update_time_factor(num_queued_ticks);
+                assert!(time_factor >= MIN_TIME_FACTOR && time_factor <= MAX_TIME_FACTOR,
+                        "time factor {} with {} queued ticks", time_factor, num_queued_ticks);
+            }
+
+            if num_queued_ticks == 0 {
+                assert!(time_factor < 1.0);
+            } else if num_queued_ticks == 100 {
+                assert!((time_factor - MAX_TIME_FACTOR).abs() < 0.001);
+            }
+        }
+    }
+
+    #[test]
+    fn extrapolation_stops_at_max_extrapolation() {
+        let buffer = JitterBuffer::new(TICKS_PER_SECOND);
+        let tick_period_s = 1.0 / TICKS_PER_SECOND as f32;
+
+        let extrapolation_s = (buffer.max_tick_progress() - 1.0) * tick_period_s;
+        assert!((extrapolation_s - MAX_EXTRAPOLATION_S).abs() < 0.0001);
+    }
+}
@@ -0,0 +1,106 @@
+use std::thread::{self, JoinHandle};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+
+use time;
+
+use shared::transport::{MemoryTransport, MemoryAddress};
+
+use catch_server::{server, Server};
+
+use client::Client;
+use dummy::DummyClient;
+
+/// A server that runs on a background thread of the client, for playing alone without
+/// starting a separate server. Clients in the same process connect to it through channels
+/// instead of the network. The server and its bots are stopped when this is dropped.
+pub struct LocalServer {
+    address: MemoryAddress,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    bot_threads: Vec<JoinHandle<()>>,
+}
+
+impl LocalServer {
+    /// Starts a server on the given map and waits until it accepts connections
+    pub fn start(map_name: &str) -> Result<LocalServer, String> {
+        let game_info = try!(server::game_info(map_name));
+        let seed = time::get_time().nsec as u64;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let (sender, receiver) = channel();
+
+        // The server is not Send, so it needs to be created on its thread
+        let thread = thread::spawn(move || {
+            let transport = MemoryTransport::new();
+            let address = transport.address();
+
+            match Server::start(&game_info, seed, None, Box::new(transport)) {
+                Ok(mut server) => {
+                    sender.send(Ok(address)).unwrap();
+                    server.run_until(&thread_stop);
+                }
+                Err(error) => {
+                    sender.send(Err(error)).unwrap();
+                }
+            }
+        });
+
+        let address = match receiver.recv() {
+            Ok(Ok(address)) => address,
+            Ok(Err(error)) => return Err(format!("could not start local server: {}", error)),
+            Err(_) => return Err("local server stopped while starting".to_string()),
+        };
+
+        info!("started local server on map {}", map_name);
+
+        Ok(LocalServer {
+            address: address,
+            stop: stop,
+            thread: Some(thread),
+            bot_threads: Vec::new(),
+        })
+    }
+
+    pub fn address(&self) -> &MemoryAddress {
+        &self.address
+    }
+
+    /// Connects a dummy client that plays on a background thread until the server is stopped
+    pub fn add_bot(&mut self, color: [f32; 3]) {
+        let address = self.address.clone();
+        let stop = self.stop.clone();
+
+        self.bot_threads.push(thread::spawn(move || {
+            let client = Client::connect_local(&address, "bot".to_string(), color)
+                             .and_then(|mut client| {
+                                 try!(client.finish_connecting(5000));
+                                 Ok(client)
+                             });
+
+            match client {
+                Ok(client) => DummyClient::new(client).run_until(&stop),
+                Err(error) => warn!("could not connect bot to local server: {}", error),
+            }
+        }));
+    }
+}
+
+impl Drop for LocalServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("local server panicked");
+            }
+        }
+        for thread in self.bot_threads.drain(..) {
+            if thread.join().is_err() {
+                warn!("bot of local server panicked");
+            }
+        }
+    }
+}
@@ -18,10 +18,16 @@ extern crate image;
 #[macro_use] extern crate bitflags;
 
 #[macro_use] extern crate catch_shared as shared;
+extern crate catch_server;
 
 mod client;
 mod player_input;
+mod gamepad;
+mod bindings_screen;
 mod draw_map;
+mod editor;
+mod menu;
+mod local_server;
 mod components;
 mod entities;
 mod services;
@@ -31,19 +37,40 @@ mod game;
 mod particles;
 mod sounds;
 mod dummy;
+mod jitter;
+mod prof_overlay;
+mod renderer;
+mod snapshot;
+mod capture;
 mod draw;
 
 use std::env;
+use std::mem;
+use std::process;
+use std::io::{self, Write};
+use std::path::PathBuf;
 
-use getopts::Options;
+use getopts::{Options, Matches};
 
-use glium::DisplayBuild;
+use glium::{Display, DisplayBuild};
+
+use shared::map::{Map, MapError};
+use shared::player::PLAYER_COLORS;
+use shared::transport::{Transport, LossyTransport, LossSettings};
+use shared::discovery;
+
+use catch_server::server;
 
 use client::Client;
 use player_input::InputMap;
-use game::Game;
+use gamepad::Gamepad;
+use game::{Game, GameExit};
+use editor::{Editor, TestPlaySettings};
+use menu::{Menu, MenuAction};
+use local_server::LocalServer;
 use draw::PostSettings;
 use dummy::DummyClient;
+use snapshot::Snapshot;
 
 fn main() {
     env_logger::init().unwrap();
@@ -51,51 +78,343 @@ fn main() {
     let args: Vec<String> = env::args().collect();
 
     let mut opts = Options::new();
-    opts.optopt("c", "connect", "set server address to connect to", "ADDRESS");
+    opts.optopt("c", "connect", "connect to the given server without showing the menu first",
+                "ADDRESS[:PORT]");
+    opts.optflag("", "list", "look for servers on the local network and list them");
     opts.optflag("", "dummy", "create a dummy client without graphical display");
+    opts.optopt("", "bindings", "load and save key bindings from the given file", "FILE");
+    opts.optopt("", "gamepad", "read gamepad input from the given joystick device (Linux only)",
+                "DEVICE");
+    opts.optopt("", "edit", "edit the given map file instead of connecting to a server", "MAP");
+    opts.optflag("", "local", "start a server in the background and play on it");
+    opts.optopt("", "map", "map for the local server, or random:SEED[:WIDTHxHEIGHT]", "MAP");
+    opts.optopt("", "bots", "number of dummy clients to add to the local server", "N");
+    opts.optopt("", "simulate-network",
+                "delay packets in both directions, e.g. latency=100,jitter=20,loss=0.05. Loss \
+                 and reorder only affect unreliable packets, and the game sends everything \
+                 reliably", "SETTINGS");
+    opts.optopt("", "render-snapshot",
+                "render a snapshot saved with F12 into the image file given after the options, \
+                 without opening a window", "FILE");
+    opts.optopt("", "size", "size of the image rendered with --render-snapshot",
+                "WIDTHxHEIGHT");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
         Err(e) => panic!(e.to_string())
     };
-    let address = match matches.opt_str("c") {
-        Some(s) => s,
-        None => "127.0.0.1".to_string()
-    };
     let dummy = matches.opt_present("dummy");
 
     let post_settings = PostSettings {
         blur: false,
     };
 
-    // Connect
+    if let Some(snapshot_path) = matches.opt_str("render-snapshot") {
+        render_snapshot(&matches, &snapshot_path);
+        return;
+    }
+
     enet::initialize().unwrap();
-    let port = 9988;
-    info!("connecting to {}:{}", address, port);
-    let name = if dummy { "bot" } else { "leo" };
-    let mut client = Client::connect(5000,
-                                     address,
-                                     9988,
-                                     name.to_string()).unwrap();
-    client.finish_connecting(5000).unwrap();
+
+    if let Some(map_path) = matches.opt_str("edit") {
+        edit(&matches, PathBuf::from(map_path), post_settings);
+        return;
+    }
+
+    if dummy {
+        run_dummy(&matches);
+        return;
+    }
+
+    let display = create_display();
+    let mut input_map = load_input_map(&matches);
+    let mut gamepad = open_gamepad(&matches);
+
+    let name = env::var("USER").unwrap_or("player".to_string());
+    let address = matches.opt_str("c").unwrap_or("127.0.0.1".to_string());
+    let mut menu = Menu::new(display.clone(), name, address);
+
+    if matches.opt_present("list") {
+        menu.find_servers();
+    }
+
+    // Choices given on the command line skip the menu the first time
+    let mut action = if matches.opt_present("local") {
+        Some(MenuAction::PlayLocal)
+    } else if let Some(address) = matches.opt_str("c") {
+        match menu::parse_address(&address) {
+            Ok((host, port)) => Some(MenuAction::Join(host, port)),
+            Err(error) => {
+                menu.show_error(&error);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    loop {
+        let next_action = match action.take().or_else(|| menu.run()) {
+            Some(next_action) => next_action,
+            None => break,
+        };
+
+        match play(&matches, &mut menu, &next_action, &display, &mut input_map, &mut gamepad,
+                   &post_settings) {
+            Ok(true) => menu.clear_status(),
+            Ok(false) => break,
+            Err(error) => menu.show_error(&error),
+        }
+    }
+}
+
+/// Connects and plays until the game is over. Returns false if the window was closed.
+fn play(matches: &Matches,
+        menu: &mut Menu,
+        action: &MenuAction,
+        display: &Display,
+        input_map: &mut InputMap,
+        gamepad: &mut Option<Gamepad>,
+        post_settings: &PostSettings) -> Result<bool, String> {
+    let name = menu.name();
+    let color = menu.color();
+
+    // Kept until the game is over, which stops the server
+    let (client, _local_server) =
+        try!(connect(matches, action, name, color, |status| menu.show_progress(status)));
+
+    // The game gets the input devices for as long as it runs
+    let mut game = Game::new(client,
+                             mem::replace(input_map, InputMap::new()),
+                             gamepad.take(),
+                             post_settings.clone(),
+                             display.clone());
+    let exit = game.run();
+
+    let (game_input_map, game_gamepad) = game.into_input();
+    *input_map = game_input_map;
+    *gamepad = game_gamepad;
+
+    match exit {
+        GameExit::Left => Ok(true),
+        GameExit::Closed => Ok(false),
+        GameExit::Disconnected(error) => Err(format!("disconnected: {}", error)),
+    }
+}
+
+/// Starts a local server if that is what was chosen and connects to the server. Progress is
+/// reported through the given function.
+fn connect<F: FnMut(&str)>(matches: &Matches,
+                           action: &MenuAction,
+                           name: String,
+                           color: [f32; 3],
+                           mut progress: F) -> Result<(Client, Option<LocalServer>), String> {
+    let (client, local_server) = match *action {
+        MenuAction::PlayLocal => {
+            let map_name = matches.opt_str("map").unwrap_or(server::DEFAULT_MAP.to_string());
+            progress(&format!("starting local server on {}...", map_name));
+            let mut local_server = try!(LocalServer::start(&map_name));
+
+            let num_bots = match matches.opt_str("bots") {
+                Some(s) => try!(s.parse::<usize>().map_err(|_| {
+                    format!("invalid number of bots: {}", s)
+                })),
+                None => 0,
+            };
+            for i in 0..num_bots {
+                local_server.add_bot(PLAYER_COLORS[(i + 1) % PLAYER_COLORS.len()].1);
+            }
+
+            progress("connecting to local server...");
+            let client = try!(Client::connect_local(local_server.address(), name, color));
+            (client, Some(local_server))
+        }
+        MenuAction::Join(ref host, port) => {
+            progress(&format!("connecting to {}:{}...", host, port));
+            (try!(Client::connect(5000, host.clone(), port, name, color)), None)
+        }
+    };
+
+    let mut client = match matches.opt_str("simulate-network") {
+        Some(s) => {
+            let settings = try!(LossSettings::parse(&s));
+            info!("simulating network with {:?}", settings);
+            client.map_transport(|transport| {
+                Box::new(LossyTransport::new(transport, settings)) as Box<Transport>
+            })
+        }
+        None => client,
+    };
+
+    progress("joining game...");
+    try!(client.finish_connecting(5000));
 
     info!("connected to server! My id: {}", client.my_id());
     info!("game info: {:?}", client.game_info());
 
-    if !dummy {
-        let display = glium::glutin::WindowBuilder::new()
-            .with_dimensions(640, 480)
-            .with_title(format!("Catching game"))
-            .build_glium()
-            .unwrap();
-
-        let mut game = Game::new(client,
-                                 InputMap::new(),
-                                 post_settings,
-                                 display);
-        game.run();
+    Ok((client, local_server))
+}
+
+/// Plays with a dummy client that has no graphical display
+fn run_dummy(matches: &Matches) {
+    let action = if matches.opt_present("local") {
+        MenuAction::PlayLocal
+    } else if matches.opt_present("list") {
+        match choose_server() {
+            Some((host, port)) => MenuAction::Join(host, port),
+            None => return,
+        }
     } else {
-        let mut dummy = DummyClient::new(client);
-        dummy.run();
+        let address = matches.opt_str("c").unwrap_or("127.0.0.1".to_string());
+        let (host, port) = menu::parse_address(&address).unwrap();
+        MenuAction::Join(host, port)
+    };
+
+    let (client, _local_server) =
+        connect(matches, &action, "bot".to_string(), PLAYER_COLORS[0].1,
+                |status| info!("{}", status)).unwrap();
+
+    let mut dummy = DummyClient::new(client);
+    dummy.run();
+}
+
+fn edit(matches: &Matches, map_path: PathBuf, post_settings: PostSettings) {
+    let map = match Map::load(&map_path) {
+        Ok(map) => map,
+        Err(MapError::Io(_, _)) if !map_path.exists() => {
+            info!("creating new map {}", map_path.display());
+            Map::new(32, 32, 32, 32, Vec::new(), Vec::new())
+        }
+        Err(error) => panic!("Couldn't load map to edit: {}", error),
+    };
+
+    let test_play = TestPlaySettings {
+        input_map: load_input_map(matches),
+        gamepad: open_gamepad(matches),
+        post_settings: post_settings,
+    };
+
+    let mut editor = Editor::new(map_path, map, test_play, create_display());
+    editor.run();
+}
+
+/// Lists the servers on the local network and lets the user pick one on the terminal
+fn choose_server() -> Option<(String, u16)> {
+    println!("looking for servers...");
+    let servers = match discovery::discover(1000) {
+        Ok(servers) => servers,
+        Err(error) => {
+            println!("{}", error);
+            return None;
+        }
+    };
+    if servers.is_empty() {
+        println!("no servers found");
+        return None;
+    }
+
+    for (i, server) in servers.iter().enumerate() {
+        let announcement = &server.announcement;
+        println!("{:>2}. {:<24} {:<28} {:<8} {:>2}/{:<2} {:>4}ms  {}",
+                 i + 1, announcement.name, announcement.map_name, announcement.mode,
+                 announcement.num_players, announcement.max_players, server.ping_ms,
+                 server.address);
+        if !announcement.motd.is_empty() {
+            println!("    {}", announcement.motd);
+        }
+    }
+
+    loop {
+        print!("server to join (empty to quit): ");
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        io::stdin().read_line(&mut line).unwrap();
+        let line = line.trim();
+        if line.is_empty() {
+            return None;
+        }
+
+        match line.parse::<usize>() {
+            Ok(i) if i >= 1 && i <= servers.len() => {
+                let address = servers[i - 1].address;
+                return Some((address.ip().to_string(), address.port()));
+            }
+            _ => println!("please enter a number from 1 to {}", servers.len()),
+        }
+    }
+}
+
+/// Renders a snapshot into an image file offscreen, e.g. on machines without a GPU
+fn render_snapshot(matches: &Matches, snapshot_path: &str) {
+    let image_path = match matches.free.get(0) {
+        Some(image_path) => image_path.clone(),
+        None => {
+            println!("usage: catch_client --render-snapshot SNAPSHOT [--size WIDTHxHEIGHT] IMAGE");
+            process::exit(2);
+        }
+    };
+
+    let result = parse_size(matches.opt_str("size")).and_then(|(width, height)| {
+        Snapshot::load(snapshot_path).and_then(|snapshot| {
+            snapshot::render(&snapshot, width, height)
+        })
+    }).and_then(|image| {
+        image.save(&image_path).map_err(|error| {
+            format!("could not save image {}: {}", image_path, error)
+        })
+    });
+
+    match result {
+        Ok(()) => info!("rendered {} into {}", snapshot_path, image_path),
+        Err(error) => {
+            println!("{}", error);
+            process::exit(1);
+        }
+    }
+}
+
+/// Parses the size of rendered images, which is the same as the window's if not given
+fn parse_size(size: Option<String>) -> Result<(u32, u32), String> {
+    let size = match size {
+        Some(size) => size,
+        None => return Ok((640, 480)),
+    };
+
+    let parts = size.split('x').map(|n| n.parse::<u32>().ok()).collect::<Vec<_>>();
+    match (parts.len(), parts[0], parts.get(1).cloned().unwrap_or(None)) {
+        (2, Some(width), Some(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(format!("invalid size (WIDTHxHEIGHT): {}", size)),
+    }
+}
+
+fn create_display() -> Display {
+    glium::glutin::WindowBuilder::new()
+        .with_dimensions(640, 480)
+        .with_title(format!("Catching game"))
+        .build_glium()
+        .unwrap()
+}
+
+fn load_input_map(matches: &Matches) -> InputMap {
+    let bindings_path = match matches.opt_str("bindings") {
+        Some(path) => PathBuf::from(path),
+        None => env::home_dir().unwrap_or(PathBuf::from("."))
+                               .join(".catch").join("bindings.cfg"),
+    };
+    InputMap::load(bindings_path)
+}
+
+fn open_gamepad(matches: &Matches) -> Option<Gamepad> {
+    let gamepad_path = matches.opt_str("gamepad").unwrap_or("/dev/input/js0".to_string());
+    match Gamepad::open(&gamepad_path) {
+        Ok(gamepad) => {
+            info!("reading gamepad input from {}", gamepad_path);
+            Some(gamepad)
+        }
+        Err(error) => {
+            info!("{}, continuing without gamepad", error);
+            None
+        }
     }
 }
 
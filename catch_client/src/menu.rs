@@ -0,0 +1,340 @@
+use std::thread;
+use std::fs::File;
+use std::path::Path;
+
+use glium::{glutin, Display, Surface};
+use glium::glutin::{VirtualKeyCode, ElementState};
+use glium_text;
+
+use shared::player::PLAYER_COLORS;
+use shared::discovery::{self, DiscoveredServer};
+
+use catch_server::server;
+
+use draw::{draw_text, pixel_proj_mat};
+
+const MAX_NAME_LEN: usize = 16;
+const MAX_ADDRESS_LEN: usize = 64;
+
+// How long we wait for servers on the local network to reply
+const DISCOVERY_TIMEOUT_MS: u32 = 1000;
+
+/// What the player chose to do in the menu
+#[derive(Debug, Clone)]
+pub enum MenuAction {
+    Join(String, u16),
+    PlayLocal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Entry {
+    Name,
+    Color,
+    Address,
+    Join,
+    PlayLocal,
+    FindServers,
+    Quit,
+    Server(usize),
+}
+
+/// The screen that is shown before joining a game and after leaving one. It lets the player
+/// enter their name, pick a color and choose a server, and shows how connecting goes.
+pub struct Menu {
+    display: Display,
+
+    text_system: glium_text::TextSystem,
+    font: glium_text::FontTexture,
+
+    name: String,
+    color_index: usize,
+    address: String,
+
+    // Servers found on the local network, shown as additional entries
+    servers: Vec<DiscoveredServer>,
+
+    selected: usize,
+
+    // Progress or the last error, shown at the bottom
+    status: String,
+    status_is_error: bool,
+
+    quit: bool,
+    action: Option<MenuAction>,
+}
+
+impl Menu {
+    pub fn new(display: Display, name: String, address: String) -> Menu {
+        let text_system = glium_text::TextSystem::new(&display);
+        let font_file = File::open(&Path::new("data/ProggyClean.ttf"));
+        let font = glium_text::FontTexture::new(&display, font_file.unwrap(), 70).unwrap();
+
+        Menu {
+            display: display,
+            text_system: text_system,
+            font: font,
+            name: name,
+            color_index: 0,
+            address: address,
+            servers: Vec::new(),
+            selected: 0,
+            status: String::new(),
+            status_is_error: false,
+            quit: false,
+            action: None,
+        }
+    }
+
+    /// Shows the menu until the player chooses a game. Returns None if they want to quit.
+    pub fn run(&mut self) -> Option<MenuAction> {
+        self.quit = false;
+        self.action = None;
+
+        while !self.quit && self.action.is_none() {
+            self.read_input();
+            self.draw();
+
+            thread::sleep_ms(5);
+        }
+
+        self.action.take()
+    }
+
+    pub fn name(&self) -> String {
+        if self.name.trim().is_empty() {
+            "player".to_string()
+        } else {
+            self.name.trim().to_string()
+        }
+    }
+
+    pub fn color(&self) -> [f32; 3] {
+        PLAYER_COLORS[self.color_index].1
+    }
+
+    /// Shows what we are doing right now. Drawn immediately, since the caller is about to block.
+    pub fn show_progress(&mut self, status: &str) {
+        info!("{}", status);
+
+        self.status = status.to_string();
+        self.status_is_error = false;
+        self.draw();
+    }
+
+    pub fn show_error(&mut self, error: &str) {
+        warn!("{}", error);
+
+        self.status = error.to_string();
+        self.status_is_error = true;
+    }
+
+    pub fn clear_status(&mut self) {
+        self.status.clear();
+    }
+
+    /// Looks for servers on the local network and lists them in the menu
+    pub fn find_servers(&mut self) {
+        self.show_progress("looking for servers...");
+
+        match discovery::discover(DISCOVERY_TIMEOUT_MS) {
+            Ok(servers) => {
+                self.status = format!("found {} servers", servers.len());
+                self.servers = servers;
+            }
+            Err(error) => self.show_error(&error),
+        }
+    }
+
+    fn entries(&self) -> Vec<Entry> {
+        let mut entries = vec![Entry::Name, Entry::Color, Entry::Address, Entry::Join,
+                               Entry::PlayLocal, Entry::FindServers, Entry::Quit];
+        entries.extend((0..self.servers.len()).map(Entry::Server));
+        entries
+    }
+
+    fn read_input(&mut self) {
+        for event in self.display.poll_events() {
+            match event {
+                glutin::Event::Closed => {
+                    self.quit = true;
+                }
+                glutin::Event::KeyboardInput(ElementState::Pressed, _, Some(key)) => {
+                    self.on_key(key);
+                }
+                glutin::Event::ReceivedCharacter(c) => {
+                    self.on_character(c);
+                }
+                _ => ()
+            }
+        }
+    }
+
+    fn on_key(&mut self, key: VirtualKeyCode) {
+        let entries = self.entries();
+        let entry = entries[self.selected];
+
+        match key {
+            VirtualKeyCode::Escape => {
+                self.quit = true;
+            }
+            VirtualKeyCode::Up => {
+                self.selected = (self.selected + entries.len() - 1) % entries.len();
+            }
+            VirtualKeyCode::Down | VirtualKeyCode::Tab => {
+                self.selected = (self.selected + 1) % entries.len();
+            }
+            VirtualKeyCode::Left if entry == Entry::Color => {
+                self.color_index = (self.color_index + PLAYER_COLORS.len() - 1) %
+                                   PLAYER_COLORS.len();
+            }
+            VirtualKeyCode::Right if entry == Entry::Color => {
+                self.color_index = (self.color_index + 1) % PLAYER_COLORS.len();
+            }
+            VirtualKeyCode::Back => {
+                match entry {
+                    Entry::Name => { self.name.pop(); }
+                    Entry::Address => { self.address.pop(); }
+                    _ => ()
+                }
+            }
+            VirtualKeyCode::Return => {
+                self.activate(entry);
+            }
+            _ => ()
+        }
+    }
+
+    fn on_character(&mut self, c: char) {
+        if c.is_control() {
+            return;
+        }
+
+        match self.entries()[self.selected] {
+            Entry::Name if self.name.chars().count() < MAX_NAME_LEN => self.name.push(c),
+            Entry::Address if self.address.len() < MAX_ADDRESS_LEN => self.address.push(c),
+            _ => ()
+        }
+    }
+
+    fn activate(&mut self, entry: Entry) {
+        match entry {
+            Entry::Name | Entry::Color | Entry::Address => {
+                self.selected += 1;
+            }
+            Entry::Join => {
+                match parse_address(&self.address) {
+                    Ok((host, port)) => self.action = Some(MenuAction::Join(host, port)),
+                    Err(error) => self.show_error(&error),
+                }
+            }
+            Entry::PlayLocal => {
+                self.action = Some(MenuAction::PlayLocal);
+            }
+            Entry::FindServers => {
+                self.find_servers();
+            }
+            Entry::Quit => {
+                self.quit = true;
+            }
+            Entry::Server(i) => {
+                let address = self.servers[i].address;
+                self.address = address.to_string();
+                self.action = Some(MenuAction::Join(address.ip().to_string(), address.port()));
+            }
+        }
+    }
+
+    fn entry_text(&self, entry: Entry) -> String {
+        match entry {
+            Entry::Name => format!("Name:    {}", self.name),
+            Entry::Color => format!("Color:   < {} >", PLAYER_COLORS[self.color_index].0),
+            Entry::Address => format!("Server:  {}", self.address),
+            Entry::Join => "Join server".to_string(),
+            Entry::PlayLocal => "Play locally".to_string(),
+            Entry::FindServers => "Find servers on the local network".to_string(),
+            Entry::Quit => "Quit".to_string(),
+            Entry::Server(i) => {
+                let server = &self.servers[i];
+                let announcement = &server.announcement;
+                format!("{:<20} {:<16} {:>2}/{:<2} {:>4}ms",
+                        announcement.name, announcement.map_name, announcement.num_players,
+                        announcement.max_players, server.ping_ms)
+            }
+        }
+    }
+
+    fn draw(&mut self) {
+        let mut target = self.display.draw();
+        target.clear_color(0.05, 0.05, 0.1, 1.0);
+
+        let (w, h) = target.get_dimensions();
+        let proj_mat = pixel_proj_mat(w, h);
+        let x = 40.0;
+        let mut y = 40.0;
+
+        draw_text(&self.text_system, &self.font, (1.0, 1.0, 1.0, 1.0), x, y, "Catching game",
+                  &proj_mat, 24.0, &mut target);
+        y += 60.0;
+
+        let entries = self.entries();
+        for (i, &entry) in entries.iter().enumerate() {
+            if let Entry::Server(0) = entry {
+                y += 15.0;
+            }
+
+            let selected = i == self.selected;
+            let color = match entry {
+                Entry::Color => {
+                    let c = self.color();
+                    (c[0], c[1], c[2], 1.0)
+                }
+                _ if selected => (1.0, 1.0, 0.0, 1.0),
+                _ => (0.8, 0.8, 0.8, 1.0),
+            };
+
+            let mut text = self.entry_text(entry);
+            if selected {
+                text = format!("> {}", text);
+                if entry == Entry::Name || entry == Entry::Address {
+                    text.push('_');
+                }
+            } else {
+                text = format!("  {}", text);
+            }
+
+            draw_text(&self.text_system, &self.font, color, x, y, &text, &proj_mat, 12.0,
+                      &mut target);
+            y += 25.0;
+        }
+
+        if !self.status.is_empty() {
+            let color = if self.status_is_error {
+                (1.0, 0.3, 0.3, 1.0)
+            } else {
+                (1.0, 1.0, 0.0, 1.0)
+            };
+            draw_text(&self.text_system, &self.font, color, x, h as f32 - 40.0, &self.status,
+                      &proj_mat, 12.0, &mut target);
+        }
+
+        target.finish().unwrap();
+    }
+}
+
+/// Splits HOST:PORT, using the default port if none is given
+pub fn parse_address(s: &str) -> Result<(String, u16), String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("please enter a server address".to_string());
+    }
+
+    match s.rfind(':') {
+        Some(i) => {
+            let port = try!(s[i + 1..].parse().map_err(|_| {
+                format!("invalid port in server address {}", s)
+            }));
+            Ok((s[..i].to_string(), port))
+        }
+        None => Ok((s.to_string(), server::DEFAULT_PORT)),
+    }
+}
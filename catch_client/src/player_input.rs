@@ -1,52 +1,433 @@
-use glium::glutin::{ElementState, VirtualKeyCode};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use glium::glutin::VirtualKeyCode;
+
+use gamepad::GamepadEvent;
 
 pub use shared::player::{PlayerInput, PlayerInputKey};
 
+// Axis values beyond this count as a pressed direction. Sticks only move the player like
+// keys do, so this is the point of pressing rather than a dead zone around the center.
+const AXIS_DEADZONE: f32 = 0.5;
+
+pub const ALL_INPUT_KEYS: [PlayerInputKey; 12] = [
+    PlayerInputKey::Left,
+    PlayerInputKey::Right,
+    PlayerInputKey::Forward,
+    PlayerInputKey::Back,
+    PlayerInputKey::StrafeLeft,
+    PlayerInputKey::StrafeRight,
+    PlayerInputKey::Flip,
+    PlayerInputKey::Dash,
+    PlayerInputKey::Item1,
+    PlayerInputKey::Item2,
+    PlayerInputKey::Item3,
+    PlayerInputKey::Equip,
+];
+
+// Keys that can be used in bindings. Their names in the config file are the variant names.
+// Keys that the game handles itself are left out: Return opens the chat, L and P are debug
+// shortcuts for simulating a lag spike and printing the profile, F3 toggles the profiler
+// overlay, F9 takes a screenshot, F10 starts and stops recording and F12 saves a snapshot.
+const BINDABLE_KEYS: &'static [VirtualKeyCode] = &[
+    VirtualKeyCode::Key1, VirtualKeyCode::Key2, VirtualKeyCode::Key3, VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5, VirtualKeyCode::Key6, VirtualKeyCode::Key7, VirtualKeyCode::Key8,
+    VirtualKeyCode::Key9, VirtualKeyCode::Key0,
+    VirtualKeyCode::A, VirtualKeyCode::B, VirtualKeyCode::C, VirtualKeyCode::D,
+    VirtualKeyCode::E, VirtualKeyCode::F, VirtualKeyCode::G, VirtualKeyCode::H,
+    VirtualKeyCode::I, VirtualKeyCode::J, VirtualKeyCode::K, VirtualKeyCode::M,
+    VirtualKeyCode::N, VirtualKeyCode::O, VirtualKeyCode::Q, VirtualKeyCode::R,
+    VirtualKeyCode::S, VirtualKeyCode::T, VirtualKeyCode::U, VirtualKeyCode::V,
+    VirtualKeyCode::W, VirtualKeyCode::X, VirtualKeyCode::Y, VirtualKeyCode::Z,
+    VirtualKeyCode::F2, VirtualKeyCode::F4, VirtualKeyCode::F5, VirtualKeyCode::F6,
+    VirtualKeyCode::F7, VirtualKeyCode::F8, VirtualKeyCode::F11,
+    VirtualKeyCode::Left, VirtualKeyCode::Up, VirtualKeyCode::Right, VirtualKeyCode::Down,
+    VirtualKeyCode::Space, VirtualKeyCode::Back,
+    VirtualKeyCode::Insert, VirtualKeyCode::Delete, VirtualKeyCode::Home, VirtualKeyCode::End,
+    VirtualKeyCode::PageUp, VirtualKeyCode::PageDown,
+    VirtualKeyCode::LShift, VirtualKeyCode::RShift, VirtualKeyCode::LControl,
+    VirtualKeyCode::RControl, VirtualKeyCode::LAlt, VirtualKeyCode::RAlt,
+    VirtualKeyCode::Numpad0, VirtualKeyCode::Numpad1, VirtualKeyCode::Numpad2,
+    VirtualKeyCode::Numpad3, VirtualKeyCode::Numpad4, VirtualKeyCode::Numpad5,
+    VirtualKeyCode::Numpad6, VirtualKeyCode::Numpad7, VirtualKeyCode::Numpad8,
+    VirtualKeyCode::Numpad9,
+    VirtualKeyCode::Comma, VirtualKeyCode::Period, VirtualKeyCode::Minus,
+    VirtualKeyCode::Slash,
+];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AxisDirection {
+    Positive,
+    Negative,
+}
+
+/// Something that can be held down: a key, a gamepad button or an analog stick direction
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Trigger {
+    Key(VirtualKeyCode),
+    PadButton(u8),
+    PadAxis(u8, AxisDirection),
+}
+
+impl Trigger {
+    pub fn name(&self) -> String {
+        match *self {
+            Trigger::Key(key) => format!("{:?}", key),
+            Trigger::PadButton(n) => format!("Pad:Button{}", n),
+            Trigger::PadAxis(n, AxisDirection::Positive) => format!("Pad:Axis{}Pos", n),
+            Trigger::PadAxis(n, AxisDirection::Negative) => format!("Pad:Axis{}Neg", n),
+        }
+    }
+
+    /// Whether the trigger can be saved in bindings. Gamepad input always can, keys only if
+    /// they have a name in the config file.
+    pub fn is_bindable(&self) -> bool {
+        match *self {
+            Trigger::Key(key) => BINDABLE_KEYS.contains(&key),
+            Trigger::PadButton(_) | Trigger::PadAxis(_, _) => true,
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Trigger, String> {
+        if s.starts_with("Pad:Button") {
+            match s["Pad:Button".len()..].parse() {
+                Ok(n) => Ok(Trigger::PadButton(n)),
+                Err(_) => Err(format!("invalid gamepad button: {}", s)),
+            }
+        } else if s.starts_with("Pad:Axis") {
+            let rest = &s["Pad:Axis".len()..];
+            let (number, direction) =
+                if rest.ends_with("Pos") {
+                    (&rest[..rest.len()-3], AxisDirection::Positive)
+                } else if rest.ends_with("Neg") {
+                    (&rest[..rest.len()-3], AxisDirection::Negative)
+                } else {
+                    return Err(format!("gamepad axis needs a direction (Pos or Neg): {}", s));
+                };
+            match number.parse() {
+                Ok(n) => Ok(Trigger::PadAxis(n, direction)),
+                Err(_) => Err(format!("invalid gamepad axis: {}", s)),
+            }
+        } else {
+            for key in BINDABLE_KEYS.iter() {
+                if format!("{:?}", key) == s {
+                    return Ok(Trigger::Key(*key));
+                }
+            }
+            Err(format!("unknown key: {}", s))
+        }
+    }
+}
+
+/// A trigger, optionally together with a modifier that needs to be held at the same time
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Binding {
+    pub modifier: Option<Trigger>,
+    pub trigger: Trigger,
+}
+
+impl Binding {
+    pub fn new(trigger: Trigger) -> Binding {
+        Binding {
+            modifier: None,
+            trigger: trigger,
+        }
+    }
+
+    pub fn chord(modifier: Trigger, trigger: Trigger) -> Binding {
+        Binding {
+            modifier: Some(modifier),
+            trigger: trigger,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self.modifier {
+            Some(modifier) => format!("{}+{}", modifier.name(), self.trigger.name()),
+            None => self.trigger.name(),
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Binding, String> {
+        let parts = s.split('+').map(|part| part.trim()).collect::<Vec<_>>();
+        match parts.len() {
+            1 => Ok(Binding::new(try!(Trigger::parse(parts[0])))),
+            2 => Ok(Binding::chord(try!(Trigger::parse(parts[0])),
+                                   try!(Trigger::parse(parts[1])))),
+            _ => Err(format!("a binding can have at most one modifier: {}", s)),
+        }
+    }
+}
+
+pub fn input_key_name(key: PlayerInputKey) -> String {
+    format!("{:?}", key)
+}
+
+fn parse_input_key(s: &str) -> Result<PlayerInputKey, String> {
+    for &key in ALL_INPUT_KEYS.iter() {
+        if input_key_name(key) == s {
+            return Ok(key);
+        }
+    }
+    Err(format!("unknown input: {}", s))
+}
+
+/// Parses `inputs = binding` lines, as written by `InputMap::save`
+fn parse_bindings(text: &str, path: &Path) -> Result<Vec<(Vec<PlayerInputKey>, Binding)>, String> {
+    let mut bindings = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parts = line.split('=').collect::<Vec<_>>();
+        if parts.len() != 2 {
+            return Err(format!("{:?}:{}: expected `inputs = binding`", path, i + 1));
+        }
+
+        let mut keys = Vec::new();
+        for key in parts[0].split('+') {
+            match parse_input_key(key.trim()) {
+                Ok(key) => keys.push(key),
+                Err(error) => return Err(format!("{:?}:{}: {}", path, i + 1, error)),
+            }
+        }
+        let binding = match Binding::parse(parts[1].trim()) {
+            Ok(binding) => binding,
+            Err(error) => return Err(format!("{:?}:{}: {}", path, i + 1, error)),
+        };
+
+        bindings.push((keys, binding));
+    }
+
+    Ok(bindings)
+}
+
+/// Maps keys and gamepad input to player input. Each binding sets one or more input keys,
+/// so that e.g. Equip+Item1 can be put onto a single button.
 pub struct InputMap {
-    inputs: Vec<(VirtualKeyCode, PlayerInputKey)>,
+    bindings: Vec<(Vec<PlayerInputKey>, Binding)>,
+
+    // Where the bindings are saved to when they are changed
+    path: Option<PathBuf>,
+
+    // Triggers that are currently held down
+    held: Vec<Trigger>,
 }
 
 impl InputMap {
     pub fn new() -> InputMap {
-        let inputs = vec![
-            (VirtualKeyCode::Left, PlayerInputKey::Left),
-            (VirtualKeyCode::Right, PlayerInputKey::Right),
-            (VirtualKeyCode::Up, PlayerInputKey::Forward),
-            (VirtualKeyCode::Down, PlayerInputKey::Back),
-            (VirtualKeyCode::A, PlayerInputKey::StrafeLeft),
-            (VirtualKeyCode::D, PlayerInputKey::StrafeRight),
-            (VirtualKeyCode::LShift, PlayerInputKey::Flip),
-            (VirtualKeyCode::Space, PlayerInputKey::Dash),
-            (VirtualKeyCode::Q, PlayerInputKey::Item1),
-            (VirtualKeyCode::W, PlayerInputKey::Item2),
-            (VirtualKeyCode::E, PlayerInputKey::Item3),
-            (VirtualKeyCode::LControl, PlayerInputKey::Equip),
+        let key = |k| Binding::new(Trigger::Key(k));
+        let button = |n| Binding::new(Trigger::PadButton(n));
+        let axis = |n, d| Binding::new(Trigger::PadAxis(n, d));
+
+        let bindings = vec![
+            (vec![PlayerInputKey::Left], key(VirtualKeyCode::Left)),
+            (vec![PlayerInputKey::Right], key(VirtualKeyCode::Right)),
+            (vec![PlayerInputKey::Forward], key(VirtualKeyCode::Up)),
+            (vec![PlayerInputKey::Back], key(VirtualKeyCode::Down)),
+            (vec![PlayerInputKey::StrafeLeft], key(VirtualKeyCode::A)),
+            (vec![PlayerInputKey::StrafeRight], key(VirtualKeyCode::D)),
+            (vec![PlayerInputKey::Flip], key(VirtualKeyCode::LShift)),
+            (vec![PlayerInputKey::Dash], key(VirtualKeyCode::Space)),
+            (vec![PlayerInputKey::Item1], key(VirtualKeyCode::Q)),
+            (vec![PlayerInputKey::Item2], key(VirtualKeyCode::W)),
+            (vec![PlayerInputKey::Item3], key(VirtualKeyCode::E)),
+            (vec![PlayerInputKey::Equip], key(VirtualKeyCode::LControl)),
+
+            // Left stick turns and moves, as on a typical XInput-style pad
+            (vec![PlayerInputKey::Left], axis(0, AxisDirection::Negative)),
+            (vec![PlayerInputKey::Right], axis(0, AxisDirection::Positive)),
+            (vec![PlayerInputKey::Forward], axis(1, AxisDirection::Negative)),
+            (vec![PlayerInputKey::Back], axis(1, AxisDirection::Positive)),
+            (vec![PlayerInputKey::Dash], button(0)),
+            (vec![PlayerInputKey::Flip], button(1)),
+            (vec![PlayerInputKey::Item1], button(2)),
+            (vec![PlayerInputKey::Item2], button(3)),
+            (vec![PlayerInputKey::Item3], button(5)),
+            (vec![PlayerInputKey::Equip], button(4)),
         ];
 
         InputMap {
-            inputs: inputs
+            bindings: bindings,
+            path: None,
+            held: Vec::new(),
         }
     }
 
-    pub fn update_player_input(&self,
-                               state: ElementState, key: VirtualKeyCode,
-                               player_input: &mut PlayerInput) {
-        match state {
-            ElementState::Pressed => {
-                for &(k, input_key) in self.inputs.iter() {
-                    if k == key {
-                        player_input.set(input_key);
+    /// Loads bindings from a config file, falling back to the defaults if the file does not
+    /// exist yet. Changed bindings are saved back to the same file. A file that can't be read
+    /// is moved aside with a `.bak` extension, so that the defaults can be saved in its place.
+    pub fn load(path: PathBuf) -> InputMap {
+        let mut input_map = InputMap::new();
+
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(_) => {
+                info!("no bindings found at {:?}, using defaults", path);
+                input_map.path = Some(path);
+                input_map.save_or_warn();
+                return input_map;
+            }
+        };
+
+        let mut text = String::new();
+        let result = file.read_to_string(&mut text)
+                         .map_err(|error| format!("could not read bindings {:?}: {}", path, error))
+                         .and_then(|_| parse_bindings(&text, &path));
+        match result {
+            Ok(bindings) => {
+                info!("loaded {} bindings from {:?}", bindings.len(), path);
+                input_map.bindings = bindings;
+            }
+            Err(error) => {
+                let backup_path = path.with_extension("bak");
+                warn!("{}, using default bindings and moving the file to {:?}", error,
+                      backup_path);
+                match fs::rename(&path, &backup_path) {
+                    Ok(()) => {
+                        input_map.path = Some(path);
+                        input_map.save_or_warn();
+                        return input_map;
+                    }
+                    Err(error) => {
+                        warn!("could not move {:?} to {:?}: {}", path, backup_path, error)
                     }
                 }
             }
-            ElementState::Released => {
-                for &(k, input_key) in self.inputs.iter() {
-                    if k == key {
-                        player_input.unset(input_key);
+        }
+
+        input_map.path = Some(path);
+        input_map
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(dir) = path.parent() {
+            if let Err(error) = fs::create_dir_all(dir) {
+                return Err(format!("could not create {:?}: {}", dir, error));
+            }
+        }
+
+        let mut text = String::new();
+        text.push_str("# inputs = [modifier+]trigger\n");
+        text.push_str("# Triggers are key names, Pad:Button<n> or Pad:Axis<n>{Pos,Neg}\n");
+        for &(ref keys, binding) in self.bindings.iter() {
+            text.push_str(&format!("{} = {}\n", keys_name(keys), binding.name()));
+        }
+
+        let result = File::create(path).and_then(|mut file| file.write_all(text.as_bytes()));
+        match result {
+            Ok(()) => Ok(()),
+            Err(error) => Err(format!("could not write bindings {:?}: {}", path, error)),
+        }
+    }
+
+    pub fn bindings(&self) -> &[(Vec<PlayerInputKey>, Binding)] {
+        &self.bindings
+    }
+
+    /// Changes a binding and saves the bindings
+    pub fn set_binding(&mut self, index: usize, binding: Binding) {
+        self.bindings[index].1 = binding;
+        self.save_or_warn();
+    }
+
+    /// Adds a copy of a binding right after it, so that an input can get another binding
+    pub fn duplicate_binding(&mut self, index: usize) {
+        let binding = self.bindings[index].clone();
+        self.bindings.insert(index + 1, binding);
+        self.save_or_warn();
+    }
+
+    pub fn remove_binding(&mut self, index: usize) {
+        self.bindings.remove(index);
+        self.save_or_warn();
+    }
+
+    /// Name of the first binding that sets exactly the given key, for display purposes
+    pub fn binding_name(&self, key: PlayerInputKey) -> Option<String> {
+        self.bindings.iter()
+            .find(|&&(ref keys, _)| keys.len() == 1 && keys[0] == key)
+            .map(|&(_, binding)| binding.name())
+    }
+
+    pub fn is_held(&self, trigger: Trigger) -> bool {
+        self.held.iter().any(|&t| t == trigger)
+    }
+
+    pub fn on_trigger(&mut self, pressed: bool, trigger: Trigger) {
+        if pressed {
+            if !self.is_held(trigger) {
+                self.held.push(trigger);
+            }
+        } else {
+            self.held.retain(|&t| t != trigger);
+        }
+    }
+
+    /// Turns a gamepad event into the triggers that are pressed or released by it
+    pub fn gamepad_transitions(&self, event: GamepadEvent) -> Vec<(bool, Trigger)> {
+        match event {
+            GamepadEvent::Button(n, pressed) => {
+                let trigger = Trigger::PadButton(n);
+                if pressed != self.is_held(trigger) {
+                    vec![(pressed, trigger)]
+                } else {
+                    Vec::new()
+                }
+            }
+            GamepadEvent::Axis(n, value) => {
+                let positive = Trigger::PadAxis(n, AxisDirection::Positive);
+                let negative = Trigger::PadAxis(n, AxisDirection::Negative);
+
+                let mut transitions = Vec::new();
+                for &(trigger, pressed) in [(positive, value > AXIS_DEADZONE),
+                                            (negative, value < -AXIS_DEADZONE)].iter() {
+                    if pressed != self.is_held(trigger) {
+                        transitions.push((pressed, trigger));
                     }
                 }
+                transitions
             }
         }
     }
+
+    /// Releases everything, e.g. when the window loses focus
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+
+    pub fn player_input(&self) -> PlayerInput {
+        let mut player_input = PlayerInput::new();
+
+        for &(ref keys, binding) in self.bindings.iter() {
+            let modifier_held = binding.modifier.map(|m| self.is_held(m)).unwrap_or(true);
+            if modifier_held && self.is_held(binding.trigger) {
+                for &key in keys.iter() {
+                    player_input.set(key);
+                }
+            }
+        }
+
+        player_input
+    }
+
+    fn save_or_warn(&self) {
+        if let Err(error) = self.save() {
+            warn!("{}", error);
+        }
+    }
 }
 
+pub fn keys_name(keys: &[PlayerInputKey]) -> String {
+    keys.iter().map(|&key| input_key_name(key)).collect::<Vec<_>>().join("+")
+}
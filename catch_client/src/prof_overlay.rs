@@ -0,0 +1,186 @@
+//! Rolling graphs of frame time, tick buffering, network traffic and profiled scopes, drawn on
+//! top of the game
+
+use std::collections::VecDeque;
+
+use hprof::{self, ProfileNode};
+use na::{Vec2, Vec4};
+
+use draw::{FLAG_NONE, DrawList};
+
+// Number of frames shown in each graph, one pixel per frame
+const NUM_SAMPLES: usize = 200;
+pub const OVERLAY_WIDTH: f32 = NUM_SAMPLES as f32;
+
+const GRAPH_HEIGHT: f32 = 40.0;
+
+// Room for the label above each graph
+const LABEL_HEIGHT: f32 = 20.0;
+
+// Bandwidth is averaged over this window
+const BANDWIDTH_WINDOW_S: f32 = 1.0;
+
+// Time per frame is graphed for these scopes, summed over all places where they are entered
+const SCOPES: &'static [&'static str] = &["draw list", "draw particles", "interpolate",
+                                          "run tick"];
+
+struct Graph {
+    name: String,
+    unit: &'static str,
+    color: Vec4<f32>,
+    samples: VecDeque<f32>,
+}
+
+impl Graph {
+    fn new(name: &str, unit: &'static str, color: Vec4<f32>) -> Graph {
+        Graph {
+            name: name.to_string(),
+            unit: unit,
+            color: color,
+            samples: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, value: f32) {
+        if self.samples.len() == NUM_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(value);
+    }
+
+    fn max(&self) -> f32 {
+        self.samples.iter().fold(0.0, |max, &value| value.max(max))
+    }
+
+    fn label(&self) -> String {
+        format!("{}: {:.1} {} (max {:.1})", self.name,
+                self.samples.back().cloned().unwrap_or(0.0), self.unit, self.max())
+    }
+}
+
+/// What was measured in one frame
+pub struct FrameStats {
+    pub frame_s: f32,
+    pub queued_ticks: usize,
+    pub jitter_s: f32,
+
+    // Counted since connecting
+    pub num_received_bytes: usize,
+}
+
+pub struct ProfOverlay {
+    frame_time: Graph,
+    queued_ticks: Graph,
+    jitter: Graph,
+    bandwidth: Graph,
+    scopes: Vec<Graph>,
+
+    // Total time of each scope up to the last frame in seconds, as hprof only gives us totals
+    last_scope_totals_s: Vec<f64>,
+
+    // Frame durations and the bytes that were received in them
+    recent_bytes: VecDeque<(f32, usize)>,
+    last_num_received_bytes: usize,
+}
+
+impl ProfOverlay {
+    pub fn new() -> ProfOverlay {
+        let scope_color = Vec4::new(0.9, 0.6, 0.1, 1.0);
+
+        ProfOverlay {
+            frame_time: Graph::new("frame time", "ms", Vec4::new(0.2, 0.9, 0.2, 1.0)),
+            queued_ticks: Graph::new("queued ticks", "", Vec4::new(0.2, 0.6, 1.0, 1.0)),
+            jitter: Graph::new("tick jitter", "ms", Vec4::new(0.9, 0.2, 0.9, 1.0)),
+            bandwidth: Graph::new("received", "kb/s", Vec4::new(0.9, 0.9, 0.2, 1.0)),
+            scopes: SCOPES.iter().map(|name| Graph::new(name, "ms", scope_color)).collect(),
+            last_scope_totals_s: vec![0.0; SCOPES.len()],
+            recent_bytes: VecDeque::new(),
+            last_num_received_bytes: 0,
+        }
+    }
+
+    /// Should be called once per frame, after `hprof::end_frame`
+    pub fn record_frame(&mut self, stats: &FrameStats) {
+        self.frame_time.push(stats.frame_s * 1000.0);
+        self.queued_ticks.push(stats.queued_ticks as f32);
+        self.jitter.push(stats.jitter_s * 1000.0);
+
+        let new_bytes = stats.num_received_bytes - self.last_num_received_bytes;
+        self.last_num_received_bytes = stats.num_received_bytes;
+        self.recent_bytes.push_back((stats.frame_s, new_bytes));
+        let mut window_s = self.recent_bytes.iter().fold(0.0, |sum, &(s, _)| sum + s);
+        while self.recent_bytes.len() > 1 && window_s > BANDWIDTH_WINDOW_S {
+            window_s -= self.recent_bytes.pop_front().unwrap().0;
+        }
+        let window_bytes = self.recent_bytes.iter().fold(0, |sum, &(_, b)| sum + b);
+        self.bandwidth.push(if window_s > 0.0 {
+            window_bytes as f32 / window_s / 1000.0
+        } else {
+            0.0
+        });
+
+        let root = hprof::profiler().root();
+        for (i, &name) in SCOPES.iter().enumerate() {
+            let total_s = scope_total_s(&root, name);
+
+            // Be careful in case the profiler was reset
+            let frame_s = if total_s >= self.last_scope_totals_s[i] {
+                total_s - self.last_scope_totals_s[i]
+            } else {
+                total_s
+            };
+            self.last_scope_totals_s[i] = total_s;
+
+            self.scopes[i].push(frame_s as f32 * 1000.0);
+        }
+    }
+
+    /// Adds the graphs to a draw list in pixel coordinates with the y axis pointing down, so
+    /// that the top left corner is at the given position. Returns the labels that are to be
+    /// drawn as text.
+    pub fn draw(&self, x: f32, y: f32, draw_list: &mut DrawList) -> Vec<(f32, f32, String)> {
+        let mut labels = Vec::new();
+
+        let graphs = [&self.frame_time, &self.queued_ticks, &self.jitter, &self.bandwidth];
+        let mut y = y;
+        for graph in graphs.iter().map(|g| *g).chain(self.scopes.iter()) {
+            labels.push((x, y, graph.label()));
+            y += LABEL_HEIGHT;
+
+            let background = Vec4::new(0.15, 0.15, 0.15, 1.0);
+            let center = Vec2::new(x + NUM_SAMPLES as f32 / 2.0, y + GRAPH_HEIGHT / 2.0);
+            draw_list.push_rect(FLAG_NONE, background, NUM_SAMPLES as f32, GRAPH_HEIGHT,
+                                center, 0.0, 0.0);
+
+            // Newest sample on the right
+            let max = graph.max();
+            if max > 0.0 {
+                let offset = NUM_SAMPLES - graph.samples.len();
+                for (i, &value) in graph.samples.iter().enumerate() {
+                    let height = value / max * GRAPH_HEIGHT;
+                    let p = Vec2::new(x + (offset + i) as f32 + 0.5,
+                                      y + GRAPH_HEIGHT - height / 2.0);
+                    draw_list.push_rect(FLAG_NONE, graph.color, 1.0, height, p, 0.1, 0.0);
+                }
+            }
+
+            y += GRAPH_HEIGHT + 5.0;
+        }
+
+        labels
+    }
+}
+
+/// Total time spent in all scopes with the given name
+fn scope_total_s(node: &ProfileNode, name: &str) -> f64 {
+    let mut total_s = if node.name == name {
+        node.total_time.get() as f64 / 1000000000.0
+    } else {
+        0.0
+    };
+
+    for child in node.children.borrow().iter() {
+        total_s += scope_total_s(child, name);
+    }
+    total_s
+}
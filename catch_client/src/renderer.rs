@@ -0,0 +1,172 @@
+//! Draws the world of a `GameState`. Nothing here depends on a window, so the same code renders
+//! the game and offscreen snapshots.
+
+use hprof;
+use na::{Vec2, Mat4};
+
+use glium::{self, Surface};
+use glium::backend::Facade;
+
+use shared::Map;
+
+use state::GameState;
+use draw_map::DrawMap;
+use particles::Particles;
+use draw::{FLAG_BLUR, FLAG_NONE, DrawOp, DrawList, DrawDrawList, DrawContext, Post, PostSettings};
+
+pub const ZOOM: f32 = 3.0;
+
+struct DrawListsOp<'a, 'b: 'a> {
+    draw_draw_list: &'a mut DrawDrawList,
+    draw_list: &'a DrawList,
+    particles: &'a Particles,
+    draw_context: &'a DrawContext<'b>,
+}
+
+impl<'a, 'b> DrawOp for DrawListsOp<'a, 'b> {
+    type Result = ();
+
+    fn draw<S: Surface>(&mut self, target: &mut S) {
+        let _g = hprof::enter("draw list");
+        target.clear_color_and_depth((0.0, 0.0, 0.0, 1.0), -1.0);
+        self.draw_draw_list.draw(FLAG_BLUR, self.draw_list.clone(), &self.draw_context, target);
+        {
+            let _g = hprof::enter("draw particles");
+            self.particles.draw(&self.draw_context, target);
+        }
+    }
+}
+
+pub struct Renderer {
+    pub draw_draw_list: DrawDrawList,
+    pub particles: Particles,
+    draw_map: DrawMap,
+    draw_post: Post,
+}
+
+impl Renderer {
+    pub fn new<F: Facade + Clone>(facade: &F, map: &Map, post_settings: PostSettings)
+                                  -> Result<Renderer, String> {
+        Ok(Renderer {
+            draw_draw_list: try!(DrawDrawList::new(facade)),
+            particles: Particles::new(facade),
+            draw_map: try!(DrawMap::load(facade, map)),
+            draw_post: Post::new(post_settings, facade),
+        })
+    }
+
+    /// Replaces the map that is drawn, e.g. after the server reloaded it
+    pub fn load_map<F: Facade + Clone>(&mut self, facade: &F, map: &Map) -> Result<(), String> {
+        self.draw_map = try!(DrawMap::load(facade, map));
+        Ok(())
+    }
+
+    /// Moves the camera position so that nothing outside of the map is shown
+    pub fn clip_camera(map: &Map, cam_pos: Vec2<f32>, (width, height): (u32, u32))
+                       -> Vec2<f32> {
+        let half_width = width as f32 / 2.0 / ZOOM;
+        let half_height = height as f32 / 2.0 / ZOOM;
+        let mut cam_pos = cam_pos;
+
+        if cam_pos[0] < half_width {
+            cam_pos[0] = half_width;
+        } else if cam_pos[0] + half_width > map.width_pixels() as f32 {
+            cam_pos[0] = map.width_pixels() as f32 - half_width;
+        }
+        if cam_pos[1] < half_height {
+            cam_pos[1] = half_height;
+        } else if cam_pos[1] + half_height > map.height_pixels() as f32 {
+            cam_pos[1] = map.height_pixels() as f32 - half_height;
+        }
+
+        cam_pos
+    }
+
+    /// Context for drawing the world centered on the given camera position
+    pub fn draw_context(cam_pos: Vec2<f32>, (width, height): (u32, u32))
+                        -> DrawContext<'static> {
+        let draw_parameters = glium::DrawParameters {
+            depth: glium::Depth {
+                test: glium::draw_parameters::DepthTest::IfMore,
+                write: true,
+                .. Default::default()
+            },
+            .. Default::default()
+        };
+
+        let far = -10.0;
+        let near = 1.0;
+        DrawContext {
+            proj_mat: Mat4::new(2.0 / (width as f32), 0.0, 0.0, 0.0,
+                                0.0, 2.0 / (height as f32), 0.0, 0.0,
+                                0.0, 0.0, -2.0 / (far - near), -(far + near) / (far - near),
+                                0.0, 0.0, 0.0, 1.0),
+            camera_mat: Mat4::new(ZOOM, 0.0, 0.0, -cam_pos.x * ZOOM,
+                                  0.0, ZOOM, 0.0, -cam_pos.y * ZOOM,
+                                  0.0, 0.0, ZOOM, 0.0,
+                                  0.0, 0.0, 0.0, 1.0),
+            parameters: draw_parameters,
+        }
+    }
+
+    /// Draws the map, the entities and the particles onto a target that has been cleared.
+    /// `tick_time` is the current tick number plus the progress towards the next tick.
+    pub fn draw_world<S: Surface>(&mut self,
+                                  state: &mut GameState,
+                                  tick_time: f32,
+                                  simulation_time_s: f32,
+                                  draw_context: &DrawContext,
+                                  target: &mut S) {
+        {
+            let _g = hprof::enter("map");
+            self.draw_map.draw(draw_context, target);
+        }
+
+        let mut draw_list = DrawList::new();
+        {
+            let _g = hprof::enter("entities");
+
+            // TODO: particle emitters
+            state.world.systems.draw_player_system
+                .spawn_particles(&mut state.world.data, simulation_time_s, &mut self.particles);
+            state.world.systems.draw_item_system
+                .spawn_particles(&mut state.world.data, simulation_time_s, &mut self.particles);
+
+            state.world.systems.draw_player_system
+                .draw(&mut state.world.data, &mut draw_list);
+            state.world.systems.draw_wall_system
+                .draw(&mut state.world.data, &mut draw_list);
+            state.world.systems.draw_projectile_system
+                .draw(&mut state.world.data, &mut draw_list);
+            state.world.systems.draw_bouncy_enemy_system
+                .draw(&mut state.world.data, &mut draw_list);
+            state.world.systems.draw_item_system
+                .draw(&mut state.world.data, &mut draw_list);
+
+            let ticks_per_second = state.game_info.ticks_per_second as f32;
+            state.world.systems.draw_item_spawn_system
+                .draw(&mut state.world.data, tick_time, ticks_per_second, &mut draw_list);
+        }
+        {
+            let _g = hprof::enter("update particles");
+            self.particles.update(simulation_time_s);
+        }
+
+        {
+            let mut draw_lists_op = DrawListsOp {
+                draw_draw_list: &mut self.draw_draw_list,
+                draw_list: &draw_list,
+                particles: &self.particles,
+                draw_context: draw_context,
+            };
+            self.draw_post.draw(target, &mut draw_lists_op);
+        }
+
+        target.clear_depth(-1.0);
+        self.draw_draw_list.draw(FLAG_NONE, draw_list, draw_context, target);
+        {
+            let _g = hprof::enter("draw particles");
+            self.particles.draw(draw_context, target);
+        }
+    }
+}
@@ -1,12 +1,17 @@
+use std::collections::HashMap;
+
 use ecs::ServiceManager;
 
-use shared::GameEvent;
+use shared::{GameEvent, PlayerId};
 use shared::services::HasEvents;
 use shared::entities::NetEntities;
 
 #[derive(Default)]
 pub struct Services {
     pub net_entities: NetEntities,
+
+    // Colors chosen by the players, kept up to date by the game state
+    pub player_colors: HashMap<PlayerId, [f32; 3]>,
 }
 
 impl ServiceManager for Services {}
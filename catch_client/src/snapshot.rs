@@ -0,0 +1,193 @@
+//! A single tick saved to a file with everything that is needed to draw it again, and rendering
+//! of such snapshots into images without a window
+
+use std::fs::File;
+use std::path::Path;
+
+use bincode::SizeLimit;
+use bincode::rustc_serialize::{encode_into, decode_from};
+use image::{self, ImageBuffer, Rgba};
+use na::Vec2;
+
+use glium::{glutin, DisplayBuild, Surface};
+use glium::texture::{Texture2d, RawImage2d, DepthFormat, UncompressedFloatFormat,
+                     MipmapsOption};
+use glium::framebuffer::{SimpleFrameBuffer, DepthRenderBuffer};
+
+use shared::{GameInfo, GameEvent, PlayerId, EntityId, Map};
+use shared::tick::Tick;
+
+use state::GameState;
+use renderer::Renderer;
+use draw::PostSettings;
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct Snapshot {
+    pub game_info: GameInfo,
+    pub my_id: PlayerId,
+
+    // The map as it was shown, so that the snapshot still renders the same after the map file
+    // has changed
+    pub map_text: String,
+
+    // The events of the tick are replaced by ones that create all players and entities
+    pub tick: Tick,
+}
+
+impl Snapshot {
+    pub fn new(state: &mut GameState, my_id: PlayerId, tick: &Tick) -> Snapshot {
+        let mut players = state.players().iter()
+                               .map(|(&id, info)| (id, info.clone()))
+                               .collect::<Vec<_>>();
+        players.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut entities = state.world.services.net_entities.iter()
+                                .map(|(&id, &entity)| (id, entity))
+                                .collect::<Vec<(EntityId, _)>>();
+        entities.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut events = vec![GameEvent::InitialPlayerList(players)];
+        for (id, entity) in entities {
+            let (type_id, owner) = state.world.with_entity_data(&entity, |e, c| {
+                (c.net_entity[e].type_id, c.net_entity[e].owner)
+            }).unwrap();
+            events.push(GameEvent::CreateEntity(id, type_id, owner));
+        }
+
+        Snapshot {
+            game_info: state.game_info.clone(),
+            my_id: my_id,
+            map_text: state.map.to_text(&state.game_info.map_name),
+            tick: Tick {
+                tick_number: tick.tick_number,
+                events: events,
+                state: tick.state.clone(),
+            },
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot, String> {
+        let path = path.as_ref();
+        let mut file = try!(File::open(path).map_err(|error| {
+            format!("could not open snapshot {:?}: {}", path, error)
+        }));
+        decode_from(&mut file, SizeLimit::Infinite).map_err(|error| {
+            format!("could not read snapshot {:?}: {}", path, error)
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let mut file = try!(File::create(path).map_err(|error| {
+            format!("could not create snapshot {:?}: {}", path, error)
+        }));
+        encode_into(self, &mut file, SizeLimit::Infinite).map_err(|error| {
+            format!("could not write snapshot {:?}: {}", path, error)
+        })
+    }
+
+    /// Builds the game state as it was when the snapshot was taken
+    pub fn game_state(&self) -> Result<GameState, String> {
+        let map = try!(Map::parse(&self.map_text, &self.game_info.map_name)
+                           .map_err(|error| format!("could not load snapshot map: {}", error)));
+
+        let mut state = GameState::with_map(self.my_id, &self.game_info, map);
+        state.run_tick(&self.tick);
+        state.load_interp_tick_state(&self.tick, &self.tick);
+        state.world.systems.interpolation_system.interpolate(0.0, &mut state.world.data);
+        Ok(state)
+    }
+}
+
+/// Renders the world of a snapshot, as seen by the player who took it, into an image. This
+/// uses a headless GL context instead of a window, so it also works with a software
+/// implementation such as OSMesa on machines without a GPU.
+pub fn render(snapshot: &Snapshot, width: u32, height: u32)
+              -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, String> {
+    let facade = try!(glutin::HeadlessRendererBuilder::new(width, height).build_glium()
+                          .map_err(|error| {
+                              format!("could not create headless GL context: {:?}", error)
+                          }));
+
+    let mut state = try!(snapshot.game_state());
+    let mut renderer = try!(Renderer::new(&facade, &state.map, PostSettings { blur: false }));
+
+    let color = try!(Texture2d::empty_with_format(&facade, UncompressedFloatFormat::U8U8U8U8,
+                                                  MipmapsOption::NoMipmap, width, height)
+                         .map_err(|error| format!("could not create texture: {:?}", error)));
+    let depth = try!(DepthRenderBuffer::new(&facade, DepthFormat::I24, width, height)
+                         .map_err(|error| format!("could not create depth buffer: {:?}", error)));
+
+    {
+        let mut target = try!(SimpleFrameBuffer::with_depth_buffer(&facade, &color, &depth)
+                                  .map_err(|error| {
+                                      format!("could not create framebuffer: {:?}", error)
+                                  }));
+        target.clear_color_and_depth((0.1, 0.1, 0.1, 1.0), -1.0);
+
+        // Look at the middle of the map if the player has no entity
+        let map_center = Vec2::new(state.map.width_pixels() as f32 / 2.0,
+                                   state.map.height_pixels() as f32 / 2.0);
+        let player_entity = state.world.services.net_entities.get_player_entity(snapshot.my_id);
+        let cam_pos = player_entity.and_then(|entity| {
+            state.world.with_entity_data(&entity, |e, c| c.position[e].p)
+        }).unwrap_or(map_center);
+        let cam_pos = Renderer::clip_camera(&state.map, cam_pos, (width, height));
+
+        let draw_context = Renderer::draw_context(cam_pos, (width, height));
+        renderer.draw_world(&mut state, snapshot.tick.tick_number as f32, 0.0, &draw_context,
+                            &mut target);
+    }
+
+    let pixels: RawImage2d<u8> = color.read();
+    let image = try!(ImageBuffer::from_raw(width, height, pixels.data.into_owned())
+                         .ok_or("rendered image has the wrong size".to_string()));
+
+    // OpenGL stores the bottom row first
+    Ok(image::imageops::flip_vertical(&image))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::Path;
+
+    use image;
+
+    use super::{Snapshot, render};
+
+    // Rendering may differ slightly between GL implementations
+    const MAX_CHANNEL_DIFF: i32 = 8;
+
+    /// Renders `catch_client/tests/data/basic.snapshot`, which was taken with F12, and compares
+    /// it with `basic.png` next to it. After intentional changes to drawing, run the test with
+    /// `CATCH_BLESS_SNAPSHOTS=1` to write a new reference image.
+    #[test]
+    fn render_matches_reference() {
+        // The map and textures are loaded relative to the root of the repository
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+        env::set_current_dir(&root).unwrap();
+
+        let snapshot = Snapshot::load("catch_client/tests/data/basic.snapshot").unwrap();
+        let image = render(&snapshot, 320, 240).unwrap();
+
+        let reference_path = "catch_client/tests/data/basic.png";
+        if env::var("CATCH_BLESS_SNAPSHOTS").is_ok() {
+            image.save(reference_path).unwrap();
+            return;
+        }
+
+        let reference = image::open(reference_path).unwrap().to_rgba();
+        assert_eq!(image.dimensions(), reference.dimensions());
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let reference_pixel = reference.get_pixel(x, y);
+            for channel in 0..4 {
+                let diff = pixel.data[channel] as i32 - reference_pixel.data[channel] as i32;
+                assert!(diff.abs() <= MAX_CHANNEL_DIFF,
+                        "pixel ({}, {}) is {:?} instead of {:?}",
+                        x, y, pixel.data, reference_pixel.data);
+            }
+        }
+    }
+}
@@ -19,6 +19,11 @@ pub struct GameState {
 
 impl GameState {
     pub fn new(my_id: PlayerId, game_info: &GameInfo) -> GameState {
+        GameState::with_map(my_id, game_info, game_info.load_map().unwrap())
+    }
+
+    /// Creates the state with a map that has already been loaded
+    pub fn with_map(my_id: PlayerId, game_info: &GameInfo, map: Map) -> GameState {
         let mut world = ecs::World::<Systems>::new();
         world.systems.net_entity_system.init(
             NetEntitySystem::new(aspect!(<Components> all: [net_entity]),
@@ -26,7 +31,7 @@ impl GameState {
 
         GameState {
             game_info: game_info.clone(),
-            map: Map::load(&game_info.map_name).unwrap(),
+            map: map,
             world: world,
             tick_number: None,
             players: HashMap::new(),
@@ -83,11 +88,13 @@ impl GameState {
 
     fn add_player(&mut self, id: PlayerId, info: PlayerInfo) {
         assert!(self.players.get(&id).is_none());
+        self.world.services.player_colors.insert(id, info.color);
         self.players.insert(id, info);
     }
 
     fn remove_player(&mut self, id: PlayerId) {
         assert!(self.players.get(&id).is_some());
+        self.world.services.player_colors.remove(&id);
         self.players.remove(&id);
     }
 
@@ -118,6 +125,14 @@ impl GameState {
                     self.players.get_mut(&id).unwrap().stats = stats;
                 }
             }
+            GameEvent::UpdateBalance(balance) => {
+                info!("server changed the balance table");
+                self.game_info.balance = balance;
+            }
+            GameEvent::UpdateTuning(tuning) => {
+                info!("server changed the tuning");
+                self.game_info.tuning = tuning;
+            }
             _ => ()
         }
     }
@@ -0,0 +1,69 @@
+use std::f32;
+
+use ecs::{Aspect, System, DataHelper, Process};
+use na::{Vec2, Vec4};
+
+use shared::util::CachedAspect;
+
+use components::Components;
+use services::Services;
+use draw::{FLAG_NONE, DrawList};
+
+const RING_RADIUS: f32 = 8.0;
+const RING_SEGMENTS: usize = 24;
+
+/// Shows a ring around empty item spawns that shrinks until the next item is spawned
+pub struct DrawItemSpawnSystem {
+    aspect: CachedAspect<Components>,
+}
+
+impl DrawItemSpawnSystem {
+    pub fn new(aspect: Aspect<Components>) -> DrawItemSpawnSystem {
+        DrawItemSpawnSystem {
+            aspect: CachedAspect::new(aspect),
+        }
+    }
+
+    /// `tick_time` is the current tick number including the progress towards the next tick
+    pub fn draw(&mut self, data: &mut DataHelper<Components, Services>, tick_time: f32,
+                ticks_per_second: f32, draw_list: &mut DrawList) {
+        for entity in self.aspect.iter() {
+            let state = data.item_spawn_state[entity].clone();
+            let respawn_tick = match state.respawn_tick {
+                Some(respawn_tick) => respawn_tick,
+                None => continue,
+            };
+            if state.respawn_time_s <= 0.0 {
+                continue;
+            }
+
+            let remaining_s = (respawn_tick as f32 - tick_time) / ticks_per_second;
+            let fraction = (remaining_s / state.respawn_time_s).max(0.0).min(1.0);
+            let num_segments = (fraction * RING_SEGMENTS as f32).ceil() as usize;
+
+            let p = data.position[entity].p;
+            let point = |i: usize| {
+                // Start at the top and go clockwise
+                let angle = f32::consts::PI / 2.0 -
+                            i as f32 / RING_SEGMENTS as f32 * f32::consts::PI * 2.0;
+                p + Vec2::new(angle.cos(), angle.sin()) * RING_RADIUS
+            };
+
+            for i in 0..RING_SEGMENTS {
+                let color = if i < num_segments {
+                    Vec4::new(0.1, 0.9, 0.1, 1.0)
+                } else {
+                    Vec4::new(0.25, 0.25, 0.25, 1.0)
+                };
+                draw_list.push_line(FLAG_NONE, color, 1.0, point(i), point(i + 1), 0.0);
+            }
+        }
+    }
+}
+
+impl_cached_system!(Components, Services, DrawItemSpawnSystem, aspect);
+
+impl Process for DrawItemSpawnSystem {
+    fn process(&mut self, _: &mut DataHelper<Components, Services>) {
+    }
+}
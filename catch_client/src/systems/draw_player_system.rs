@@ -84,7 +84,10 @@ impl DrawPlayerSystem {
                 } else if data.player_state[entity].is_catcher {
                     [0.0, 1.0, 0.0, 1.0]
                 } else {
-                    [0.0f32, 0.0, 1.0, 1.0]
+                    let owner = data.net_entity[entity].owner;
+                    let c = data.services.player_colors.get(&owner).cloned()
+                                .unwrap_or([0.0, 0.0, 1.0]);
+                    [c[0], c[1], c[2], 1.0]
                 };
             data.draw_player[entity].color = color;
 
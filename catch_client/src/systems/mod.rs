@@ -5,6 +5,7 @@ pub mod draw_bouncy_enemy_system;
 pub mod draw_item_system;
 pub mod draw_projectile_system;
 pub mod draw_wall_system;
+pub mod draw_item_spawn_system;
 
 use ecs::system::LazySystem;
 
@@ -17,6 +18,7 @@ pub use self::draw_bouncy_enemy_system::DrawBouncyEnemySystem;
 pub use self::draw_item_system::DrawItemSystem;
 pub use self::draw_projectile_system::DrawProjectileSystem;
 pub use self::draw_wall_system::DrawWallSystem;
+pub use self::draw_item_spawn_system::DrawItemSpawnSystem;
 
 systems! {
     struct Systems<Components, Services> {
@@ -27,7 +29,7 @@ systems! {
                 aspect!(<Components> all: [position, interp_position]),
                 aspect!(<Components> all: [orientation, interp_orientation])),
             draw_player_system: DrawPlayerSystem = DrawPlayerSystem::new(
-                aspect!(<Components> all: [draw_player])),
+                aspect!(<Components> all: [draw_player, net_entity])),
             draw_bouncy_enemy_system: DrawBouncyEnemySystem = DrawBouncyEnemySystem::new(
                 aspect!(<Components> all: [draw_bouncy_enemy])),
             draw_item_system: DrawItemSystem = DrawItemSystem::new(
@@ -36,6 +38,8 @@ systems! {
                 aspect!(<Components> all: [draw_projectile])),
             draw_wall_system: DrawWallSystem = DrawWallSystem::new(
                 aspect!(<Components> all: [draw_wall])),
+            draw_item_spawn_system: DrawItemSpawnSystem = DrawItemSpawnSystem::new(
+                aspect!(<Components> all: [draw_item_spawn])),
         }
     }
 }
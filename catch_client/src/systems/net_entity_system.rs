@@ -70,14 +70,15 @@ impl NetEntitySystem {
             }
 
             // Add other shared components
-            let type_name = &self.entity_types[entity_type_id as usize].0;
-            shared::entities::build_shared(type_name, entity, data);
+            let entity_type = &self.entity_types[entity_type_id as usize].1;
+            shared::entities::build_shared(entity_type, entity, data);
 
             // Add client-side components to the entity (e.g. for drawing)
-            entities::build_client(type_name, entity, data);
+            entities::build_client(entity_type, entity, data);
         });
 
-        data.services.net_entities.on_build(entity_id, entity_type_id, owner, entity);
+        let entity_type = &self.entity_types[entity_type_id as usize].1;
+        data.services.net_entities.on_build(entity_id, entity_type, owner, entity);
 
         entity
     }
@@ -0,0 +1,118 @@
+//! Compares assembling and encoding the ticks of 64 clients on the server thread with doing it
+//! on a pool of encoder threads. The players press random keys, so that they move around and
+//! use their items as in a real game. Run with
+//! `cargo run --release --example tick_encoding`.
+
+extern crate rand;
+extern crate time;
+extern crate catch_shared as shared;
+extern crate catch_server;
+
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use shared::{PlayerId, PlayerInfo, PlayerInput};
+use shared::net::TimedPlayerInput;
+use shared::player::{PLAYER_COLORS, NUM_INPUT_KEYS};
+use catch_server::server;
+use catch_server::state::GameState;
+use catch_server::snapshot::WorldSnapshot;
+use catch_server::encoder::{EncoderPool, EncodeJob};
+
+const NUM_CLIENTS: PlayerId = 64;
+const NUM_WARMUP_TICKS: usize = 30;
+const NUM_ITERATIONS: usize = 200;
+
+// Chance of each key being held down during a tick is one in this
+const KEY_ODDS: u32 = 4;
+
+/// Gives every player an input with random keys held down for the next tick
+fn random_inputs(game_state: &mut GameState, rng: &mut XorShiftRng, tick_dur_s: f32) {
+    for player_id in 1..NUM_CLIENTS + 1 {
+        let mut input = PlayerInput::new();
+        for i in 0..NUM_INPUT_KEYS {
+            input.pressed[i] = rng.gen_weighted_bool(KEY_ODDS);
+        }
+
+        game_state.on_player_input(player_id, &TimedPlayerInput {
+            duration_s: tick_dur_s,
+            input: input,
+        });
+    }
+}
+
+fn run_tick(game_state: &mut GameState, rng: &mut XorShiftRng, tick_dur_s: f32) {
+    random_inputs(game_state, rng, tick_dur_s);
+    game_state.tick();
+    game_state.clear_player_events();
+}
+
+/// Snapshots of two consecutive ticks of a game with all clients playing
+fn setup() -> (Arc<WorldSnapshot>, Arc<WorldSnapshot>) {
+    // The data files are loaded relative to the repository root
+    if !Path::new("data").exists() {
+        env::set_current_dir("..").unwrap();
+    }
+
+    let game_info = server::game_info(server::DEFAULT_MAP).unwrap();
+    let tick_dur_s = 1.0 / game_info.ticks_per_second as f32;
+    let mut game_state = GameState::new(&game_info, 1);
+    let mut rng = XorShiftRng::from_seed([0x2545f491, 1, 2, 3]);
+
+    for player_id in 1..NUM_CLIENTS + 1 {
+        let color = PLAYER_COLORS[player_id as usize % PLAYER_COLORS.len()].1;
+        game_state.add_player(player_id, PlayerInfo::new(format!("bot{}", player_id), color));
+    }
+    for _ in 0..NUM_WARMUP_TICKS {
+        run_tick(&mut game_state, &mut rng, tick_dur_s);
+    }
+
+    let last_snapshot = Arc::new(game_state.take_snapshot());
+
+    run_tick(&mut game_state, &mut rng, tick_dur_s);
+
+    (last_snapshot, Arc::new(game_state.take_snapshot()))
+}
+
+/// Returns the average time in milliseconds for encoding the ticks of all clients
+fn encode_all(last_snapshot: &Arc<WorldSnapshot>, snapshot: &Arc<WorldSnapshot>,
+              num_threads: usize) -> f64 {
+    let pool = EncoderPool::new(num_threads);
+
+    // Summing up the sizes keeps the encoding from being optimized away
+    let mut num_bytes = 0;
+    let start_ns = time::precise_time_ns();
+
+    for _ in 0..NUM_ITERATIONS {
+        // Every client has received the previous tick, so we are measuring delta encoding
+        let jobs = (1..NUM_CLIENTS + 1).map(|player_id| {
+            EncodeJob {
+                player_id: player_id,
+                snapshot: snapshot.clone(),
+                events: Vec::new(),
+                last_snapshot: Some(last_snapshot.clone()),
+            }
+        }).collect();
+        num_bytes += pool.encode_all(jobs).iter().fold(0, |sum, tick| sum + tick.data.len());
+    }
+
+    let duration_ns = time::precise_time_ns() - start_ns;
+    assert!(num_bytes > 0);
+    duration_ns as f64 / NUM_ITERATIONS as f64 / 1000000.0
+}
+
+fn main() {
+    let (last_snapshot, snapshot) = setup();
+
+    for &num_threads in &[0, 2, 4, 8] {
+        let ms = encode_all(&last_snapshot, &snapshot, num_threads);
+        if num_threads == 0 {
+            println!("{} clients inline: {:.3}ms", NUM_CLIENTS, ms);
+        } else {
+            println!("{} clients on {} threads: {:.3}ms", NUM_CLIENTS, num_threads, ms);
+        }
+    }
+}
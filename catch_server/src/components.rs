@@ -6,9 +6,10 @@ use shared::player::Item;
 use shared::net_components::ComponentType;
 use shared::components::{HasPosition, HasOrientation, HasLinearVelocity, HasShape, HasPlayerState,
                          HasFullPlayerState, HasWallPosition, HasAngularVelocity, HasWall,
-                         HasProjectile};
+                         HasProjectile, HasItemSpawnState};
 pub use shared::components::{NetEntity, Position, Orientation, LinearVelocity, Shape, PlayerState,
-                             Projectile, FullPlayerState, AngularVelocity, Wall, WallPosition};
+                             Projectile, FullPlayerState, AngularVelocity, Wall, WallPosition,
+                             ItemSpawnState};
 
 /// Server-side information about net entities
 #[derive(Default)]
@@ -30,6 +31,10 @@ impl ServerNetEntity {
 #[derive(Default)]
 pub struct PlayerController {
     pub inputs: Vec<TimedPlayerInput>,
+
+    // Item that the player left behind when swapping items. It is not picked up again until the
+    // player has stepped off it.
+    pub dropped_item: Option<ecs::Entity>,
 }
 
 /// Server-side information about bouncy enemies
@@ -49,7 +54,10 @@ pub struct Rotate;
 #[derive(Default)]
 pub struct ItemSpawn {
     pub spawned_entity: Option<ecs::Entity>,
-    pub cooldown_s: Option<f32>,
+
+    // Names and weights of the items to choose from when spawning. If empty, the spawn weights
+    // of the balance table are used.
+    pub items: Vec<(String, f32)>,
 }
 
 components! {
@@ -65,6 +73,7 @@ components! {
         #[hot] player_state: PlayerState,
         #[hot] full_player_state: FullPlayerState,
         #[hot] wall_position: WallPosition,
+        #[hot] item_spawn_state: ItemSpawnState,
         #[hot] wall: Wall,
 
         #[cold] angular_velocity: AngularVelocity,
@@ -150,6 +159,15 @@ impl HasWallPosition for Components {
     }
 }
 
+impl HasItemSpawnState for Components {
+    fn item_spawn_state(&self) -> &ComponentList<Components, ItemSpawnState> {
+        &self.item_spawn_state
+    }
+    fn item_spawn_state_mut(&mut self) -> &mut ComponentList<Components, ItemSpawnState> {
+        &mut self.item_spawn_state
+    }
+}
+
 impl HasWall for Components {
     fn wall(&self) -> &ComponentList<Components, Wall> {
         &self.wall
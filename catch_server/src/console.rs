@@ -0,0 +1,51 @@
+use std::io::{self, BufRead};
+use std::thread;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+
+/// Reads admin commands from stdin without blocking the server
+pub struct Console {
+    receiver: Receiver<String>,
+}
+
+impl Console {
+    pub fn start() -> Console {
+        let (sender, receiver) = channel();
+
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            for line in stdin.lock().lines() {
+                match line {
+                    Ok(line) => {
+                        if sender.send(line).is_err() {
+                            // The server is gone
+                            break;
+                        }
+                    }
+                    Err(error) => {
+                        warn!("could not read from console: {}", error);
+                        break;
+                    }
+                }
+            }
+        });
+
+        Console {
+            receiver: receiver,
+        }
+    }
+
+    /// Returns the next command that was entered, if any
+    pub fn poll(&mut self) -> Option<String> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(line) => {
+                    let line = line.trim();
+                    if !line.is_empty() {
+                        return Some(line.to_string());
+                    }
+                }
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => return None,
+            }
+        }
+    }
+}
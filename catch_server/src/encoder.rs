@@ -0,0 +1,126 @@
+use std::thread::{self, JoinHandle};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender, Receiver};
+
+use bincode::SizeLimit;
+use bincode::rustc_serialize::encode_into;
+
+use shared::{PlayerId, GameEvent};
+
+use snapshot::WorldSnapshot;
+
+/// Number of threads that encode ticks, if not configured otherwise
+pub const DEFAULT_ENCODER_THREADS: usize = 4;
+
+/// Everything that is needed to assemble and encode the tick of one client
+pub struct EncodeJob {
+    pub player_id: PlayerId,
+    pub snapshot: Arc<WorldSnapshot>,
+    pub events: Vec<GameEvent>,
+
+    // The last snapshot that was sent to the client, if any, so that we can do delta encoding
+    pub last_snapshot: Option<Arc<WorldSnapshot>>,
+}
+
+pub struct EncodedTick {
+    pub player_id: PlayerId,
+    pub data: Vec<u8>,
+}
+
+/// Assembles the tick of one client from the snapshot and encodes it
+pub fn encode(job: EncodeJob) -> EncodedTick {
+    let mut data = Vec::new();
+
+    let last_tick_number = job.last_snapshot.as_ref().map(|snapshot| snapshot.tick_number);
+    if let Some(last_tick_number) = last_tick_number {
+        trace!("encoding delta from {} to {} for {}", job.snapshot.tick_number,
+               last_tick_number, job.player_id);
+    }
+    encode_into(&last_tick_number, &mut data, SizeLimit::Infinite).unwrap();
+
+    job.snapshot.encode_tick(job.player_id, &job.events,
+                             job.last_snapshot.as_ref().map(|snapshot| &**snapshot), &mut data);
+
+    EncodedTick {
+        player_id: job.player_id,
+        data: data,
+    }
+}
+
+/// Encodes the ticks of many clients in parallel on a fixed set of threads
+pub struct EncoderPool {
+    // None only while dropping
+    job_sender: Option<Sender<(usize, EncodeJob)>>,
+    result_receiver: Receiver<(usize, EncodedTick)>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl EncoderPool {
+    /// With zero threads, everything is encoded on the calling thread
+    pub fn new(num_threads: usize) -> EncoderPool {
+        let (job_sender, job_receiver) = channel::<(usize, EncodeJob)>();
+        let (result_sender, result_receiver) = channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let threads = (0..num_threads).map(|_| {
+            let job_receiver = job_receiver.clone();
+            let result_sender = result_sender.clone();
+
+            thread::spawn(move || {
+                loop {
+                    // The lock is released before encoding, so other threads can take jobs
+                    let job = job_receiver.lock().unwrap().recv();
+                    let (index, job) = match job {
+                        Ok(job) => job,
+                        Err(_) => break, // The pool is gone
+                    };
+                    if result_sender.send((index, encode(job))).is_err() {
+                        break;
+                    }
+                }
+            })
+        }).collect();
+
+        EncoderPool {
+            job_sender: Some(job_sender),
+            result_receiver: result_receiver,
+            threads: threads,
+        }
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Encodes all jobs and returns the results in the same order
+    pub fn encode_all(&self, jobs: Vec<EncodeJob>) -> Vec<EncodedTick> {
+        if self.threads.is_empty() {
+            return jobs.into_iter().map(encode).collect();
+        }
+
+        let num_jobs = jobs.len();
+        for (index, job) in jobs.into_iter().enumerate() {
+            self.job_sender.as_ref().unwrap().send((index, job)).unwrap();
+        }
+
+        let mut results = (0..num_jobs).map(|_| None).collect::<Vec<Option<EncodedTick>>>();
+        for _ in 0..num_jobs {
+            let (index, result) = self.result_receiver.recv()
+                                      .expect("tick encoder threads died");
+            results[index] = Some(result);
+        }
+
+        results.into_iter().map(|result| result.unwrap()).collect()
+    }
+}
+
+impl Drop for EncoderPool {
+    fn drop(&mut self) {
+        // Closing the channel makes the threads stop
+        self.job_sender = None;
+
+        for thread in self.threads.drain(..) {
+            thread.join().ok();
+        }
+    }
+}
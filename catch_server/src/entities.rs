@@ -1,11 +1,11 @@
-use std::f32;
 use std::iter::Iterator;
 
 use ecs;
 use ecs::{BuildData, EntityData, DataHelper, EntityBuilder};
 
 use shared;
-use shared::{PlayerId, DeathReason, GameEvent};
+use shared::{PlayerId, DeathReason, GameEvent, EntityType};
+use shared::entities::Behavior;
 use shared::net_components::NetComponents;
 use shared::services::HasEvents;
 
@@ -52,45 +52,58 @@ pub fn build_net_custom<B: EntityBuilder<Components>>
         }
 
         // Add shared components that don't need to be synchronized
-        shared::entities::build_shared(type_name, entity, data);
+        shared::entities::build_shared(&entity_type, entity, data);
 
         // Add server-side only components
-        build_server(type_name, entity, data);
+        build_server(&entity_type, entity, data);
 
         // Possibly add some custom components
         builder.build(entity, data);
     });
 
-    data.services.net_entities.on_build(entity_id, entity_type_id, owner, entity);
+    data.services.net_entities.on_build(entity_id, &entity_type, owner, entity);
 
     entity
 }
 
-/// Adds server-side components that are not synchronized over the net to an entity
-pub fn build_server(type_name: &str,
+/// Adds server-side components that are not synchronized over the net to an entity, according
+/// to the behaviors of its type
+pub fn build_server(entity_type: &EntityType,
                     entity: BuildData<Components>,
                     data: &mut Components) {
-    if type_name == "player" {
-        data.angular_velocity.add(&entity, AngularVelocity::default());
-        data.player_controller.add(&entity, PlayerController::default());
-    } else if type_name == "bouncy_enemy" ||
-              type_name == "player_ball" {
-        data.linear_velocity.add(&entity, LinearVelocity::default());
-        data.bouncy_enemy.add(&entity, BouncyEnemy::default());
-    } else if type_name == "item_spawn" {
-        data.item_spawn.add(&entity, ItemSpawn::default());
-    } else if type_name == "item" {
-        data.angular_velocity.add(&entity, AngularVelocity { v: f32::consts::PI });
-        data.rotate.add(&entity, Rotate);
-    } else if type_name == "bullet" ||
-              type_name == "frag" ||
-              type_name == "shrapnel" {
-        data.linear_velocity.add(&entity, LinearVelocity::default());
-        if type_name == "frag" || type_name == "shrapnel" {
-            data.angular_velocity.add(&entity, AngularVelocity { v: 1.0 * f32::consts::PI });
-            data.rotate.add(&entity, Rotate);
+    // Several behaviors can need the same component, but it may only be added once
+    let mut linear_velocity = false;
+    let mut angular_velocity = None;
+
+    for behavior in entity_type.behaviors.iter() {
+        match *behavior {
+            Behavior::PlayerController => {
+                angular_velocity = angular_velocity.or(Some(0.0));
+                data.player_controller.add(&entity, PlayerController::default());
+            }
+            Behavior::Movement => {
+                linear_velocity = true;
+            }
+            Behavior::Rotate(v) => {
+                angular_velocity = Some(v);
+                data.rotate.add(&entity, Rotate);
+            }
+            Behavior::BouncyEnemy => {
+                linear_velocity = true;
+                data.bouncy_enemy.add(&entity, BouncyEnemy::default());
+            }
+            Behavior::ItemSpawn => {
+                data.item_spawn.add(&entity, ItemSpawn::default());
+            }
         }
     }
+
+    if linear_velocity {
+        data.linear_velocity.add(&entity, LinearVelocity::default());
+    }
+    if let Some(v) = angular_velocity {
+        data.angular_velocity.add(&entity, AngularVelocity { v: v });
+    }
 }
 
 /// Removes a net entity and tells clients about the removal.
@@ -0,0 +1,25 @@
+#[macro_use] extern crate log;
+#[macro_use] extern crate ecs;
+#[macro_use] extern crate catch_shared as shared;
+extern crate rustc_serialize;
+extern crate bincode;
+extern crate time;
+extern crate clock_ticks;
+extern crate rand;
+extern crate hprof;
+extern crate nalgebra as na;
+
+pub mod components;
+pub mod entities;
+pub mod services;
+pub mod systems;
+pub mod state;
+pub mod snapshot;
+pub mod encoder;
+pub mod metrics;
+pub mod replay;
+pub mod server;
+pub mod console;
+pub mod watcher;
+
+pub use server::Server;
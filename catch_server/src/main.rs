@@ -1,345 +1,123 @@
 #[macro_use] extern crate log;
 extern crate env_logger;
-#[macro_use] extern crate ecs;
-#[macro_use] extern crate catch_shared as shared;
+extern crate catch_server;
+extern crate catch_shared as shared;
 extern crate renet as enet;
-extern crate libc;
-extern crate rustc_serialize;
-extern crate bincode;
 extern crate time;
-extern crate clock_ticks;
-extern crate rand;
-extern crate hprof;
-extern crate nalgebra as na;
+extern crate getopts;
 
-pub mod components;
-pub mod entities;
-pub mod services;
-pub mod systems;
-pub mod state;
+use std::env;
+use std::process;
+use std::str::FromStr;
 
-use std::collections::HashMap;
-use std::thread;
-use time::{Duration, Timespec};
+use getopts::{Options, Matches};
 
-use bincode::SizeLimit;
-use bincode::rustc_serialize::{encode, encode_into, decode};
+use shared::{net, TickNumber};
+use shared::transport::EnetTransport;
+use catch_server::{server, replay, Server};
 
-use shared::net;
-use shared::{PlayerId, PlayerInfo, TickNumber, GameInfo, Tick};
-use shared::net::{ClientMessage, ServerMessage};
-use shared::util::PeriodicTimer;
-use shared::tick::DeltaEncodeTick;
-use state::GameState;
-
-#[derive(PartialEq, Eq, Clone, Copy)]
-enum ClientState {
-    Connecting,
-    Connected,
-}
-
-struct Client {
-    peer: enet::Peer,
-    state: ClientState,
-
-    ping_sent_time: Option<Timespec>,
-    ping: Option<Duration>,
-
-    // Not adjusted for ping
-    at_tick: Option<TickNumber>,
-
-    last_tick: Option<Tick>,
-}
-
-struct Server {
-    game_info: GameInfo,
-
-    host: enet::Host,
-    player_id_counter: PlayerId,
-    clients: HashMap<PlayerId, Client>,
-
-    game_state: GameState,
-
-    tick_timer: PeriodicTimer,
-
-    // Statistics and stuff
-    print_prof_timer: PeriodicTimer,
-    sum_tick_size: usize,
-    samples_tick_size: usize,
-}
-
-impl Server {
-    fn start(game_info: &GameInfo,
-             port: u16,
-             peer_count: u32) -> Result<Server, String> {
-        let host = try!(enet::Host::new_server(port, peer_count,
-                                               net::NUM_CHANNELS as u32,
-                                               0, 0));
-
-        info!("server started on port {}", port);
-        info!("game info: {:?}", game_info);
-
-        let tick_duration_s = 1.0 / (game_info.ticks_per_second as f32);
-
-        Ok(Server {
-            game_info: game_info.clone(),
-            host: host,
-            player_id_counter: 0,
-            clients: HashMap::new(),
-            game_state: GameState::new(game_info),
-            tick_timer: PeriodicTimer::new(tick_duration_s),
-            print_prof_timer: PeriodicTimer::new(5.0),
-            sum_tick_size: 0,
-            samples_tick_size: 0,
-        })
-    }
-
-    fn tick_time(&self) -> f32 {
-        self.game_state.tick_number() as f32 + self.tick_timer.progress()
-    }
-
-    fn service(&mut self) -> bool {
-        let event = self.host.service(0); 
-        match event {
-            Ok(enet::Event::Connect(peer)) => {
-                self.player_id_counter += 1;
-
-                info!("client {} is connecting", self.player_id_counter);
-
-                assert!(self.clients.get(&self.player_id_counter).is_none());
-                peer.set_user_data(self.player_id_counter as *mut libc::c_void);
-                self.clients.insert(self.player_id_counter,
-                    Client {
-                        peer: peer,
-                        state: ClientState::Connecting,
-                        ping_sent_time: None,
-                        ping: None,
-                        at_tick: None,
-                        last_tick: None,
-                    });
-
-                return true;
-            }
-            Ok(enet::Event::Disconnect(peer)) => {
-                let player_id = peer.get_user_data() as u32; 
-                let client_state = self.clients[&player_id].state;
-
-                info!("client {} disconnected", player_id);
-
-                self.clients.remove(&player_id);
-
-                if client_state == ClientState::Connected {
-                    // At the start of the next tick, broadcast PlayerLeave game events
-                    self.game_state.remove_player(player_id);
-                }
-
-                return true;
-            }
-            Ok(enet::Event::Receive(peer, channel_id, packet)) => {
-                let player_id = peer.get_user_data() as u32;
-                assert!(self.clients.get(&player_id).is_some());
+fn main() {
+    env_logger::init().unwrap();
 
-                if channel_id != net::Channel::Messages as u8 {
-                    warn!("received packet on non-message channel from client {}", player_id);
-                }
-                
-                match decode(&packet.data()) {
-                    Ok(message) => 
-                        self.process_client_message(player_id, &message),
-                    Err(_) => 
-                        warn!("received invalid message from client {}", player_id),
-                };
+    let args: Vec<String> = env::args().collect();
+
+    let mut opts = Options::new();
+    opts.optopt("", "seed", "seed for the simulation's random number generator", "SEED");
+    opts.optopt("", "map", "map file to play on, or random:SEED[:WIDTHxHEIGHT] to generate one",
+                "MAP");
+    opts.optopt("", "record", "record all inputs to the given file", "LOG");
+    opts.optopt("", "replay", "replay a recorded input log without networking", "LOG");
+    opts.optopt("", "replay-until", "stop replaying after the given tick", "TICK");
+    opts.optopt("", "name", "server name shown to players", "NAME");
+    opts.optopt("", "motd", "message shown to players when they join", "TEXT");
+    opts.optopt("", "max-players", "maximum number of connected players", "N");
+    opts.optopt("", "encoder-threads", "number of threads that encode ticks for the clients, 0 \
+                                        to encode on the main thread", "N");
+    opts.optflagopt("", "metrics", "serve metrics in the Prometheus text format on a local port, \
+                                     9990 by default", "PORT");
+    opts.optopt("", "metrics-csv", "append the metrics to a CSV file every second", "FILE");
+    opts.optflag("", "no-discovery", "don't answer clients looking for servers on the LAN");
+    opts.optflag("", "no-hot-reload", "don't reload the map, balance and tuning files when they \
+                                       change");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(e) => panic!(e.to_string())
+    };
+    let seed = parse_opt::<u64>(&matches, "seed").unwrap_or(time::get_time().nsec as u64);
 
-                return true;
-            }
-            Ok(enet::Event::None) => return false,
-            Err(error) => {
-                warn!("error servicing: {}", error);
-                return false;
-            }
+    if let Some(path) = matches.opt_str("replay") {
+        let until_tick = parse_opt::<TickNumber>(&matches, "replay-until");
+        if let Err(error) = replay::replay(&path, until_tick) {
+            error!("Couldn't replay: {}", error);
         }
+        return;
     }
 
-    fn send(&self, client: &Client, message: &ServerMessage) {
-        //print!("sending message {:?}", message);
-        assert!(client.state == ClientState::Connected);
+    enet::initialize().unwrap();
 
-        let data = encode(message, SizeLimit::Infinite).unwrap();
-        client.peer.send(&data, enet::ffi::ENET_PACKET_FLAG_RELIABLE,
-                         net::Channel::Messages as u8);
+    let map_name = matches.opt_str("map").unwrap_or(server::DEFAULT_MAP.to_string());
+    let mut game_info = match server::game_info(&map_name) {
+        Ok(game_info) => game_info,
+        Err(error) => panic!(error),
+    };
+    if let Some(name) = matches.opt_str("name") {
+        game_info.server_name = name;
     }
+    if let Some(motd) = matches.opt_str("motd") {
+        game_info.motd = motd;
+    }
+    let max_players = parse_opt::<u32>(&matches, "max-players").unwrap_or(16);
 
-    fn process_client_message(&mut self, player_id: PlayerId, message: &ClientMessage) {
-        match message {
-            &ClientMessage::Pong => {
-                debug!("got pong from {}", player_id);
-                let client = self.clients.get_mut(&player_id).unwrap();
-                
-                match client.ping_sent_time {
-                    Some(ping_sent_time) =>
-                        client.ping = Some(time::get_time() - ping_sent_time),
-                    None =>
-                        warn!("received unwarranted pong from {}", player_id)
-                };
-
-                client.ping_sent_time = None;
-            }
-            &ClientMessage::WishConnect { ref name } => {
-                let client_state = self.clients[&player_id].state;
-
-                if client_state != ClientState::Connecting {
-                    warn!("connected player {} is trying to connect again, ignoring",
-                          player_id);
-                    return;
+    let transport = match EnetTransport::server(server::DEFAULT_PORT, max_players,
+                                                net::NUM_CHANNELS) {
+        Ok(transport) => transport,
+        Err(error) => panic!("Couldn't listen on port {}: {}", server::DEFAULT_PORT, error),
+    };
+    info!("listening on port {}", server::DEFAULT_PORT);
+
+    match Server::start(&game_info, seed, matches.opt_str("record"),
+                        Box::new(transport)).as_mut() {
+        Ok(server) => {
+            if !matches.opt_present("no-discovery") {
+                if let Err(error) = server.enable_discovery(server::DEFAULT_PORT, max_players) {
+                    warn!("{}, the server won't be found on the LAN", error);
                 }
-
-                info!("player {} connected with name {}", player_id, name);
-
-                self.clients.get_mut(&player_id).unwrap().state = ClientState::Connected;
-                self.send(&self.clients[&player_id],
-                          &ServerMessage::AcceptConnect {
-                              your_id: player_id,
-                              game_info: self.game_info.clone(),
-                          });
-
-                let player_info = PlayerInfo::new(name.clone());
-
-                // This officially adds the player to the game state.
-                // At the beginning of the next tick, PlayerJoin messages will be sent out.
-                self.game_state.add_player(player_id, player_info);
-            }
-            &ClientMessage::PlayerInput(ref input)  => {
-                self.game_state.on_player_input(player_id, input);
             }
-            &ClientMessage::StartingTick { ref tick } => {
-                self.clients.get_mut(&player_id).unwrap().at_tick = Some(*tick);
+            if let Some(n) = parse_opt::<usize>(&matches, "encoder-threads") {
+                server.set_encoder_threads(n);
             }
-        }
-    }
-
-    fn run(&mut self) {
-        let mut start_ns = clock_ticks::precise_time_ns();
-
-        loop {
-            // Is this how DDOS happens?
-            while self.service() {}
-
-            {
-                // Start ticks
-                hprof::start_frame();
-                let mut r = false;
-                if self.tick_timer.next() {
-                    let _g = hprof::enter("ticks");
-                    self.tick();
-                    r = true;
-                }
-                hprof::end_frame();
-
-                if r && self.print_prof_timer.next_reset() {
-                    //hprof::profiler().print_timing();  
-
-                    if self.samples_tick_size > 0 {
-                        info!("average tick size over last {} ticks: {:.2} bytes, {:.2} kb/s",
-                              self.samples_tick_size,
-                              self.sum_tick_size as f64 / self.samples_tick_size as f64,
-                              self.sum_tick_size as f64 / (1000.0 * 5.0));
-                    }
-                    self.sum_tick_size = 0;
-                    self.samples_tick_size = 0;
+            if matches.opt_present("metrics") {
+                let port = parse_opt::<u16>(&matches, "metrics")
+                               .unwrap_or(server::DEFAULT_METRICS_PORT);
+                if let Err(error) = server.enable_metrics_endpoint(port) {
+                    warn!("{}", error);
                 }
             }
-
-            thread::sleep_ms(0);
-
-            let new_start_ns = clock_ticks::precise_time_ns();
-            let delta_s = (new_start_ns - start_ns) as f32 / 1000000000.0;
-            self.tick_timer.add(delta_s);
-            self.print_prof_timer.add(delta_s);
-            start_ns = new_start_ns;
-        }
-    }
-
-    fn tick(&mut self) {
-        self.game_state.tick();
-
-        //debug!("sending tick {}", self.game_state.tick_number);
-        
-        // Broadcast tick to clients
-        let _g = hprof::enter("broadcast");
-
-        let mut data = Vec::new();
-        for &player_id in &self.clients.keys().map(|k| *k).collect::<Vec<_>>() {
-            if self.clients[&player_id].state == ClientState::Connected {
-                // Build tick for each client separately. This makes it possible to do
-                // delta encoding and stuff.
-                let _g = hprof::enter("store");
-                let tick_number = self.game_state.tick_number;
-
-                let mut tick = Tick::new(tick_number);
-                tick.events = self.game_state.world.services.next_player_events[&player_id]
-                                  .clone();
-
-                self.game_state.world.systems.net_entity_system
-                    .store_in_tick_state(player_id, &mut tick.state,
-                                         &mut self.game_state.world.data);
-                drop(_g);
-                let _g = hprof::enter("encode");
-
-                data.clear();
-                if let Some(last_tick) = self.clients[&player_id].last_tick.as_ref() {
-                    // We can do delta encoding
-                    let delta_encode_tick = DeltaEncodeTick {
-                        last_tick: last_tick,
-                        tick: &tick,
-                    };
-
-                    trace!("encoding delta from {} to {} for {}", tick_number,
-                           last_tick.tick_number, player_id);
-
-                    encode_into(&Some(last_tick.tick_number), &mut data, SizeLimit::Infinite)
-                        .unwrap();
-                    encode_into(&delta_encode_tick, &mut data, SizeLimit::Infinite)
-                        .unwrap();
-                } else {
-                    let delta_tick: Option<TickNumber> = None;
-                    encode_into(&delta_tick, &mut data, SizeLimit::Infinite).unwrap();
-                    encode_into(&tick, &mut data, SizeLimit::Infinite).unwrap();
+            if let Some(path) = matches.opt_str("metrics-csv") {
+                if let Err(error) = server.enable_metrics_csv(&path) {
+                    warn!("{}", error);
                 }
-
-                drop(_g);
-                let _g = hprof::enter("send");
-
-                self.sum_tick_size += data.len();
-                self.samples_tick_size += 1;
-
-                self.clients[&player_id]
-                    .peer.send(&data, enet::ffi::ENET_PACKET_FLAG_RELIABLE,
-                               net::Channel::Ticks as u8);
-
-                self.game_state.world.services.next_player_events
-                    .get_mut(&player_id).unwrap().clear();
-
-                self.clients.get_mut(&player_id).unwrap().last_tick = Some(tick);
             }
+            server.enable_console();
+            if !matches.opt_present("no-hot-reload") {
+                server.enable_hot_reload();
+            }
+            server.run()
         }
-    }
-}
-
-fn main() {
-    env_logger::init().unwrap();
-    enet::initialize().unwrap();
-
-    let entity_types = shared::entities::all_entity_types();
-    let game_info = GameInfo {
-        map_name: "data/maps/linemap.tmx".to_string(),
-        entity_types: entity_types,
-        ticks_per_second: 30,
-    };
-
-    match Server::start(&game_info, 9988, 128).as_mut() {
-        Ok(server) =>
-            server.run(),
         Err(error) =>
             error!("Couldn't start server: {}", error),
     };
 }
+
+/// Parses the value of an option if it is given, exiting with a usage error if it is invalid
+fn parse_opt<T: FromStr>(matches: &Matches, name: &str) -> Option<T> {
+    matches.opt_str(name).map(|s| {
+        match s.parse() {
+            Ok(value) => value,
+            Err(_) => {
+                println!("invalid value for --{}: {}", name, s);
+                process::exit(2);
+            }
+        }
+    })
+}
@@ -0,0 +1,394 @@
+//! Server diagnostics. The server feeds the `Metrics` with what happens, and they can be
+//! rendered in the Prometheus text format, served over a local TCP port and appended to a CSV
+//! file.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write as FmtWrite;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write, BufRead, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use time;
+use hprof::{self, ProfileNode};
+
+use shared::PlayerId;
+
+// Tick duration percentiles are computed over this many of the last ticks
+const TICK_WINDOW: usize = 300;
+
+const TICK_DURATION_QUANTILES: &'static [f64] = &[0.5, 0.9, 0.99, 1.0];
+
+// How long the endpoint waits for a request before just sending the metrics
+const REQUEST_TIMEOUT_S: u64 = 1;
+
+struct ClientMetrics {
+    name: String,
+    sent_bytes: u64,
+    received_bytes: u64,
+    rtt_s: Option<f64>,
+}
+
+/// A metric with all of its labeled values
+struct Family {
+    name: &'static str,
+    help: &'static str,
+    kind: &'static str,
+    samples: Vec<Sample>,
+}
+
+struct Sample {
+    // Appended to the family's name, e.g. `_sum` for summaries
+    suffix: &'static str,
+    labels: Vec<(&'static str, String)>,
+    value: f64,
+}
+
+impl Family {
+    fn new(name: &'static str, help: &'static str, kind: &'static str) -> Family {
+        Family {
+            name: name,
+            help: help,
+            kind: kind,
+            samples: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, labels: Vec<(&'static str, String)>, value: f64) {
+        self.add_with_suffix("", labels, value);
+    }
+
+    fn add_with_suffix(&mut self, suffix: &'static str, labels: Vec<(&'static str, String)>,
+                       value: f64) {
+        self.samples.push(Sample {
+            suffix: suffix,
+            labels: labels,
+            value: value,
+        });
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    // Durations of the last ticks in seconds
+    tick_durations_s: VecDeque<f64>,
+    tick_duration_sum_s: f64,
+    num_ticks: u64,
+
+    entity_counts: Vec<(String, usize)>,
+    event_counts: BTreeMap<&'static str, u64>,
+    player_event_counts: BTreeMap<&'static str, u64>,
+    clients: BTreeMap<PlayerId, ClientMetrics>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    pub fn record_tick(&mut self, duration_s: f64) {
+        if self.tick_durations_s.len() == TICK_WINDOW {
+            self.tick_durations_s.pop_front();
+        }
+        self.tick_durations_s.push_back(duration_s);
+        self.tick_duration_sum_s += duration_s;
+        self.num_ticks += 1;
+    }
+
+    pub fn set_entity_counts(&mut self, entity_counts: Vec<(String, usize)>) {
+        self.entity_counts = entity_counts;
+    }
+
+    pub fn set_event_counts(&mut self, event_counts: BTreeMap<&'static str, u64>,
+                            player_event_counts: BTreeMap<&'static str, u64>) {
+        self.event_counts = event_counts;
+        self.player_event_counts = player_event_counts;
+    }
+
+    pub fn add_client(&mut self, player_id: PlayerId, name: String) {
+        self.clients.insert(player_id, ClientMetrics {
+            name: name,
+            sent_bytes: 0,
+            received_bytes: 0,
+            rtt_s: None,
+        });
+    }
+
+    pub fn remove_client(&mut self, player_id: PlayerId) {
+        self.clients.remove(&player_id);
+    }
+
+    pub fn record_sent(&mut self, player_id: PlayerId, num_bytes: usize) {
+        if let Some(client) = self.clients.get_mut(&player_id) {
+            client.sent_bytes += num_bytes as u64;
+        }
+    }
+
+    pub fn record_received(&mut self, player_id: PlayerId, num_bytes: usize) {
+        if let Some(client) = self.clients.get_mut(&player_id) {
+            client.received_bytes += num_bytes as u64;
+        }
+    }
+
+    pub fn set_rtt(&mut self, player_id: PlayerId, rtt_s: f64) {
+        if let Some(client) = self.clients.get_mut(&player_id) {
+            client.rtt_s = Some(rtt_s);
+        }
+    }
+
+    fn tick_duration_quantile(&self, q: f64) -> f64 {
+        if self.tick_durations_s.is_empty() {
+            return 0.0;
+        }
+
+        let mut durations = self.tick_durations_s.iter().cloned().collect::<Vec<_>>();
+        durations.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let index = ((durations.len() - 1) as f64 * q).round() as usize;
+        durations[index]
+    }
+
+    fn families(&self) -> Vec<Family> {
+        let mut tick_duration = Family::new("catch_tick_duration_seconds",
+                                            "Time it takes to run and broadcast a tick",
+                                            "summary");
+        for &q in TICK_DURATION_QUANTILES {
+            tick_duration.add(vec![("quantile", q.to_string())], self.tick_duration_quantile(q));
+        }
+        tick_duration.add_with_suffix("_sum", vec![], self.tick_duration_sum_s);
+        tick_duration.add_with_suffix("_count", vec![], self.num_ticks as f64);
+
+        // The scopes are only known to the profiler of the thread that runs the ticks
+        let mut scope_seconds = Family::new("catch_scope_seconds_total",
+                                            "Time spent in each profiled scope", "counter");
+        let mut scope_calls = Family::new("catch_scope_calls_total",
+                                          "Number of times each profiled scope was entered",
+                                          "counter");
+        for (scope, calls, seconds) in profiled_scopes() {
+            scope_seconds.add(vec![("scope", scope.clone())], seconds);
+            scope_calls.add(vec![("scope", scope)], calls);
+        }
+
+        let mut entities = Family::new("catch_entities", "Number of net entities by type",
+                                       "gauge");
+        for &(ref entity_type, count) in &self.entity_counts {
+            entities.add(vec![("type", entity_type.clone())], count as f64);
+        }
+
+        let mut events = Family::new("catch_events_total",
+                                     "Number of game events for all players by variant",
+                                     "counter");
+        for (&event, &count) in &self.event_counts {
+            events.add(vec![("event", event.to_string())], count as f64);
+        }
+        let mut player_events = Family::new("catch_player_events_total",
+                                            "Number of game events queued for single players \
+                                             by variant, counted once per player",
+                                            "counter");
+        for (&event, &count) in &self.player_event_counts {
+            player_events.add(vec![("event", event.to_string())], count as f64);
+        }
+
+        let mut clients = Family::new("catch_clients", "Number of connected clients", "gauge");
+        clients.add(vec![], self.clients.len() as f64);
+
+        let mut sent_bytes = Family::new("catch_client_sent_bytes_total",
+                                         "Bytes sent to each client", "counter");
+        let mut received_bytes = Family::new("catch_client_received_bytes_total",
+                                             "Bytes received from each client", "counter");
+        let mut rtt = Family::new("catch_client_rtt_seconds",
+                                  "Round trip time of each client's last ping", "gauge");
+        for (&player_id, client) in &self.clients {
+            let labels = vec![("player_id", player_id.to_string()),
+                              ("name", client.name.clone())];
+            sent_bytes.add(labels.clone(), client.sent_bytes as f64);
+            received_bytes.add(labels.clone(), client.received_bytes as f64);
+            if let Some(rtt_s) = client.rtt_s {
+                rtt.add(labels, rtt_s);
+            }
+        }
+
+        vec![tick_duration, scope_seconds, scope_calls, entities, events, player_events, clients,
+             sent_bytes, received_bytes, rtt]
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format
+    pub fn render_prometheus(&self) -> String {
+        let mut text = String::new();
+
+        for family in self.families() {
+            writeln!(text, "# HELP {} {}", family.name, family.help).unwrap();
+            writeln!(text, "# TYPE {} {}", family.name, family.kind).unwrap();
+
+            for sample in &family.samples {
+                text.push_str(family.name);
+                text.push_str(sample.suffix);
+                if !sample.labels.is_empty() {
+                    let labels = sample.labels.iter()
+                                       .map(|&(key, ref value)| {
+                                           format!("{}=\"{}\"", key, escape_label(value))
+                                       })
+                                       .collect::<Vec<_>>();
+                    write!(text, "{{{}}}", labels.join(",")).unwrap();
+                }
+                writeln!(text, " {}", sample.value).unwrap();
+            }
+        }
+
+        text
+    }
+
+    /// Appends one `unix_time_s,metric,labels,value` row per sample
+    fn write_csv<W: Write>(&self, time_s: f64, w: &mut W) -> io::Result<()> {
+        for family in self.families() {
+            for sample in &family.samples {
+                let labels = sample.labels.iter()
+                                   .map(|&(key, ref value)| format!("{}={}", key, value))
+                                   .collect::<Vec<_>>();
+                try!(writeln!(w, "{:.3},{}{},{},{}", time_s, family.name, sample.suffix,
+                              escape_csv(&labels.join(";")), sample.value));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Cumulative calls and seconds of every `hprof::enter` scope of the current thread, named by
+/// their path, e.g. `ticks/broadcast/encode`
+fn profiled_scopes() -> Vec<(String, f64, f64)> {
+    fn collect(node: &ProfileNode, parent_path: &str, scopes: &mut Vec<(String, f64, f64)>) {
+        let path = if parent_path.is_empty() {
+            node.name.to_string()
+        } else {
+            format!("{}/{}", parent_path, node.name)
+        };
+        scopes.push((path.clone(), node.calls.get() as f64,
+                     node.total_time.get() as f64 / 1000000000.0));
+
+        for child in node.children.borrow().iter() {
+            collect(child, &path, scopes);
+        }
+    }
+
+    let mut scopes = Vec::new();
+    for child in hprof::profiler().root().children.borrow().iter() {
+        collect(child, "", &mut scopes);
+    }
+    scopes
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace("\\", "\\\\").replace("\"", "\\\"").replace("\n", "\\n")
+}
+
+fn escape_csv(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') {
+        format!("\"{}\"", value.replace("\"", "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Serves the last published metrics to anyone connecting to a local TCP port. HTTP requests,
+/// e.g. from Prometheus, get an HTTP response, other connections just get the text.
+pub struct MetricsEndpoint {
+    text: Arc<Mutex<String>>,
+}
+
+impl MetricsEndpoint {
+    pub fn start(port: u16) -> Result<MetricsEndpoint, String> {
+        let listener = try!(TcpListener::bind(("127.0.0.1", port)).map_err(|error| {
+            format!("could not listen for metrics on port {}: {}", port, error)
+        }));
+
+        let text = Arc::new(Mutex::new(String::new()));
+        let thread_text = text.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| {
+                    let text = thread_text.lock().unwrap().clone();
+                    serve(stream, &text)
+                });
+                if let Err(error) = result {
+                    debug!("could not serve metrics: {}", error);
+                }
+            }
+        });
+
+        Ok(MetricsEndpoint {
+            text: text,
+        })
+    }
+
+    pub fn publish(&self, text: String) {
+        *self.text.lock().unwrap() = text;
+    }
+}
+
+fn serve(mut stream: TcpStream, text: &str) -> io::Result<()> {
+    try!(stream.set_read_timeout(Some(::std::time::Duration::from_secs(REQUEST_TIMEOUT_S))));
+
+    let is_http = {
+        let mut reader = BufReader::new(try!(stream.try_clone()));
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).is_err() {
+            // The client didn't ask for anything
+            request_line.clear();
+        }
+
+        let is_http = request_line.contains("HTTP/");
+        if is_http {
+            // Skip the headers, we answer every request the same way
+            let mut line = String::new();
+            while try!(reader.read_line(&mut line)) > 0 && !line.trim().is_empty() {
+                line.clear();
+            }
+        }
+        is_http
+    };
+
+    if is_http {
+        try!(write!(stream, "HTTP/1.0 200 OK\r\n\
+                             Content-Type: text/plain; version=0.0.4\r\n\
+                             Content-Length: {}\r\n\r\n", text.len()));
+    }
+    stream.write_all(text.as_bytes())
+}
+
+/// Appends the metrics to a CSV file from time to time
+pub struct MetricsCsv {
+    file: File,
+}
+
+impl MetricsCsv {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<MetricsCsv, String> {
+        let path = path.as_ref();
+        let is_new = !path.exists();
+
+        let file = OpenOptions::new().create(true).append(true).open(path);
+        let mut file = try!(file.map_err(|error| {
+            format!("could not open metrics file {:?}: {}", path, error)
+        }));
+        if is_new {
+            try!(writeln!(file, "unix_time_s,metric,labels,value").map_err(|error| {
+                format!("could not write metrics file {:?}: {}", path, error)
+            }));
+        }
+
+        Ok(MetricsCsv {
+            file: file,
+        })
+    }
+
+    pub fn write(&mut self, metrics: &Metrics) {
+        let now = time::get_time();
+        let time_s = now.sec as f64 + now.nsec as f64 / 1000000000.0;
+
+        if let Err(error) = metrics.write_csv(time_s, &mut self.file) {
+            warn!("could not write metrics: {}", error);
+        }
+    }
+}
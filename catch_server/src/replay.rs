@@ -0,0 +1,185 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use bincode::SizeLimit;
+use bincode::rustc_serialize::{encode_into, decode_from};
+
+use shared::{GameInfo, PlayerId, PlayerInfo, TickNumber, Balance, Tuning, Map};
+use shared::net::TimedPlayerInput;
+
+use state::GameState;
+
+/// Everything needed to recreate the game state from the start
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct InputLogHeader {
+    pub game_info: GameInfo,
+    pub seed: u64,
+
+    // Contents of the map file at the start, so that replays don't depend on the file staying
+    // the same. None for generated maps.
+    pub map_text: Option<String>,
+}
+
+/// Inputs from the outside world that change the game state
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub enum InputLogEvent {
+    PlayerJoin(PlayerId, PlayerInfo),
+    PlayerLeave(PlayerId),
+    PlayerInput(PlayerId, TimedPlayerInput),
+    SetBalance(Balance),
+    SetTuning(Tuning),
+
+    // The map file was reloaded, with its new contents
+    ReloadMap(String),
+}
+
+/// An event together with the number of the last tick that was run before the event happened
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct InputLogEntry {
+    pub tick_number: TickNumber,
+    pub event: InputLogEvent,
+}
+
+/// Writes every input to the game state into a file, so that a game can be replayed offline
+pub struct InputLog {
+    writer: BufWriter<File>,
+}
+
+impl InputLog {
+    pub fn create<P: AsRef<Path>>(path: P, header: &InputLogHeader) -> Result<InputLog, String> {
+        let file = match File::create(path.as_ref()) {
+            Ok(file) => file,
+            Err(error) => return Err(format!("could not create input log {:?}: {}",
+                                             path.as_ref(), error)),
+        };
+
+        let mut log = InputLog {
+            writer: BufWriter::new(file),
+        };
+        if let Err(error) = encode_into(header, &mut log.writer, SizeLimit::Infinite) {
+            return Err(format!("could not write input log header: {:?}", error));
+        }
+        Ok(log)
+    }
+
+    pub fn write(&mut self, tick_number: TickNumber, event: InputLogEvent) {
+        let entry = InputLogEntry {
+            tick_number: tick_number,
+            event: event,
+        };
+        if let Err(error) = encode_into(&entry, &mut self.writer, SizeLimit::Infinite) {
+            warn!("could not write to input log: {:?}", error);
+        }
+    }
+
+    /// Should be called after every tick, so that we don't lose anything when the server panics
+    pub fn flush(&mut self) {
+        if let Err(error) = self.writer.flush() {
+            warn!("could not flush input log: {}", error);
+        }
+    }
+}
+
+/// Loads the map of a game, together with the contents of the map file if it is not generated
+pub fn load_map(game_info: &GameInfo) -> Result<(Map, Option<String>), String> {
+    if game_info.map_gen.is_some() {
+        let map = try!(game_info.load_map().map_err(|error| error.to_string()));
+        return Ok((map, None));
+    }
+
+    let (map, text) = try!(Map::load_with_text(&game_info.map_name)
+                               .map_err(|error| error.to_string()));
+    Ok((map, Some(text)))
+}
+
+/// Re-runs the game recorded in an input log without any networking. If `until_tick` is given,
+/// the replay stops after that tick and the resulting state is printed.
+pub fn replay<P: AsRef<Path>>(path: P, until_tick: Option<TickNumber>)
+                              -> Result<GameState, String> {
+    let file = match File::open(path.as_ref()) {
+        Ok(file) => file,
+        Err(error) => return Err(format!("could not open input log {:?}: {}",
+                                         path.as_ref(), error)),
+    };
+    let mut reader = BufReader::new(file);
+
+    let header: InputLogHeader = match decode_from(&mut reader, SizeLimit::Infinite) {
+        Ok(header) => header,
+        Err(error) => return Err(format!("invalid input log header: {:?}", error)),
+    };
+
+    info!("replaying {:?} with seed {}", header.game_info, header.seed);
+
+    // Generated maps only depend on their parameters, which are part of the game info
+    let map = match header.map_text {
+        Some(ref text) => Map::parse(text, &header.game_info.map_name),
+        None => header.game_info.load_map(),
+    };
+    let map = try!(map.map_err(|error| format!("invalid map in input log: {}", error)));
+
+    let mut game_state = GameState::with_map(&header.game_info, map, header.seed);
+
+    loop {
+        let entry: InputLogEntry = match decode_from(&mut reader, SizeLimit::Infinite) {
+            Ok(entry) => entry,
+            Err(_) => {
+                // We don't know whether the log ended here because the server was quit or
+                // because it crashed while writing, so just stop
+                info!("end of input log after tick {}", game_state.tick_number());
+                break;
+            }
+        };
+
+        // Run ticks until we reach the point where the event happened
+        while game_state.tick_number() < entry.tick_number {
+            if Some(game_state.tick_number()) == until_tick {
+                game_state.log_summary();
+                return Ok(game_state);
+            }
+            game_state.tick();
+            game_state.clear_player_events();
+        }
+
+        trace!("tick {}: {:?}", entry.tick_number, entry.event);
+
+        match entry.event {
+            InputLogEvent::PlayerJoin(player_id, info) =>
+                game_state.add_player(player_id, info),
+            InputLogEvent::PlayerLeave(player_id) =>
+                game_state.remove_player(player_id),
+            InputLogEvent::PlayerInput(player_id, input) =>
+                game_state.on_player_input(player_id, &input),
+            InputLogEvent::SetBalance(balance) =>
+                game_state.set_balance(balance),
+            InputLogEvent::SetTuning(tuning) =>
+                game_state.set_tuning(tuning),
+            InputLogEvent::ReloadMap(text) => {
+                let result = Map::parse(&text, &header.game_info.map_name)
+                                 .map_err(|error| error.to_string())
+                                 .and_then(|map| game_state.set_map(map));
+                if let Err(error) = result {
+                    warn!("could not reload map: {}", error);
+                }
+            }
+        }
+    }
+
+    match until_tick {
+        Some(until_tick) => {
+            // Nothing happened after the end of the log, but the server kept running ticks
+            while game_state.tick_number() < until_tick {
+                game_state.tick();
+                game_state.clear_player_events();
+            }
+        }
+        None => {
+            // Run the last tick for which we have inputs
+            game_state.tick();
+            game_state.clear_player_events();
+        }
+    }
+
+    game_state.log_summary();
+    Ok(game_state)
+}
@@ -0,0 +1,695 @@
+use std::collections::{HashMap, VecDeque};
+use std::thread;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use time::{self, Duration, Timespec};
+
+use clock_ticks;
+use hprof;
+
+use bincode::SizeLimit;
+use bincode::rustc_serialize::{encode, decode};
+
+use shared::{self, net};
+use shared::{player, PlayerId, PlayerInfo, TickNumber, GameInfo, MapGenParams, Balance, Tuning,
+             Map};
+use shared::balance::BALANCE_PATH;
+use shared::tuning::TUNING_PATH;
+use shared::net::{ClientMessage, ServerMessage};
+use shared::util::PeriodicTimer;
+use shared::transport::{Transport, TransportEvent, PeerId, Reliability};
+use shared::discovery::{DiscoveryResponder, ServerAnnouncement};
+use state::GameState;
+use snapshot::WorldSnapshot;
+use encoder::{EncoderPool, EncodeJob, DEFAULT_ENCODER_THREADS};
+use metrics::{Metrics, MetricsEndpoint, MetricsCsv};
+use replay::{self, InputLog, InputLogHeader, InputLogEvent};
+use console::Console;
+use watcher::FileWatcher;
+
+pub const DEFAULT_PORT: u16 = 9988;
+pub const DEFAULT_MAP: &'static str = "data/maps/linemap.tmx";
+pub const DEFAULT_NAME: &'static str = "catch server";
+pub const DEFAULT_METRICS_PORT: u16 = 9990;
+
+// There is only one way to play for now
+pub const GAME_MODE: &'static str = "catch";
+
+// Players can send at most this many chat messages within the window, the rest is dropped
+const MAX_CHAT_MESSAGES: usize = 5;
+const CHAT_WINDOW_S: i64 = 10;
+
+// How often we check whether watched files have changed
+const WATCH_INTERVAL_S: f32 = 1.0;
+
+// How often we measure the round trip time of the clients
+const PING_INTERVAL_S: f32 = 2.0;
+
+// How often the metrics are published and written
+const METRICS_INTERVAL_S: f32 = 1.0;
+
+/// Creates the game info for playing on the given map, which can also be
+/// random:SEED[:WIDTHxHEIGHT] to generate one
+pub fn game_info(map_name: &str) -> Result<GameInfo, String> {
+    let map_gen = match MapGenParams::parse(map_name) {
+        Some(Ok(params)) => Some(params),
+        Some(Err(error)) => return Err(error),
+        None => None,
+    };
+
+    Ok(GameInfo {
+        server_name: DEFAULT_NAME.to_string(),
+        motd: String::new(),
+        map_name: map_name.to_string(),
+        map_gen: map_gen,
+        entity_types: try!(shared::entities::load_entity_types(
+                              shared::entities::ENTITY_TYPES_PATH)),
+        balance: try!(Balance::load(BALANCE_PATH)),
+        tuning: try!(Tuning::load(TUNING_PATH)),
+        ticks_per_second: 30,
+    })
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum ClientState {
+    Connecting,
+    Connected,
+}
+
+struct Client {
+    peer: PeerId,
+    state: ClientState,
+
+    ping_sent_time: Option<Timespec>,
+    ping: Option<Duration>,
+
+    // Not adjusted for ping
+    at_tick: Option<TickNumber>,
+
+    // The snapshot that the last tick sent to the client was built from
+    last_snapshot: Option<Arc<WorldSnapshot>>,
+
+    // When the chat messages within the current window were sent, for rate limiting
+    chat_times: VecDeque<Timespec>,
+}
+
+/// Runs a game and sends its ticks to the clients that connect over the network
+pub struct Server {
+    game_info: GameInfo,
+
+    transport: Box<Transport>,
+    player_id_counter: PlayerId,
+    clients: HashMap<PlayerId, Client>,
+    peer_players: HashMap<PeerId, PlayerId>,
+
+    game_state: GameState,
+
+    // Builds and encodes the ticks of the clients
+    encoder_pool: EncoderPool,
+
+    // If given, all inputs to the game state are recorded for replaying
+    input_log: Option<InputLog>,
+
+    // If given, we answer clients that look for servers on the local network
+    discovery: Option<(DiscoveryResponder, ServerAnnouncement)>,
+
+    // If given, admin commands are read from stdin
+    console: Option<Console>,
+
+    // If given, changed files are reloaded while running
+    watcher: Option<(FileWatcher, PeriodicTimer)>,
+
+    tick_timer: PeriodicTimer,
+    ping_timer: PeriodicTimer,
+
+    // Statistics and stuff
+    print_prof_timer: PeriodicTimer,
+    sum_tick_size: usize,
+    samples_tick_size: usize,
+
+    metrics: Metrics,
+    metrics_timer: PeriodicTimer,
+
+    // If given, the metrics are served on a local port and written to a file, respectively
+    metrics_endpoint: Option<MetricsEndpoint>,
+    metrics_csv: Option<MetricsCsv>,
+}
+
+impl Server {
+    pub fn start(game_info: &GameInfo,
+                 seed: u64,
+                 record_path: Option<String>,
+                 transport: Box<Transport>) -> Result<Server, String> {
+        info!("server started");
+        info!("game info: {:?}", game_info);
+        info!("simulation seed: {}", seed);
+
+        let (map, map_text) = try!(replay::load_map(game_info));
+
+        let input_log = match record_path {
+            Some(path) => {
+                let header = InputLogHeader {
+                    game_info: game_info.clone(),
+                    seed: seed,
+                    map_text: map_text,
+                };
+                info!("recording inputs to {}", path);
+                Some(try!(InputLog::create(&path, &header)))
+            }
+            None => None,
+        };
+
+        let tick_duration_s = 1.0 / (game_info.ticks_per_second as f32);
+
+        Ok(Server {
+            game_info: game_info.clone(),
+            transport: transport,
+            player_id_counter: 0,
+            clients: HashMap::new(),
+            peer_players: HashMap::new(),
+            game_state: GameState::with_map(game_info, map, seed),
+            encoder_pool: EncoderPool::new(DEFAULT_ENCODER_THREADS),
+            input_log: input_log,
+            discovery: None,
+            console: None,
+            watcher: None,
+            tick_timer: PeriodicTimer::new(tick_duration_s),
+            ping_timer: PeriodicTimer::new(PING_INTERVAL_S),
+            print_prof_timer: PeriodicTimer::new(5.0),
+            sum_tick_size: 0,
+            samples_tick_size: 0,
+            metrics: Metrics::new(),
+            metrics_timer: PeriodicTimer::new(METRICS_INTERVAL_S),
+            metrics_endpoint: None,
+            metrics_csv: None,
+        })
+    }
+
+    /// Sets the number of threads that encode ticks for the clients. With zero, ticks are
+    /// encoded on the server thread.
+    pub fn set_encoder_threads(&mut self, num_threads: usize) {
+        if num_threads != self.encoder_pool.num_threads() {
+            info!("encoding ticks on {} threads", num_threads);
+            self.encoder_pool = EncoderPool::new(num_threads);
+        }
+    }
+
+    /// Serves the metrics in the Prometheus text format on the given local port
+    pub fn enable_metrics_endpoint(&mut self, port: u16) -> Result<(), String> {
+        self.metrics_endpoint = Some(try!(MetricsEndpoint::start(port)));
+        info!("serving metrics on port {}", port);
+        Ok(())
+    }
+
+    /// Appends the metrics to a CSV file every second
+    pub fn enable_metrics_csv(&mut self, path: &str) -> Result<(), String> {
+        self.metrics_csv = Some(try!(MetricsCsv::create(path)));
+        info!("writing metrics to {}", path);
+        Ok(())
+    }
+
+    fn update_metrics(&mut self) {
+        if self.metrics_endpoint.is_none() && self.metrics_csv.is_none() {
+            return;
+        }
+
+        self.metrics.set_entity_counts(self.game_state.entity_counts());
+        self.metrics.set_event_counts(self.game_state.event_counts().clone(),
+                                      self.game_state.player_event_counts().clone());
+
+        if let Some(ref endpoint) = self.metrics_endpoint {
+            endpoint.publish(self.metrics.render_prometheus());
+        }
+        if let Some(ref mut csv) = self.metrics_csv {
+            csv.write(&self.metrics);
+        }
+    }
+
+    /// Answers discovery queries on the local network, telling clients that they can connect
+    /// on the given port
+    pub fn enable_discovery(&mut self, port: u16, max_players: u32) -> Result<(), String> {
+        let responder = try!(DiscoveryResponder::bind());
+        let announcement = ServerAnnouncement {
+            name: self.game_info.server_name.clone(),
+            motd: self.game_info.motd.clone(),
+            map_name: self.game_info.map_name.clone(),
+            mode: GAME_MODE.to_string(),
+            num_players: 0,
+            max_players: max_players,
+            port: port,
+        };
+
+        self.discovery = Some((responder, announcement));
+        Ok(())
+    }
+
+    /// Reads admin commands from stdin while running
+    pub fn enable_console(&mut self) {
+        info!("reading commands from stdin, enter `help` for a list");
+        self.console = Some(Console::start());
+    }
+
+    fn run_console_commands(&mut self) {
+        loop {
+            let command = match self.console.as_mut().and_then(|console| console.poll()) {
+                Some(command) => command,
+                None => return,
+            };
+
+            match &command[..] {
+                "reload" => {
+                    if let Err(error) = self.reload_balance() {
+                        warn!("could not reload balance: {}", error);
+                    }
+                }
+                "reload tuning" => {
+                    if let Err(error) = self.reload_tuning() {
+                        warn!("could not reload tuning: {}", error);
+                    }
+                }
+                "reload map" => {
+                    if let Err(error) = self.reload_map() {
+                        warn!("could not reload map: {}", error);
+                    }
+                }
+                "help" => {
+                    info!("commands:");
+                    info!("  reload: reload the balance table from {}", BALANCE_PATH);
+                    info!("  reload tuning: reload the tuning from {}", TUNING_PATH);
+                    info!("  reload map: reload the map file");
+                    info!("  help: show this list");
+                }
+                _ => warn!("unknown command {}, enter `help` for a list", command),
+            }
+        }
+    }
+
+    /// Loads the balance table again. It is applied and sent to the clients in the next tick.
+    pub fn reload_balance(&mut self) -> Result<(), String> {
+        let balance = try!(Balance::load(BALANCE_PATH));
+        info!("reloaded balance from {}", BALANCE_PATH);
+
+        // Clients that connect later get the new values with the game info
+        self.game_info.balance = balance.clone();
+
+        self.record(InputLogEvent::SetBalance(balance.clone()));
+        self.game_state.set_balance(balance);
+        Ok(())
+    }
+
+    /// Reloads the tuning. It is applied and sent to the clients in the next tick.
+    pub fn reload_tuning(&mut self) -> Result<(), String> {
+        let tuning = try!(Tuning::load(TUNING_PATH));
+        info!("reloaded tuning from {}", TUNING_PATH);
+
+        self.game_info.tuning = tuning;
+
+        self.record(InputLogEvent::SetTuning(tuning));
+        self.game_state.set_tuning(tuning);
+        Ok(())
+    }
+
+    /// Reloads the map file. It replaces the current map in the next tick.
+    pub fn reload_map(&mut self) -> Result<(), String> {
+        if self.game_info.map_gen.is_some() {
+            return Err("generated maps can't be reloaded".to_string());
+        }
+
+        let (map, text) = try!(Map::load_with_text(&self.game_info.map_name)
+                                   .map_err(|error| error.to_string()));
+        try!(self.game_state.set_map(map));
+        info!("reloaded map {}", self.game_info.map_name);
+
+        self.record(InputLogEvent::ReloadMap(text));
+        Ok(())
+    }
+
+    /// Polls the map, balance and tuning files for changes while running, and reloads them
+    pub fn enable_hot_reload(&mut self) {
+        let mut watcher = FileWatcher::new();
+        if self.game_info.map_gen.is_none() {
+            watcher.watch(&self.game_info.map_name);
+        }
+        watcher.watch(BALANCE_PATH);
+        watcher.watch(TUNING_PATH);
+
+        info!("watching files for changes");
+        self.watcher = Some((watcher, PeriodicTimer::new(WATCH_INTERVAL_S)));
+    }
+
+    fn reload_changed_files(&mut self) {
+        let changed = match self.watcher {
+            Some((ref mut watcher, ref mut timer)) => {
+                if !timer.next_reset() {
+                    return;
+                }
+                watcher.poll()
+            }
+            None => return,
+        };
+
+        for path in changed {
+            let result = if path == Path::new(BALANCE_PATH) {
+                self.reload_balance()
+            } else if path == Path::new(TUNING_PATH) {
+                self.reload_tuning()
+            } else {
+                self.reload_map()
+            };
+
+            if let Err(error) = result {
+                warn!("could not reload {:?}: {}", path, error);
+            }
+        }
+    }
+
+    fn respond_to_discovery(&mut self) {
+        let num_players = self.clients.values()
+                                      .filter(|client| client.state == ClientState::Connected)
+                                      .count() as u32;
+
+        if let Some((ref responder, ref mut announcement)) = self.discovery {
+            announcement.num_players = num_players;
+            responder.respond(announcement);
+        }
+    }
+
+    fn tick_time(&self) -> f32 {
+        self.game_state.tick_number() as f32 + self.tick_timer.progress()
+    }
+
+    fn service(&mut self) -> bool {
+        let event = self.transport.service(0); 
+        match event {
+            Ok(Some(TransportEvent::Connect(peer))) => {
+                self.player_id_counter += 1;
+
+                info!("client {} is connecting", self.player_id_counter);
+
+                assert!(self.clients.get(&self.player_id_counter).is_none());
+                self.peer_players.insert(peer, self.player_id_counter);
+                self.clients.insert(self.player_id_counter,
+                    Client {
+                        peer: peer,
+                        state: ClientState::Connecting,
+                        ping_sent_time: None,
+                        ping: None,
+                        at_tick: None,
+                        last_snapshot: None,
+                        chat_times: VecDeque::new(),
+                    });
+
+                return true;
+            }
+            Ok(Some(TransportEvent::Disconnect(peer))) => {
+                let player_id = self.peer_players.remove(&peer).unwrap();
+                let client_state = self.clients[&player_id].state;
+
+                info!("client {} disconnected", player_id);
+
+                self.clients.remove(&player_id);
+                self.metrics.remove_client(player_id);
+
+                if client_state == ClientState::Connected {
+                    self.record(InputLogEvent::PlayerLeave(player_id));
+
+                    // At the start of the next tick, broadcast PlayerLeave game events
+                    self.game_state.remove_player(player_id);
+                }
+
+                return true;
+            }
+            Ok(Some(TransportEvent::Receive(peer, channel_id, data))) => {
+                let player_id = self.peer_players[&peer];
+                assert!(self.clients.get(&player_id).is_some());
+
+                if channel_id != net::Channel::Messages as u8 {
+                    warn!("received packet on non-message channel from client {}", player_id);
+                }
+                
+                match decode(&data) {
+                    Ok(message) => 
+                        self.process_client_message(player_id, &message),
+                    Err(_) => 
+                        warn!("received invalid message from client {}", player_id),
+                };
+
+                // Only after processing, so that the message that connects the client counts
+                self.metrics.record_received(player_id, data.len());
+
+                return true;
+            }
+            Ok(None) => return false,
+            Err(error) => {
+                warn!("error servicing: {}", error);
+                return false;
+            }
+        }
+    }
+
+    fn record(&mut self, event: InputLogEvent) {
+        if let Some(input_log) = self.input_log.as_mut() {
+            input_log.write(self.game_state.tick_number(), event);
+        }
+    }
+
+    fn send_pings(&mut self) {
+        let now = time::get_time();
+
+        let mut player_ids = Vec::new();
+        for (&player_id, client) in self.clients.iter_mut() {
+            // Wait for the pong to the last ping before sending another one
+            if client.state == ClientState::Connected && client.ping_sent_time.is_none() {
+                client.ping_sent_time = Some(now);
+                player_ids.push(player_id);
+            }
+        }
+
+        for player_id in player_ids {
+            self.send(player_id, &ServerMessage::Ping);
+        }
+    }
+
+    fn send(&mut self, player_id: PlayerId, message: &ServerMessage) {
+        //print!("sending message {:?}", message);
+        let peer = {
+            let client = &self.clients[&player_id];
+            assert!(client.state == ClientState::Connected);
+            client.peer
+        };
+
+        let data = encode(message, SizeLimit::Infinite).unwrap();
+        self.transport.send(peer, net::Channel::Messages as u8, Reliability::Reliable, &data);
+        self.metrics.record_sent(player_id, data.len());
+    }
+
+    fn process_client_message(&mut self, player_id: PlayerId, message: &ClientMessage) {
+        match message {
+            &ClientMessage::Pong => {
+                debug!("got pong from {}", player_id);
+                let client = self.clients.get_mut(&player_id).unwrap();
+                
+                match client.ping_sent_time {
+                    Some(ping_sent_time) => {
+                        let ping = time::get_time() - ping_sent_time;
+                        client.ping = Some(ping);
+
+                        let rtt_us = ping.num_microseconds().unwrap_or(i64::max_value());
+                        self.metrics.set_rtt(player_id, rtt_us as f64 / 1000000.0);
+                    }
+                    None =>
+                        warn!("received unwarranted pong from {}", player_id)
+                };
+
+                client.ping_sent_time = None;
+            }
+            &ClientMessage::WishConnect { ref name, color } => {
+                let client_state = self.clients[&player_id].state;
+
+                if client_state != ClientState::Connecting {
+                    warn!("connected player {} is trying to connect again, ignoring",
+                          player_id);
+                    return;
+                }
+
+                info!("player {} connected with name {}", player_id, name);
+
+                self.clients.get_mut(&player_id).unwrap().state = ClientState::Connected;
+                self.metrics.add_client(player_id, name.clone());
+                let game_info = self.game_info.clone();
+                self.send(player_id,
+                          &ServerMessage::AcceptConnect {
+                              your_id: player_id,
+                              game_info: game_info,
+                          });
+
+                let color = player::nearest_player_color(color);
+                let player_info = PlayerInfo::new(name.clone(), color);
+                self.record(InputLogEvent::PlayerJoin(player_id, player_info.clone()));
+
+                // This officially adds the player to the game state.
+                // At the beginning of the next tick, PlayerJoin messages will be sent out.
+                self.game_state.add_player(player_id, player_info);
+            }
+            &ClientMessage::PlayerInput(ref input)  => {
+                self.record(InputLogEvent::PlayerInput(player_id, input.clone()));
+                self.game_state.on_player_input(player_id, input);
+            }
+            &ClientMessage::Chat { ref text, team_only } => {
+                if self.clients[&player_id].state != ClientState::Connected {
+                    warn!("player {} is trying to chat before connecting, ignoring", player_id);
+                    return;
+                }
+
+                let text = text.chars()
+                               .filter(|c| !c.is_control())
+                               .take(net::MAX_CHAT_LEN)
+                               .collect::<String>();
+                let text = text.trim();
+                if text.is_empty() {
+                    return;
+                }
+
+                let now = time::get_time();
+                let chat_times = &mut self.clients.get_mut(&player_id).unwrap().chat_times;
+                while chat_times.front()
+                                .map_or(false, |&t| now - t > Duration::seconds(CHAT_WINDOW_S)) {
+                    chat_times.pop_front();
+                }
+                if chat_times.len() >= MAX_CHAT_MESSAGES {
+                    warn!("player {} is chatting too fast, dropping message", player_id);
+                    return;
+                }
+                chat_times.push_back(now);
+
+                info!("chat from player {}{}: {}", player_id,
+                      if team_only { " to team" } else { "" }, text);
+
+                // Sent out at the beginning of the next tick
+                self.game_state.on_player_chat(player_id, text.to_string(), team_only);
+            }
+            &ClientMessage::StartingTick { ref tick } => {
+                self.clients.get_mut(&player_id).unwrap().at_tick = Some(*tick);
+            }
+        }
+    }
+
+    pub fn run(&mut self) {
+        self.run_until(&AtomicBool::new(false));
+    }
+
+    /// Runs the server until the given flag is set, e.g. by another thread
+    pub fn run_until(&mut self, stop: &AtomicBool) {
+        let mut start_ns = clock_ticks::precise_time_ns();
+
+        while !stop.load(Ordering::Relaxed) {
+            // Is this how DDOS happens?
+            while self.service() {}
+            self.respond_to_discovery();
+            self.run_console_commands();
+            self.reload_changed_files();
+
+            {
+                // Start ticks
+                hprof::start_frame();
+                let mut r = false;
+                if self.tick_timer.next() {
+                    let tick_start_ns = clock_ticks::precise_time_ns();
+                    {
+                        let _g = hprof::enter("ticks");
+                        self.tick();
+                    }
+                    let duration_ns = clock_ticks::precise_time_ns() - tick_start_ns;
+                    self.metrics.record_tick(duration_ns as f64 / 1000000000.0);
+                    r = true;
+                }
+                hprof::end_frame();
+
+                if r && self.print_prof_timer.next_reset() {
+                    //hprof::profiler().print_timing();  
+
+                    if self.samples_tick_size > 0 {
+                        info!("average tick size over last {} ticks: {:.2} bytes, {:.2} kb/s",
+                              self.samples_tick_size,
+                              self.sum_tick_size as f64 / self.samples_tick_size as f64,
+                              self.sum_tick_size as f64 / (1000.0 * 5.0));
+                    }
+                    self.sum_tick_size = 0;
+                    self.samples_tick_size = 0;
+                }
+            }
+
+            if self.ping_timer.next_reset() {
+                self.send_pings();
+            }
+            if self.metrics_timer.next_reset() {
+                self.update_metrics();
+            }
+
+            thread::sleep_ms(0);
+
+            // Wall-clock time only decides when the next tick is run. The simulation itself
+            // always advances by a fixed duration per tick (see `GameState::tick`).
+            let new_start_ns = clock_ticks::precise_time_ns();
+            let delta_s = (new_start_ns - start_ns) as f32 / 1000000000.0;
+            self.tick_timer.add(delta_s);
+            self.print_prof_timer.add(delta_s);
+            self.ping_timer.add(delta_s);
+            self.metrics_timer.add(delta_s);
+            if let Some((_, ref mut timer)) = self.watcher {
+                timer.add(delta_s);
+            }
+            start_ns = new_start_ns;
+        }
+    }
+
+    fn tick(&mut self) {
+        self.game_state.tick();
+
+        if let Some(input_log) = self.input_log.as_mut() {
+            input_log.flush();
+        }
+
+        //debug!("sending tick {}", self.game_state.tick_number);
+
+        // Broadcast tick to clients
+        let _g = hprof::enter("broadcast");
+
+        // The snapshot is shared by all clients. Their ticks are assembled from it separately,
+        // which makes it possible to do delta encoding and stuff.
+        let snapshot = {
+            let _g = hprof::enter("snapshot");
+            Arc::new(self.game_state.take_snapshot())
+        };
+
+        let mut jobs = Vec::new();
+        for (&player_id, client) in self.clients.iter_mut() {
+            if client.state == ClientState::Connected {
+                jobs.push(EncodeJob {
+                    player_id: player_id,
+                    snapshot: snapshot.clone(),
+                    events: self.game_state.take_player_events(player_id),
+                    last_snapshot: client.last_snapshot.take(),
+                });
+            }
+        }
+
+        let encoded_ticks = {
+            let _g = hprof::enter("encode");
+            self.encoder_pool.encode_all(jobs)
+        };
+
+        let _g = hprof::enter("send");
+        for encoded_tick in encoded_ticks {
+            self.sum_tick_size += encoded_tick.data.len();
+            self.samples_tick_size += 1;
+            self.metrics.record_sent(encoded_tick.player_id, encoded_tick.data.len());
+
+            let client = self.clients.get_mut(&encoded_tick.player_id).unwrap();
+            self.transport.send(client.peer, net::Channel::Ticks as u8, Reliability::Reliable,
+                                &encoded_tick.data);
+            client.last_snapshot = Some(snapshot.clone());
+        }
+    }
+}
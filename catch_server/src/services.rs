@@ -1,8 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, BTreeMap};
 
 use ecs::ServiceManager;
+use rand::{SeedableRng, XorShiftRng};
 
-use shared::{EntityId, EntityTypeId, EntityTypes, TickNumber, PlayerId, GameEvent};
+use shared::{EntityId, EntityTypeId, EntityTypes, TickNumber, PlayerId, GameEvent, GameInfo,
+             Balance, Tuning};
 use shared::services::HasEvents;
 use shared::entities::NetEntities;
 
@@ -11,6 +13,15 @@ pub struct Services {
     // List of entity types by name
     pub entity_types: EntityTypes,
 
+    // Item values and other numbers for tuning the game
+    pub balance: Balance,
+
+    // Player movement constants and game rules
+    pub tuning: Tuning,
+
+    // Number of the tick that is being run
+    pub tick_number: TickNumber,
+
     // Tick duration in seconds
     pub tick_dur_s: f32,
 
@@ -21,11 +32,20 @@ pub struct Services {
     // `next_player_events`.  Each event in `next_events` is also stored for each player here.
     pub next_player_events: HashMap<PlayerId, Vec<GameEvent>>,
 
+    // Number of events created so far by variant, for the metrics. Events for all players
+    // are counted once, events queued for single players once per player.
+    pub event_counts: BTreeMap<&'static str, u64>,
+    pub player_event_counts: BTreeMap<&'static str, u64>,
+
     // Net entities
     pub net_entities: NetEntities,
 
     // Counter for creating net entities
     entity_id_counter: EntityId,
+
+    // All randomness in the simulation needs to come from here, so that it can be reproduced
+    // given the seed
+    pub rng: XorShiftRng,
 }
 
 impl HasEvents for Services {
@@ -40,27 +60,37 @@ impl HasEvents for Services {
         }
 
         self.next_events.push(event.clone());
+        *self.event_counts.entry(event.name()).or_insert(0) += 1;
     }
 }
 
 impl Services {
-    pub fn new(entity_types: EntityTypes) -> Services {
+    pub fn new(game_info: &GameInfo, seed: u64) -> Services {
         Services {
-            entity_types: entity_types,
+            entity_types: game_info.entity_types.clone(),
+            balance: game_info.balance.clone(),
+            tuning: game_info.tuning,
+            tick_number: 0,
             tick_dur_s: 0.0, // the correct duration is set by GameState::tick
             next_events: Vec::new(),
             next_player_events: HashMap::new(),
+            event_counts: BTreeMap::new(),
+            player_event_counts: BTreeMap::new(),
             net_entities: NetEntities::default(),
             entity_id_counter: 0,
+            rng: XorShiftRng::from_seed([0x193a6754, seed as u32, (seed >> 32) as u32,
+                                         0x9e3779b9]),
         }
     }
 
     pub fn prepare_for_tick<T: Iterator<Item=PlayerId>>
                            (&mut self,
-                            _number: TickNumber,
+                            number: TickNumber,
                             player_ids: T) {
         assert!(self.next_events.is_empty());
 
+        self.tick_number = number;
+
         let mut next_player_events = HashMap::new();
         for player_id in player_ids {
             next_player_events.insert(player_id, Vec::new()); 
@@ -85,6 +115,7 @@ impl Services {
     /// Queue an event only for one specific player
     pub fn add_player_event(&mut self, player_id: PlayerId, event: &GameEvent) {
         self.next_player_events.get_mut(&player_id).unwrap().push(event.clone());
+        *self.player_event_counts.entry(event.name()).or_insert(0) += 1;
     }
 
     pub fn entity_type_id(&self, type_name: &str) -> EntityTypeId {
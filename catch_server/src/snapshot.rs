@@ -0,0 +1,226 @@
+use std::io::Write;
+
+use rustc_serialize::{Encodable, Encoder};
+use bincode::SizeLimit;
+use bincode::rustc_serialize::encode_into;
+
+use shared::{PlayerId, EntityId, TickNumber, GameEvent};
+use shared::net_components::{NetComponents, ComponentType, ComponentsBitSet};
+
+/// Net components of an entity, each encoded separately so that the bytes can be copied into
+/// the ticks of every client and compared with the last tick instead of the components
+#[derive(Default)]
+struct EncodedComponents {
+    bit_set: ComponentsBitSet,
+
+    // End of each component's bytes in `data`, ordered by bit
+    ends: Vec<usize>,
+    data: Vec<u8>,
+}
+
+impl EncodedComponents {
+    fn new<I: Iterator<Item=ComponentType>>(component_types: I, components: &NetComponents)
+                                            -> EncodedComponents {
+        let mut component_types = component_types.collect::<Vec<_>>();
+        component_types.sort_by(|a, b| (*a as usize).cmp(&(*b as usize)));
+
+        let mut encoded = EncodedComponents::default();
+        for component_type in component_types {
+            encoded.bit_set |= 1 << component_type as usize;
+            encode_into(&ComponentEncoder(components, component_type), &mut encoded.data,
+                        SizeLimit::Infinite).unwrap();
+            encoded.ends.push(encoded.data.len());
+        }
+        encoded
+    }
+
+    fn get(&self, bit: usize) -> Option<&[u8]> {
+        if (self.bit_set >> bit) & 1 == 0 {
+            return None;
+        }
+
+        // Components are stored in the order of their bits
+        let index = (self.bit_set & ((1 << bit) - 1)).count_ones() as usize;
+        let start = if index == 0 { 0 } else { self.ends[index - 1] };
+        Some(&self.data[start..self.ends[index]])
+    }
+}
+
+/// Encodes one of the net components
+struct ComponentEncoder<'a>(&'a NetComponents, ComponentType);
+
+impl<'a> Encodable for ComponentEncoder<'a> {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        self.0.encode_component(self.1, s)
+    }
+}
+
+struct SnapshotEntity {
+    id: EntityId,
+    owner: PlayerId,
+
+    // What everybody gets to see of this entity
+    components: EncodedComponents,
+
+    // Overlay of the components that are only sent to the owner, if the entity type has any
+    owner_components: Option<EncodedComponents>,
+}
+
+impl SnapshotEntity {
+    /// The components that the given player sees
+    fn view(&self, player_id: PlayerId) -> EntityView {
+        EntityView {
+            components: &self.components,
+            owner_components: if self.owner == player_id {
+                self.owner_components.as_ref()
+            } else {
+                None
+            },
+        }
+    }
+}
+
+struct EntityView<'a> {
+    components: &'a EncodedComponents,
+    owner_components: Option<&'a EncodedComponents>,
+}
+
+impl<'a> EntityView<'a> {
+    fn bit_set(&self) -> ComponentsBitSet {
+        self.components.bit_set | self.owner_components.map_or(0, |c| c.bit_set)
+    }
+
+    fn get(&self, bit: usize) -> Option<&'a [u8]> {
+        self.components.get(bit).or_else(|| self.owner_components.and_then(|c| c.get(bit)))
+    }
+
+    /// Bits of the components whose bytes differ from the last view of the same entity
+    fn neq_components(&self, last: &EntityView) -> ComponentsBitSet {
+        let bit_set = self.bit_set();
+        assert!(bit_set == last.bit_set());
+
+        let mut neq_components = 0;
+        for bit in 0..16 {
+            if (bit_set >> bit) & 1 == 1 && self.get(bit) != last.get(bit) {
+                neq_components |= 1 << bit;
+            }
+        }
+        neq_components
+    }
+
+    /// Writes the components given by `bit_set` the same way as `NetComponents::encode` and
+    /// `NetComponents::delta_encode` do
+    fn write(&self, bit_set: ComponentsBitSet, data: &mut Vec<u8>) {
+        encode_into(&bit_set, data, SizeLimit::Infinite).unwrap();
+        for bit in 0..16 {
+            if (bit_set >> bit) & 1 == 1 {
+                data.write_all(self.get(bit).unwrap()).unwrap();
+            }
+        }
+    }
+}
+
+/// The state of all net entities after a tick. It is taken and encoded once per tick and then
+/// only read, so that the ticks of all clients can be assembled from it in parallel.
+pub struct WorldSnapshot {
+    pub tick_number: TickNumber,
+
+    // Ordered by id
+    entities: Vec<SnapshotEntity>,
+
+    forced_components: Vec<(EntityId, ComponentType)>,
+}
+
+impl WorldSnapshot {
+    pub fn new(tick_number: TickNumber) -> WorldSnapshot {
+        WorldSnapshot {
+            tick_number: tick_number,
+            entities: Vec::new(),
+            forced_components: Vec::new(),
+        }
+    }
+
+    /// Adds an entity whose `components` contain both the public and the owner components
+    pub fn add_entity(&mut self, id: EntityId, owner: PlayerId, components: &NetComponents,
+                      component_types: &[ComponentType],
+                      owner_component_types: &[ComponentType]) {
+        let owner_components = if !owner_component_types.is_empty() {
+            Some(EncodedComponents::new(owner_component_types.iter().cloned(), components))
+        } else {
+            None
+        };
+
+        self.entities.push(SnapshotEntity {
+            id: id,
+            owner: owner,
+            components: EncodedComponents::new(component_types.iter().cloned(), components),
+            owner_components: owner_components,
+        });
+    }
+
+    pub fn force_component(&mut self, id: EntityId, component_type: ComponentType) {
+        self.forced_components.push((id, component_type));
+    }
+
+    /// Needs to be called after all entities have been added
+    pub fn finish(&mut self) {
+        self.entities.sort_by(|a, b| a.id.cmp(&b.id));
+    }
+
+    /// Writes the tick for the given player in the format of `Tick` or, if we know the last
+    /// snapshot that the player got, `DeltaEncodeTick`
+    pub fn encode_tick(&self, player_id: PlayerId, events: &[GameEvent],
+                       last_snapshot: Option<&WorldSnapshot>, data: &mut Vec<u8>) {
+        encode_into(&self.tick_number, data, SizeLimit::Infinite).unwrap();
+        encode_into(&events, data, SizeLimit::Infinite).unwrap();
+
+        match last_snapshot {
+            Some(last_snapshot) => self.delta_encode_state(player_id, last_snapshot, data),
+            None => self.encode_state(player_id, data),
+        }
+
+        encode_into(&self.forced_components, data, SizeLimit::Infinite).unwrap();
+    }
+
+    fn encode_state(&self, player_id: PlayerId, data: &mut Vec<u8>) {
+        encode_into(&self.entities.len(), data, SizeLimit::Infinite).unwrap();
+
+        for entity in &self.entities {
+            let view = entity.view(player_id);
+            encode_into(&entity.id, data, SizeLimit::Infinite).unwrap();
+            view.write(view.bit_set(), data);
+        }
+    }
+
+    fn delta_encode_state(&self, player_id: PlayerId, last_snapshot: &WorldSnapshot,
+                          data: &mut Vec<u8>) {
+        // Entities that are new or changed, with the bits of the components that need to be
+        // written. Entities that are gone are left out, they are removed through events.
+        let mut changed = Vec::new();
+
+        let mut j = 0;
+        for entity in &self.entities {
+            while j < last_snapshot.entities.len() && last_snapshot.entities[j].id < entity.id {
+                j += 1;
+            }
+
+            let view = entity.view(player_id);
+            if j < last_snapshot.entities.len() && last_snapshot.entities[j].id == entity.id {
+                let last_view = last_snapshot.entities[j].view(player_id);
+                let neq_components = view.neq_components(&last_view);
+                if neq_components > 0 {
+                    changed.push((entity.id, view, neq_components));
+                }
+            } else {
+                let bit_set = view.bit_set();
+                changed.push((entity.id, view, bit_set));
+            }
+        }
+
+        encode_into(&changed.len(), data, SizeLimit::Infinite).unwrap();
+        for &(id, ref view, bit_set) in &changed {
+            encode_into(&id, data, SizeLimit::Infinite).unwrap();
+            view.write(bit_set, data);
+        }
+    }
+}
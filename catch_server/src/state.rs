@@ -1,24 +1,25 @@
 use std::f32;
-use std::collections::HashMap;
+use std::mem;
+use std::collections::BTreeMap;
 
 use ecs;
-use rand;
+use rand::Rng;
 use hprof;
 use na::{Vec2, Norm};
 
 use shared::{NEUTRAL_PLAYER_ID, TickNumber, GameInfo, DeathReason, GameEvent, PlayerId, PlayerInfo,
-             Item};
+             ItemSlot, Balance, Tuning};
 use shared::services::HasEvents;
-use shared::map::Map;
+use shared::entities::Behavior;
+use shared::map::{self, Map};
 use shared::net::TimedPlayerInput;
 
 use components::WallPosition;
 use systems::Systems;
 use services::Services;
+use snapshot::WorldSnapshot;
 use entities;
 
-const RESPAWN_TIME_S: f32 = 5.0;
-
 pub struct Player {
     // Has this player been sent its first tick yet?
     is_new: bool,
@@ -34,11 +35,6 @@ pub struct Player {
     respawn_time: Option<f32>, 
 }
 
-pub struct SpawnPoint {
-    position: Vec2<f32>,
-    size: Vec2<f32>,
-}
-
 impl Player {
     fn new(info: PlayerInfo) -> Player {
         Player {
@@ -58,154 +54,151 @@ impl Player {
 pub struct GameState {
     game_info: GameInfo,
     map: Map,
-    spawn_points: Vec<SpawnPoint>,
+    // Indices of the map objects that players can spawn in
+    spawn_points: Vec<usize>,
     pub world: ecs::World<Systems>, 
     pub tick_number: TickNumber,
     time_s: f32,
-    players: HashMap<PlayerId, Player>,
+    // Ordered by id, so that players are processed in the same order in every run
+    players: BTreeMap<PlayerId, Player>,
+    // Chat messages that are to be sent out in the next tick
+    pending_chat: Vec<(PlayerId, String, bool)>,
+    // Reloaded files that are to be applied in the next tick
+    pending_balance: Option<Balance>,
+    pending_tuning: Option<Tuning>,
+    pending_map: Option<Map>,
+}
+
+/// Indices of the map objects that players can spawn in
+fn spawn_points(map: &Map) -> Vec<usize> {
+    map.objects.iter().enumerate()
+       .filter(|&(_, object)| &object.type_str == "player_spawn")
+       .map(|(i, _)| i)
+       .collect()
 }
 
 impl GameState {
-    pub fn new(game_info: &GameInfo) -> GameState {
-        let map = Map::load(&game_info.map_name).unwrap();
+    /// Creates a game state whose simulation is fully determined by `seed` and the sequence of
+    /// player inputs, joins and leaves
+    pub fn new(game_info: &GameInfo, seed: u64) -> GameState {
+        let map = match game_info.load_map() {
+            Ok(map) => map,
+            Err(error) => panic!("could not load map: {}", error),
+        };
 
-        let spawn_points = map.objects.iter()
-               .filter(|object| &object.type_str == "player_spawn")
-               .map(|object| SpawnPoint {
-                        position: Vec2::new(object.x, object.y),
-                        size: Vec2::new(object.width, object.height),
-                    })
-               .collect();
+        GameState::with_map(game_info, map, seed)
+    }
 
-        let services = Services::new(game_info.entity_types.clone());
+    /// Creates a game state on the given map instead of loading the map from `game_info`
+    pub fn with_map(game_info: &GameInfo, map: Map, seed: u64) -> GameState {
+        let spawn_points = spawn_points(&map);
+
+        // Otherwise we would fail when the first player joins
+        if spawn_points.is_empty() {
+            panic!("map {} has no player_spawn objects", game_info.map_name);
+        }
+
+        let services = Services::new(game_info, seed);
+        let mut world = ecs::World::with_services(services);
+        world.systems.net_entity_system.set_entity_types(game_info.entity_types.clone());
 
         GameState {
             game_info: game_info.clone(),
             map: map,
             spawn_points: spawn_points,
-            world: ecs::World::with_services(services),
+            world: world,
             tick_number: 0,
             time_s: 0.0,
-            players: HashMap::new(),
+            players: BTreeMap::new(),
+            pending_chat: Vec::new(),
+            pending_balance: None,
+            pending_tuning: None,
+            pending_map: None,
         }
     }
 
     fn create_map_objects(&mut self) {
         for object in self.map.objects.iter() {
-            if &object.type_str == "item_spawn" {
+            if &object.type_str == "player_spawn" {
+                continue;
+            }
+
+            // Any entity type with a suitable behavior can be placed in maps
+            let behaviors = match self.game_info.entity_types.iter()
+                                      .find(|&&(ref name, _)| name == &object.type_str) {
+                Some(&(_, ref entity_type)) => entity_type.behaviors.clone(),
+                None => {
+                    warn!("ignoring {} with unknown type {} in map",
+                          object.source, object.type_str);
+                    continue;
+                }
+            };
+
+            if behaviors.contains(&Behavior::ItemSpawn) {
+                let items = match object.property("items").map(map::parse_item_pool) {
+                    Some(Ok(items)) => items,
+                    Some(Err(error)) => {
+                        warn!("{}: {}, using default items", object.source, error);
+                        Vec::new()
+                    }
+                    None => Vec::new(),
+                };
+                let respawn_time_s = match map::respawn_time_property(object) {
+                    Some(Ok(respawn_time_s)) => respawn_time_s,
+                    Some(Err(error)) => {
+                        warn!("{}: {}, using default respawn time", object.source, error);
+                        map::DEFAULT_ITEM_RESPAWN_S
+                    }
+                    None => map::DEFAULT_ITEM_RESPAWN_S,
+                };
+
                 let entity = entities::build_net(&object.type_str, 0, &mut self.world.data);
                 self.world.with_entity_data(&entity, |e, c| {
                     c.position[e].p = Vec2::new(object.x, object.y);
+                    c.item_spawn[e].items = items;
+                    c.item_spawn_state[e].respawn_time_s = respawn_time_s;
                 });
-            } else if &object.type_str == "bouncy_enemy" {
+            } else if behaviors.contains(&Behavior::BouncyEnemy) {
                 let entity = entities::build_net(&object.type_str, 0, &mut self.world.data);
+                let angle = self.world.services.rng.gen::<f32>() * f32::consts::PI * 2.0;
+                let attract = match map::attract_property(object) {
+                    Some(Ok(attract)) => attract,
+                    Some(Err(error)) => {
+                        warn!("{}: {}, choosing randomly", object.source, error);
+                        self.world.services.rng.gen::<bool>()
+                    }
+                    None => self.world.services.rng.gen::<bool>(),
+                };
                 self.world.with_entity_data(&entity, |e, c| {
                     c.position[e].p = Vec2::new(object.x, object.y);
-                    c.orientation[e].angle = rand::random::<f32>() * f32::consts::PI * 2.0;
-                    c.bouncy_enemy[e].attract = rand::random::<bool>();
+                    c.orientation[e].angle = angle;
+                    c.bouncy_enemy[e].attract = attract;
                 });
-            } else if &object.type_str == "player_spawn" {
             } else {
-                warn!("ignoring unknown entity type {} in map", object.type_str);
+                warn!("ignoring {} of type {}, which can't be placed in maps",
+                      object.source, object.type_str);
             }
         }
 
-        for &(pos_a, pos_b) in self.map.lines.iter() {
-            let entity = entities::build_net("wall_wood", 0, &mut self.world.data);
+        for line in self.map.lines.iter() {
+            let wall_type = line.wall_type().unwrap_or_else(|| {
+                warn!("{} has an unknown wall type, using wood", line.source);
+                "wall_wood".to_string()
+            });
+
+            let entity = entities::build_net(&wall_type, 0, &mut self.world.data);
             self.world.with_entity_data(&entity, |e, c| {
                 c.wall_position[e] = WallPosition {
-                    pos_a: pos_a,
-                    pos_b: pos_b
+                    pos_a: line.a,
+                    pos_b: line.b,
                 };
             });
         }
     }
 
-    // For adding test entities and stuff
     fn init_first_tick(&mut self) {
         self.create_map_objects();
 
-        /*let num_bouncies = 50;
-
-        for _ in 0..num_bouncies {
-            let entity = entities::build_net("bouncy_enemy", 0, &mut self.world.data);
-
-            // Pick a random non-blocked tile
-            let mut rx;
-            let mut ry;
-            loop {
-                rx = rand::random::<usize>() % self.map.width();
-                ry = rand::random::<usize>() % self.map.height();
-
-                if self.map.get_tile(LayerId::Block, rx, ry).is_none() {
-                    break;
-                }
-            }
-
-            let position = [(rx * self.map.tile_width()) as f64 +
-                            self.map.tile_width() as f64 / 2.0,
-                            (ry * self.map.tile_height()) as f64 +
-                            self.map.tile_height() as f64 / 2.0];
-
-            self.world.with_entity_data(&entity, |e, c| {
-                c.position[e].p = position;
-                c.orientation[e].angle = rand::random::<f64>() * f64::consts::PI * 2.0;
-            });
-        }*/
-        
-        let num_walls = 30;
-        let width = self.map.width_pixels() as f32;
-        let height = self.map.height_pixels() as f32;
-
-        /*for _ in 0..num_walls {
-            let entity = entities::build_net("wall_wood", 0, &mut self.world.data);
-
-            let ax = rand::random::<f32>() * width;
-            let ay = rand::random::<f32>() * height;
-            let phi = rand::random::<f32>() * f32::consts::PI * 2.0;
-            let r = rand::random::<f32>() * (400.0 - 32.0) + 32.0;
-            let bx = ax + phi.cos() * r;
-            let by = ay + phi.sin() * r;
-
-            //println!("wall at {},{},{},{}", ax, ay, bx, by);
-
-            self.world.with_entity_data(&entity, |e, c| {
-                c.wall_position[e] = WallPosition {
-                    pos_a: Vec2::new(ax, ay),
-                    pos_b: Vec2::new(bx, by), 
-                };
-            });
-        }*/
-
-        /*let entity = entities::build_net("wall_wood", 0, &mut self.world.data);
-        self.world.with_entity_data(&entity, |e, c| {
-            c.wall_position[e] = WallPosition {
-                pos_a: Vec2::new(0.0, 0.0),
-                pos_b: Vec2::new(width, 0.0)
-            };
-        });
-        let entity = entities::build_net("wall_wood", 0, &mut self.world.data);
-        self.world.with_entity_data(&entity, |e, c| {
-            c.wall_position[e] = WallPosition {
-                pos_a: Vec2::new(0.0, 0.0),
-                pos_b: Vec2::new(0.0, height)
-            };
-        });
-        let entity = entities::build_net("wall_wood", 0, &mut self.world.data);
-        self.world.with_entity_data(&entity, |e, c| {
-            c.wall_position[e] = WallPosition {
-                pos_a: Vec2::new(width, 0.0),
-                pos_b: Vec2::new(width, height)
-            };
-        });
-        let entity = entities::build_net("wall_wood", 0, &mut self.world.data);
-        self.world.with_entity_data(&entity, |e, c| {
-            c.wall_position[e] = WallPosition {
-                pos_a: Vec2::new(0.0, height), 
-                pos_b: Vec2::new(width, height)
-            };
-        });*/
-
         self.world.flush_queue();
     }
 
@@ -233,25 +226,26 @@ impl GameState {
 
         // Pick a random spawn point
         let position = {
-            let spawn_point = &self.spawn_points[rand::random::<usize>() %
-                                                 self.spawn_points.len()];
-            Vec2::new(spawn_point.position[0] + rand::random::<f32>() * spawn_point.size[0],
-                      spawn_point.position[1] + rand::random::<f32>() * spawn_point.size[1])
+            let rng = &mut self.world.services.rng;
+            let spawn_point = self.spawn_points[rng.gen::<usize>() % self.spawn_points.len()];
+            self.map.objects[spawn_point].point_in_area(rng.gen::<f32>(), rng.gen::<f32>())
         };
 
         // If we don't have a catcher right now, this player is lucky
         let is_catcher = self.current_catcher() == None; 
 
+        let loadout = self.world.services.balance.loadout();
+        let invulnerable_s = self.world.services.tuning.spawn_invulnerable_s;
+
         self.world.with_entity_data(&entity, |e, c| {
             c.position[e].p = position;
-            c.player_state[e].invulnerable_s = Some(2.5);
+            c.player_state[e].invulnerable_s = Some(invulnerable_s);
             c.player_state[e].is_catcher = is_catcher;
             c.player_state[e].has_shield = true;
 
-            // We'll equip a gun for now
-            c.player_state[e].equip(0, Item::Weapon { charges: 20 }); 
-            c.player_state[e].equip(1, Item::FragWeapon { charges: 2 });
-            c.player_state[e].equip(2, Item::BallSpawner { charges: 3 }); 
+            for (slot, item) in loadout.into_iter().enumerate() {
+                c.player_state[e].equip(slot as ItemSlot, item);
+            }
         });
 
         entity
@@ -261,6 +255,64 @@ impl GameState {
         &self.players[&id].info
     }
 
+    /// Drops the events that were queued for players in the last tick. This needs to be done
+    /// when there are no clients to send the events to, e.g. when replaying an input log.
+    pub fn clear_player_events(&mut self) {
+        for events in self.world.services.next_player_events.values_mut() {
+            events.clear();
+        }
+    }
+
+    /// Takes the events that were queued for the player in the last tick
+    pub fn take_player_events(&mut self, id: PlayerId) -> Vec<GameEvent> {
+        mem::replace(self.world.services.next_player_events.get_mut(&id).unwrap(), Vec::new())
+    }
+
+    /// Number of net entities of each type
+    pub fn entity_counts(&self) -> Vec<(String, usize)> {
+        self.world.systems.net_entity_system.count_by_type(&self.world.data)
+    }
+
+    /// Number of events created so far for all players by variant
+    pub fn event_counts(&self) -> &BTreeMap<&'static str, u64> {
+        &self.world.services.event_counts
+    }
+
+    /// Number of events queued so far for single players by variant, counted once per player
+    pub fn player_event_counts(&self) -> &BTreeMap<&'static str, u64> {
+        &self.world.services.player_event_counts
+    }
+
+    /// Takes a snapshot of the net entities after the last tick, from which the ticks sent to
+    /// clients are built
+    pub fn take_snapshot(&mut self) -> WorldSnapshot {
+        self.world.systems.net_entity_system.take_snapshot(self.tick_number,
+                                                           &mut self.world.data)
+    }
+
+    /// Prints the state of every player, for inspection when replaying
+    pub fn log_summary(&mut self) {
+        info!("state after tick {} ({:.2}s):", self.tick_number, self.time_s);
+
+        let world = &mut self.world;
+        for (&player_id, player) in self.players.iter() {
+            let state = player.entity.and_then(|entity| {
+                world.data.with_entity_data(&entity, |e, c| {
+                    (c.position[e].p, c.player_state[e].is_catcher)
+                })
+            });
+
+            info!("player {} ({}): score {}, deaths {}, {}",
+                  player_id, player.info.name,
+                  player.info.stats.score, player.info.stats.deaths,
+                  match state {
+                      Some((p, true)) => format!("catcher at {:?}", p),
+                      Some((p, false)) => format!("alive at {:?}", p),
+                      None => "dead".to_string(),
+                  });
+        }
+    }
+
     pub fn on_player_input(&mut self,
                            id: PlayerId,
                            input: &TimedPlayerInput) {
@@ -271,6 +323,33 @@ impl GameState {
         }
     }
 
+    /// Queues a chat message. With `team_only`, it only goes to players of the same color.
+    pub fn on_player_chat(&mut self, id: PlayerId, text: String, team_only: bool) {
+        self.pending_chat.push((id, text, team_only));
+    }
+
+    /// Replaces the balance table. The change takes effect at the start of the next tick, when
+    /// it is also sent to the clients.
+    pub fn set_balance(&mut self, balance: Balance) {
+        self.pending_balance = Some(balance);
+    }
+
+    /// Replaces the tuning at the start of the next tick
+    pub fn set_tuning(&mut self, tuning: Tuning) {
+        self.pending_tuning = Some(tuning);
+    }
+
+    /// Replaces the map at the start of the next tick. Everything that was created from the old
+    /// map is removed and created again from the new one, while players keep their entities.
+    pub fn set_map(&mut self, map: Map) -> Result<(), String> {
+        if spawn_points(&map).is_empty() {
+            return Err("the new map has no player_spawn objects".to_string());
+        }
+
+        self.pending_map = Some(map);
+        Ok(())
+    }
+
     fn current_catcher(&mut self) -> Option<PlayerId> {
         for (player_id, player) in self.players.iter() {
             if let Some(entity) = player.entity {
@@ -310,6 +389,14 @@ impl GameState {
         // Send out a table of player stats (we probably don't need to do this every frame)
         self.tick_replicate_player_stats();
 
+        // Deliver the chat messages that arrived since the last tick
+        self.tick_send_chat();
+
+        // Switch to reloaded files
+        self.tick_apply_balance();
+        self.tick_apply_tuning();
+        self.tick_apply_map();
+
         // Create some initial entities, e.g. from the map specified in self.game_info
         if self.tick_number == 1 {
             self.init_first_tick();
@@ -385,6 +472,85 @@ impl GameState {
         }
     }
 
+    fn tick_send_chat(&mut self) {
+        for (sender_id, text, team_only) in self.pending_chat.drain(..) {
+            // The sender may have left in the meantime
+            let sender_color = match self.players.get(&sender_id) {
+                Some(sender) => sender.info.color,
+                None => continue,
+            };
+
+            let event = GameEvent::Chat {
+                player_id: sender_id,
+                text: text,
+                team_only: team_only,
+            };
+            for (&player_id, player) in self.players.iter() {
+                if !team_only || player.info.color == sender_color {
+                    self.world.services.add_player_event(player_id, &event);
+                }
+            }
+        }
+    }
+
+    fn tick_apply_balance(&mut self) {
+        if let Some(balance) = self.pending_balance.take() {
+            info!("applying new balance table in tick {}", self.tick_number);
+
+            self.world.services.balance = balance.clone();
+            self.game_info.balance = balance.clone();
+            self.world.services.add_event(&GameEvent::UpdateBalance(balance));
+        }
+    }
+
+    fn tick_apply_tuning(&mut self) {
+        if let Some(tuning) = self.pending_tuning.take() {
+            info!("applying new tuning in tick {}", self.tick_number);
+
+            self.world.services.tuning = tuning;
+            self.game_info.tuning = tuning;
+            self.world.services.add_event(&GameEvent::UpdateTuning(tuning));
+        }
+    }
+
+    fn tick_apply_map(&mut self) {
+        let map = match self.pending_map.take() {
+            Some(map) => map,
+            None => return,
+        };
+
+        info!("applying reloaded map in tick {}", self.tick_number);
+
+        self.spawn_points = spawn_points(&map);
+        self.map = map;
+
+        // In the first tick, the map objects are yet to be created
+        if self.tick_number == 1 {
+            return;
+        }
+
+        // Everything that is not owned by a player was created from the map or by item spawns.
+        // Sort by id, so that the removal events are always in the same order.
+        let mut net_entities = self.world.services.net_entities.iter()
+                                   .map(|(&id, &entity)| (id, entity))
+                                   .collect::<Vec<_>>();
+        net_entities.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (_, entity) in net_entities {
+            let owner = self.world.with_entity_data(&entity, |e, c| c.net_entity[e].owner)
+                                  .unwrap();
+            if owner == NEUTRAL_PLAYER_ID {
+                entities::remove_net(entity, &mut self.world.data);
+            }
+        }
+        self.world.flush_queue();
+
+        self.create_map_objects();
+        self.world.flush_queue();
+
+        self.world.services.add_event(&GameEvent::ReloadMap);
+    }
+
     fn tick_remove_disconnected_players(&mut self) {
         let mut remove = Vec::new();
         for (&player_id, player) in self.players.iter_mut() {
@@ -414,7 +580,8 @@ impl GameState {
                                     .map(|(&id, _)| id)
                                     .collect::<Vec<_>>();
             if is_catcher && !alive_players.is_empty() {
-                let chosen_one = alive_players[rand::random::<usize>() % alive_players.len()];
+                let chosen_one = alive_players[self.world.services.rng.gen::<usize>() %
+                                               alive_players.len()];
 
                 self.world.with_entity_data(&self.players[&chosen_one].entity.unwrap(), |e, c| {
                     assert!(!c.player_state[e].is_catcher);
@@ -560,7 +727,7 @@ impl GameState {
             {
                 let player = self.players.get_mut(&player_id).unwrap();
                 player.entity = None;
-                player.respawn_time = Some(RESPAWN_TIME_S);
+                player.respawn_time = Some(self.world.services.tuning.respawn_time_s);
             };
 
             entities::remove_net(player_entity, &mut self.world.data);
@@ -1,15 +1,26 @@
+use std::f32;
+use std::collections::HashSet;
+
 use hprof;
-use ecs::{System, Process, Aspect, EntityData, DataHelper};
-use na::Norm;
+use ecs;
+use ecs::entity::IndexedEntity;
+use ecs::{System, Process, Aspect, EntityData, EntityIter, DataHelper};
+use na::{Vec2, Norm};
 
 use shared::math;
 use shared::util::CachedAspect;
+use shared::grid::{Grid, WallGrid};
 use shared::movement::{self, WallInteractionType};
 
 use components::{Components, Shape}; 
 use services::Services;
 use systems::wall_interactions::ConstWallInteraction;
 
+// Cell size of the broadphase grid for dynamic entities
+const BROADPHASE_CELL_SIZE: f32 = 32.0;
+
+type Broadphase = Grid<ecs::Entity, IndexedEntity<Components>>;
+
 pub enum InteractionResponse {
     None,
     DisplaceNoOverlap,  
@@ -30,7 +41,7 @@ pub trait Interaction {
 
 pub struct InteractionSystem {
     /// Walls in the map
-    wall_aspect: CachedAspect<Components>,
+    walls: WallGrid<Components>,
 
     /// Interactions between two different entity types
     interactions: Vec<(CachedAspect<Components>, CachedAspect<Components>, Box<Interaction>)>,
@@ -46,7 +57,7 @@ impl InteractionSystem {
             self_interactions: Vec<(Aspect<Components>, Box<Interaction>)>)
             -> InteractionSystem {
         InteractionSystem {
-            wall_aspect: CachedAspect::new(wall_aspect),
+            walls: WallGrid::new(wall_aspect),
             interactions:
                 interactions.into_iter()
                             .map(|(a, b, i)| (CachedAspect::new(a), CachedAspect::new(b), i))
@@ -59,38 +70,79 @@ impl InteractionSystem {
     }
 
     pub fn tick(&self, data: &mut DataHelper<Components, Services>) {
-        // n^2 kinda loop over all entity pairs that can interact
+        // Only pairs of entities that are close to each other in a uniform grid are tested for
+        // overlap. The grid is rebuilt every tick, since these entities are moving.
         
         let _g = hprof::enter("interaction");
 
         for &(ref aspect_a, ref aspect_b, ref interaction) in self.interactions.iter() {
+            let broadphase = InteractionSystem::build_broadphase(aspect_b, data);
+
             for entity_a in aspect_a.iter() {
-                for entity_b in aspect_b.iter() {
+                for entity_b in InteractionSystem::candidates(entity_a, &broadphase, data) {
                     InteractionSystem::try_interaction(&**interaction, entity_a, entity_b,
-                                                       &self.wall_aspect, data);
+                                                       &self.walls, data);
                 }
             }
         }
 
         for &(ref aspect, ref interaction) in self.self_interactions.iter() {
+            let broadphase = InteractionSystem::build_broadphase(aspect, data);
+
             for entity_a in aspect.iter() {
-                for entity_b in aspect.iter() {
+                for entity_b in InteractionSystem::candidates(entity_a, &broadphase, data) {
                     if entity_a.index() <= entity_b.index() {
                         // Don't perform interactions twice
                         continue;
                     }
 
                     InteractionSystem::try_interaction(&**interaction, entity_a, entity_b,
-                                                       &self.wall_aspect, data);
+                                                       &self.walls, data);
+                }
+            }
+        }
+    }
+
+    /// Axis-aligned bounding box of an entity's shape
+    fn bounds(e: EntityData<Components>, c: &Components) -> (Vec2<f32>, Vec2<f32>) {
+        // Rotated rectangles can reach further out than their radius
+        let r = c.shape[e].radius() * f32::consts::SQRT_2;
+        let p = c.position[e].p;
+        (Vec2::new(p.x - r, p.y - r), Vec2::new(p.x + r, p.y + r))
+    }
+
+    fn build_broadphase(aspect: &CachedAspect<Components>,
+                        data: &DataHelper<Components, Services>) -> Broadphase {
+        let mut broadphase = Grid::new(BROADPHASE_CELL_SIZE);
+        for e in aspect.iter() {
+            let (min, max) = InteractionSystem::bounds(e, &data.components);
+            broadphase.insert_aabb(**e, min, max, || (*e).__clone());
+        }
+        broadphase
+    }
+
+    /// Entities in the broadphase grid that are close enough to `e` to possibly overlap
+    fn candidates<'a>(e: EntityData<Components>, broadphase: &'a Broadphase,
+                      data: &DataHelper<Components, Services>)
+                      -> Vec<EntityData<'a, Components>> {
+        let (min, max) = InteractionSystem::bounds(e, &data.components);
+
+        let mut seen = HashSet::new();
+        let mut result = Vec::new();
+        for values in broadphase.cells_in_aabb(min, max) {
+            for candidate in EntityIter::Map(values.values()) {
+                if seen.insert(**candidate) {
+                    result.push(candidate);
                 }
             }
         }
+        result
     }
 
     fn try_interaction(interaction: &Interaction,
                        e_a: EntityData<Components>,
                        e_b: EntityData<Components>,
-                       wall_aspect: &CachedAspect<Components>,
+                       walls: &WallGrid<Components>,
                        c: &mut DataHelper<Components, Services>) {
         if interaction.condition(e_a, e_b, c) &&
            InteractionSystem::overlap(e_a, e_b, &c.components) {
@@ -110,9 +162,9 @@ impl InteractionSystem {
                     let delta_no_overlap = delta.normalize() * (min_dist_no_overlap - cur_dist);
                     let interaction = ConstWallInteraction(WallInteractionType::Flip);
                     movement::move_entity(e_a, delta_no_overlap * -0.5, &interaction,
-                                          &wall_aspect, c);
+                                          walls, c);
                     movement::move_entity(e_b, delta_no_overlap * 0.5, &interaction,
-                                          &wall_aspect, c);
+                                          walls, c);
                 }
             }
         }
@@ -160,7 +212,7 @@ impl System for InteractionSystem {
 
     fn activated(&mut self, entity: &EntityData<Components>, components: &Components,
                  _: &mut Services) {
-        self.wall_aspect.activated(entity, components);
+        self.walls.activated(entity, components);
         for &mut (ref mut aspect_a, ref mut aspect_b, _) in self.interactions.iter_mut() {
             aspect_a.activated(entity, components);
             aspect_b.activated(entity, components);
@@ -172,7 +224,7 @@ impl System for InteractionSystem {
 
     fn reactivated(&mut self, entity: &EntityData<Components>, components: &Components,
                    _: &mut Services) {
-        self.wall_aspect.reactivated(entity, components);
+        self.walls.reactivated(entity, components);
         for &mut (ref mut aspect_a, ref mut aspect_b, _) in self.interactions.iter_mut() {
             aspect_a.reactivated(entity, components);
             aspect_b.reactivated(entity, components);
@@ -184,7 +236,7 @@ impl System for InteractionSystem {
 
     fn deactivated(&mut self, entity: &EntityData<Components>, components: &Components,
                    _: &mut Services) {
-        self.wall_aspect.deactivated(entity, components);
+        self.walls.deactivated(entity, components);
         for &mut (ref mut aspect_a, ref mut aspect_b, _) in self.interactions.iter_mut() {
             aspect_a.deactivated(entity, components);
             aspect_b.deactivated(entity, components);
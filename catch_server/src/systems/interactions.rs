@@ -3,7 +3,8 @@ use std::f32;
 use ecs::{EntityData, DataHelper};
 use na::{Vec2, Norm};
 
-use shared::{GameEvent, DeathReason, NEUTRAL_PLAYER_ID};
+use shared::{GameEvent, DeathReason, NEUTRAL_PLAYER_ID, NUM_ITEM_SLOTS};
+use shared::balance::PickupRule;
 use shared::services::HasEvents;
 
 use entities;
@@ -96,22 +97,64 @@ impl Interaction for BouncyEnemyInteraction {
     }
 }
 
-/// Picking up items
+/// Picking up items, according to the pickup rule of the balance table
 pub struct PlayerItemInteraction;
 impl Interaction for PlayerItemInteraction {
+    fn condition(&self,
+                 player: EntityData<Components>, item: EntityData<Components>,
+                 data: &mut DataHelper<Components, Services>) -> bool {
+        if data.player_controller[player].dropped_item == Some(**item) {
+            return false;
+        }
+
+        data.services.balance.pickup_rule != PickupRule::IgnoreWhenFull ||
+        data.full_player_state[player].hidden_item.is_none()
+    }
     fn apply(&self,
              player: EntityData<Components>, item: EntityData<Components>,
              data: &mut DataHelper<Components, Services>) -> InteractionResponse {
-        data.full_player_state[player].hidden_item = Some(data.item[item].clone());
-        entities::remove_net(**item, data);
-
         let owner = data.net_entity[player].owner;
         let position = data.position[item].p;
+        let new_item = data.item[item].clone();
+        let rule = data.services.balance.pickup_rule;
+
+        let empty_slot = if rule == PickupRule::AutoEquip {
+            (0..NUM_ITEM_SLOTS).find(|&slot| data.player_state[player].get_item(slot).is_none())
+        } else {
+            None
+        };
+
         data.services.add_event(&GameEvent::PlayerTakeItem {
            player_id: owner,
            position: position,
         });
 
+        if let Some(slot) = empty_slot {
+            data.player_state[player].equip(slot, new_item.clone());
+            entities::remove_net(**item, data);
+
+            let player_position = data.position[player].p;
+            data.services.add_event(&GameEvent::PlayerEquipItem {
+                player_id: owner,
+                position: player_position,
+                item: new_item,
+            });
+            return InteractionResponse::None;
+        }
+
+        let old_item = data.full_player_state[player].hidden_item.take();
+        data.full_player_state[player].hidden_item = Some(new_item);
+
+        match old_item {
+            Some(old_item) => {
+                // Leave the carried item in place of the one that was taken. The item spawn
+                // doesn't respawn until this one is taken, too.
+                data.item[item] = old_item;
+                data.player_controller[player].dropped_item = Some(**item);
+            }
+            None => entities::remove_net(**item, data),
+        }
+
         InteractionResponse::None
     }
 }
@@ -1,16 +1,14 @@
-use rand;
+use rand::Rng;
 use hprof;
 use ecs::{Aspect, Process, System, BuildData, DataHelper};
 
-use shared::Item;
+use shared::TickNumber;
 use shared::util::CachedAspect;
 
 use components::Components;
 use services::Services;
 use entities;
 
-const COOLDOWN_S: f32 = 5.0;
-
 pub struct ItemSpawnSystem {
     aspect: CachedAspect<Components>,
 }
@@ -25,9 +23,11 @@ impl ItemSpawnSystem {
     pub fn tick(&self, data: &mut DataHelper<Components, Services>) {
         let _g = hprof::enter("item spawn");
 
+        let tick_number = data.services.tick_number;
+
         // Iterate all item spawn entities
         for e in self.aspect.iter() {
-            // Did our spawned entity die?
+            // Was our spawned item taken?
             let spawned_entity_died =
                 match data.item_spawn[e].spawned_entity {
                     Some(entity) => 
@@ -36,48 +36,46 @@ impl ItemSpawnSystem {
                         false
                 };
             if spawned_entity_died {
-                assert!(data.item_spawn[e].cooldown_s.is_none());
+                assert!(data.item_spawn_state[e].respawn_tick.is_none());
+                let respawn_ticks = (data.item_spawn_state[e].respawn_time_s /
+                                     data.services.tick_dur_s).ceil() as TickNumber;
                 data.item_spawn[e].spawned_entity = None; 
-                data.item_spawn[e].cooldown_s = Some(COOLDOWN_S);
+                data.item_spawn_state[e].respawn_tick = Some(tick_number + respawn_ticks);
+            }
+
+            if data.item_spawn[e].spawned_entity.is_some() {
+                continue;
             }
 
-            // Check cooldown
-            let have_cooldown = match data.item_spawn[e].cooldown_s {
-                Some(cooldown_s) => {
-                    let cooldown_s = cooldown_s - data.services.tick_dur_s;
-                    if cooldown_s <= 0.0 { 
-                        data.item_spawn[e].cooldown_s = None;
-                        false
-                    } else {
-                        data.item_spawn[e].cooldown_s = Some(cooldown_s);
-                        true
-                    }
-                }
-                None => false
-            };
-            
             // Should we spawn a new item?
-            if data.item_spawn[e].spawned_entity.is_none() && !have_cooldown {
-                let item_entity = entities::build_net_custom("item", 0, data,
-                    |item_e: BuildData<Components>, c: &mut Components| {
-                        let choices = vec![
-                                           Item::Weapon { charges: 10 },
-                                           Item::FragWeapon { charges: 5 },
-                                           Item::BallSpawner { charges: 3 },
-                                           Item::Shield,
-                                          ];
-                        let item = choices[rand::random::<usize>() % choices.len()].clone();
+            match data.item_spawn_state[e].respawn_tick {
+                Some(respawn_tick) if respawn_tick > tick_number => continue,
+                _ => (),
+            }
 
-                        c.item.add(&item_e, item);
-                    });
+            let r = data.services.rng.gen::<f32>();
+            let item = if data.item_spawn[e].items.is_empty() {
+                data.services.balance.choose_spawn_item(r)
+            } else {
+                data.services.balance.choose_pool_item(&data.item_spawn[e].items, r)
+            };
+            let item = match item {
+                Some(item) => item,
+                None => continue, // All weights are zero
+            };
 
-                data.with_entity_data(&item_entity, |item_e, c| {
-                    // Spawn at our position
-                    c.position[item_e].p = c.position[e].p;
+            let item_entity = entities::build_net_custom("item", 0, data,
+                |item_e: BuildData<Components>, c: &mut Components| {
+                    c.item.add(&item_e, item);
                 });
 
-                data.item_spawn[e].spawned_entity = Some(item_entity);
-            }
+            data.with_entity_data(&item_entity, |item_e, c| {
+                // Spawn at our position
+                c.position[item_e].p = c.position[e].p;
+            });
+
+            data.item_spawn[e].spawned_entity = Some(item_entity);
+            data.item_spawn_state[e].respawn_tick = None;
         }
     }
 }
@@ -2,6 +2,7 @@ use hprof;
 use ecs::{Aspect, Process, System, DataHelper, EntityData};
 
 use shared::util::CachedAspect;
+use shared::grid::WallGrid;
 use shared::movement;
 
 use components::Components;
@@ -10,7 +11,7 @@ use services::Services;
 pub type WallInteraction = movement::WallInteraction<Components, Services>;
 
 pub struct MovementSystem {
-    wall_aspect: CachedAspect<Components>,
+    walls: WallGrid<Components>,
     aspects: Vec<(CachedAspect<Components>, Box<WallInteraction>)>,
 }
 
@@ -19,7 +20,7 @@ impl MovementSystem {
                aspects: Vec<(Aspect<Components>, Box<WallInteraction>)>)
                -> MovementSystem {
         MovementSystem {
-            wall_aspect: CachedAspect::new(wall_aspect),
+            walls: WallGrid::new(wall_aspect),
             aspects:
                 aspects.into_iter()
                        .map(|(a, i)| (CachedAspect::new(a), i))
@@ -34,7 +35,7 @@ impl MovementSystem {
             for e in aspect.iter() {
                 let delta = data.linear_velocity[e].v * data.services.tick_dur_s;
                 movement::move_entity(e, delta, &**interaction,
-                                      &self.wall_aspect, data);
+                                      &self.walls, data);
             }
         }
     }
@@ -46,7 +47,7 @@ impl System for MovementSystem {
 
     fn activated(&mut self, entity: &EntityData<Components>, components: &Components,
                  _: &mut Services) {
-        self.wall_aspect.activated(entity, components);
+        self.walls.activated(entity, components);
         for &mut (ref mut aspect, _) in self.aspects.iter_mut() {
             aspect.activated(entity, components);
         }
@@ -54,7 +55,7 @@ impl System for MovementSystem {
 
     fn reactivated(&mut self, entity: &EntityData<Components>, components: &Components,
                    _: &mut Services) {
-        self.wall_aspect.reactivated(entity, components);
+        self.walls.reactivated(entity, components);
         for &mut (ref mut aspect, _) in self.aspects.iter_mut() {
             aspect.reactivated(entity, components);
         }
@@ -62,7 +63,7 @@ impl System for MovementSystem {
 
     fn deactivated(&mut self, entity: &EntityData<Components>, components: &Components,
                    _: &mut Services) {
-        self.wall_aspect.deactivated(entity, components);
+        self.walls.deactivated(entity, components);
         for &mut (ref mut aspect, _) in self.aspects.iter_mut() {
             aspect.deactivated(entity, components);
         }
@@ -2,12 +2,12 @@ use std::iter::Iterator;
 
 use ecs::{Aspect, Process, System, EntityData, DataHelper};
 
-use shared;
 use shared::net_components::NetComponents;
-use shared::{EntityTypes, PlayerId, GameEvent, TickState};
+use shared::{EntityTypes, PlayerId, GameEvent, TickNumber};
 use shared::util::CachedAspect;
 
 use entities;
+use snapshot::WorldSnapshot;
 use components::Components;
 use services::Services;
 
@@ -20,10 +20,15 @@ impl NetEntitySystem {
     pub fn new(aspect: Aspect<Components>) -> NetEntitySystem {
         NetEntitySystem {
             aspect: CachedAspect::new(aspect),
-            entity_types: shared::entities::all_entity_types(),
+            entity_types: Vec::new(),
         }
     }
 
+    /// Needs to be called before any entities are created
+    pub fn set_entity_types(&mut self, entity_types: EntityTypes) {
+        self.entity_types = entity_types;
+    }
+
     /// Remove all entities owned by `player_id`
     pub fn remove_player_entities(&mut self,
                                   player_id: PlayerId,
@@ -47,39 +52,47 @@ impl NetEntitySystem {
         }
     }
 
-    /// Write the current state into a TickState
-    pub fn store_in_tick_state(&self, player_id: PlayerId, tick_state: &mut TickState,
-                               c: &mut DataHelper<Components, Services>) {
-        let mut forced_components = Vec::new();
+    /// Number of net entities of each type
+    pub fn count_by_type(&self, c: &DataHelper<Components, Services>) -> Vec<(String, usize)> {
+        let mut counts = self.entity_types.iter()
+                                          .map(|&(ref name, _)| (name.clone(), 0))
+                                          .collect::<Vec<_>>();
+        for e in self.aspect.iter() {
+            counts[c.net_entity[e].type_id as usize].1 += 1;
+        }
+        counts
+    }
+
+    /// Takes a snapshot of the current state of all net entities. Forced components are only
+    /// included in one snapshot.
+    pub fn take_snapshot(&self, tick_number: TickNumber,
+                         c: &mut DataHelper<Components, Services>) -> WorldSnapshot {
+        let mut snapshot = WorldSnapshot::new(tick_number);
 
         for e in self.aspect.iter() {
             let &(_, ref entity_type) =
                 &self.entity_types[c.net_entity[e].type_id as usize];
             let net_id = c.net_entity[e].id;
 
-            let net_components = 
-                if player_id == c.net_entity[e].owner {
-                    // Some components only need to be sent to the owner of the net entity
-                    let it = entity_type.component_types.iter()
-                                        .chain(entity_type.owner_component_types.iter())
-                                        .map(|c| *c);
-                    NetComponents::from_entity(it, e, c)
-                } else {
-                    let it = entity_type.component_types.iter().map(|c| *c);
-                    NetComponents::from_entity(it, e, c)
-                };
+            let it = entity_type.component_types.iter()
+                                .chain(entity_type.owner_component_types.iter())
+                                .map(|c| *c);
+            let net_components = NetComponents::from_entity(it, e, c);
 
-            tick_state.entities.push((net_id, net_components));
+            // Each component is encoded once here, no matter how many players there are
+            snapshot.add_entity(net_id, c.net_entity[e].owner, &net_components,
+                                &entity_type.component_types,
+                                &entity_type.owner_component_types);
 
             // Mark forced components
             for forced_component in &c.server_net_entity[e].forced_components {
-                forced_components.push((net_id, *forced_component));
+                snapshot.force_component(net_id, *forced_component);
             }
             c.server_net_entity[e].forced_components = Vec::new();
         }
-        tick_state.sort();
+        snapshot.finish();
 
-        tick_state.forced_components = forced_components;
+        snapshot
     }
 }
 
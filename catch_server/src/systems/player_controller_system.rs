@@ -1,5 +1,5 @@
 use ecs::{Aspect, Process, System, DataHelper, EntityData};
-use na::Vec2;
+use na::{Vec2, Norm};
 
 use shared::{ItemSlot, GameEvent, Item, NUM_ITEM_SLOTS};
 use shared::movement;
@@ -7,17 +7,16 @@ use shared::net::TimedPlayerInput;
 use shared::player::PlayerInputKey;
 use shared::services::HasEvents;
 use shared::util::CachedAspect;
+use shared::grid::WallGrid;
 
-use components::Components;
+use components::{Components, Projectile};
 use services::Services;
 use entities;
 
-const PROJECTILE_SPEED: f32 = 200.0; 
-
 /// System for interpreting player input on the server side
 pub struct PlayerControllerSystem {
     player_aspect: CachedAspect<Components>,
-    wall_aspect: CachedAspect<Components>,
+    walls: WallGrid<Components>,
 }
 
 impl PlayerControllerSystem {
@@ -25,7 +24,7 @@ impl PlayerControllerSystem {
                wall_aspect: Aspect<Components>) -> PlayerControllerSystem {
         PlayerControllerSystem {
             player_aspect: CachedAspect::new(player_aspect),
-            wall_aspect: CachedAspect::new(wall_aspect),
+            walls: WallGrid::new(wall_aspect),
         }
     }
 
@@ -35,12 +34,35 @@ impl PlayerControllerSystem {
             data.player_controller[player].inputs.clear();
 
             let owner = data.net_entity[player].owner;
+            let tuning = data.services.tuning;
 
             for input in &inputs {
-                movement::run_player_movement_input(player, owner, input, &self.wall_aspect, data);
+                movement::run_player_movement_input(player, owner, input, &tuning, &self.walls,
+                                                    data);
 
                 self.run_item_input(input, player, data);
             }
+
+            self.forget_dropped_item(player, data);
+        }
+    }
+
+    /// Allows picking up the item that the player dropped again once they have stepped off it
+    fn forget_dropped_item(&self, player: EntityData<Components>,
+                           data: &mut DataHelper<Components, Services>) {
+        let dropped_item = match data.player_controller[player].dropped_item {
+            Some(dropped_item) => dropped_item,
+            None => return,
+        };
+
+        let p = data.position[player].p;
+        let radius = data.shape[player].radius();
+        let still_touching = data.with_entity_data(&dropped_item, |item, c| {
+            (c.position[item].p - p).norm() <= radius + c.shape[item].radius()
+        });
+
+        if still_touching != Some(true) {
+            data.player_controller[player].dropped_item = None;
         }
     }
 
@@ -120,6 +142,8 @@ impl PlayerControllerSystem {
         let p = c.position[e].p;
         let angle = c.orientation[e].angle;
         let item = c.player_state[e].get_item(slot).unwrap().item.clone();
+        let projectile_speed = c.services.balance.projectile_speed;
+        let frag_lifetime_s = c.services.balance.frag_lifetime_s;
 
         let new_item = match item {
             Item::Weapon { charges } => {
@@ -129,8 +153,8 @@ impl PlayerControllerSystem {
                     c.position[projectile_e].p = p;
                     c.orientation[projectile_e].angle = angle;
                     c.linear_velocity[projectile_e].v = Vec2::new(
-                        angle.cos() * PROJECTILE_SPEED,
-                        angle.sin() * PROJECTILE_SPEED
+                        angle.cos() * projectile_speed,
+                        angle.sin() * projectile_speed
                     );
                 });
 
@@ -147,9 +171,10 @@ impl PlayerControllerSystem {
                     c.position[projectile_e].p = p;
                     c.orientation[projectile_e].angle = angle;
                     c.linear_velocity[projectile_e].v = Vec2::new(
-                        angle.cos() * PROJECTILE_SPEED,
-                        angle.sin() * PROJECTILE_SPEED
+                        angle.cos() * projectile_speed,
+                        angle.sin() * projectile_speed
                     );
+                    c.projectile[projectile_e] = Projectile::Frag(frag_lifetime_s);
                 });
 
                 if charges > 1 {
@@ -183,7 +208,7 @@ impl PlayerControllerSystem {
             &Some(ref item) => {
                 let equipped_item = c.player_state[e].get_item_mut(slot).unwrap();
                 equipped_item.item = item.clone();
-                equipped_item.cooldown_s = item.cooldown_s();
+                equipped_item.cooldown_s = c.services.balance.cooldown_s(item);
             }
             &None => {
                 c.player_state[e].unequip(slot);
@@ -207,7 +232,7 @@ impl PlayerControllerSystem {
     }
 }
 
-impl_cached_system!(Components, Services, PlayerControllerSystem, player_aspect, wall_aspect);
+impl_cached_system!(Components, Services, PlayerControllerSystem, player_aspect, walls);
 
 impl Process for PlayerControllerSystem {
     fn process(&mut self, _: &mut DataHelper<Components, Services>) {
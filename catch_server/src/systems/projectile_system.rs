@@ -1,7 +1,7 @@
 use std::f32;
 
 use hprof;
-use rand;
+use rand::Rng;
 use ecs::{EntityData, Aspect, Process, System, DataHelper};
 use na::{Vec2, Norm};
 
@@ -14,18 +14,19 @@ use services::Services;
 use entities;
 
 pub fn explode(projectile: EntityData<Components>, data: &mut DataHelper<Components, Services>) {
-    const NUM_SHRAPNELS: usize = 15;
-
     let strength = match data.projectile[projectile] {
         Projectile::Frag(_) => {
-            for _ in 0..NUM_SHRAPNELS {
+            let min_speed = data.services.balance.shrapnel_min_speed;
+            let max_speed = data.services.balance.shrapnel_max_speed;
+
+            for _ in 0..data.services.balance.shrapnel_count {
                 let player_id = data.net_entity[projectile].owner;
-                let angle = rand::random::<f32>() * f32::consts::PI * 2.0;
+                let angle = data.services.rng.gen::<f32>() * f32::consts::PI * 2.0;
                 //let angular_velocity = rand::random::<f32>() * f32::consts::PI * 5.0;
-                let speed = 50.0 + 100.0 * rand::random::<f32>();
+                let speed = min_speed + (max_speed - min_speed) * data.services.rng.gen::<f32>();
                 let linear_velocity = Vec2::new(angle.cos() * speed, angle.sin() * speed);
-                let width = rand::random::<f32>() * 6.0 + 3.0;
-                let height = rand::random::<f32>() * 6.0 + 3.0;
+                let width = data.services.rng.gen::<f32>() * 6.0 + 3.0;
+                let height = data.services.rng.gen::<f32>() * 6.0 + 3.0;
                 let shape = Shape::Rect { width: width, height: height };
 
                 let shrapnel = entities::build_net("shrapnel", player_id, data);
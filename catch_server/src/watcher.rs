@@ -0,0 +1,44 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Notices when files change by polling their modification times, so that we don't depend on
+/// any platform-specific notification service
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl FileWatcher {
+    pub fn new() -> FileWatcher {
+        FileWatcher {
+            files: Vec::new(),
+        }
+    }
+
+    /// Starts watching a file. Its current state counts as unchanged.
+    pub fn watch<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref().to_path_buf();
+        let modified = modification_time(&path);
+        self.files.push((path, modified));
+    }
+
+    /// Returns the files that were modified since the last poll. A file that can't be read,
+    /// e.g. because an editor is replacing it right now, counts as unchanged until it is back.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+
+        for &mut (ref path, ref mut last_modified) in self.files.iter_mut() {
+            let modified = modification_time(path);
+            if modified.is_some() && modified != *last_modified {
+                *last_modified = modified;
+                changed.push(path.clone());
+            }
+        }
+
+        changed
+    }
+}
+
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -0,0 +1,114 @@
+//! Connects to a server that runs on another thread over a `MemoryTransport` and checks that
+//! it accepts us and sends ticks.
+
+extern crate bincode;
+extern crate catch_shared as shared;
+extern crate catch_server;
+
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use std::thread;
+
+use bincode::SizeLimit;
+use bincode::rustc_serialize::{encode, decode, decode_from};
+
+use shared::{GameEvent, PlayerInput, Tick, TickNumber};
+use shared::net::{self, ClientMessage, ServerMessage, TimedPlayerInput};
+use shared::transport::{Transport, TransportEvent, PeerId, Reliability, MemoryTransport};
+use catch_server::{server, Server};
+
+const TIMEOUT_MS: u32 = 5000;
+
+fn send(transport: &mut MemoryTransport, server_peer: PeerId, message: &ClientMessage) {
+    let data = encode(message, SizeLimit::Infinite).unwrap();
+    transport.send(server_peer, net::Channel::Messages as u8, Reliability::Reliable, &data);
+}
+
+/// Waits for the next packet, skipping pings
+fn receive(transport: &mut MemoryTransport) -> (u8, Vec<u8>) {
+    loop {
+        match transport.service(TIMEOUT_MS) {
+            Ok(Some(TransportEvent::Receive(_, channel, data))) => {
+                if channel == net::Channel::Messages as u8 {
+                    if let Ok(ServerMessage::Ping) = decode(&data) {
+                        continue;
+                    }
+                }
+                return (channel, data);
+            }
+            event => panic!("expected a packet, got {:?}", event),
+        }
+    }
+}
+
+/// Waits for the next tick, returning the number of the tick that it is a delta to
+fn receive_tick(transport: &mut MemoryTransport) -> (Option<TickNumber>, Tick) {
+    let (channel, data) = receive(transport);
+    assert_eq!(channel, net::Channel::Ticks as u8);
+
+    let mut data = &data[..];
+    let delta_tick = decode_from(&mut data, SizeLimit::Infinite).unwrap();
+    let tick = decode_from(&mut data, SizeLimit::Infinite).unwrap();
+    (delta_tick, tick)
+}
+
+#[test]
+fn server_sends_ticks_to_connected_client() {
+    // The data files are loaded relative to the repository root
+    if !Path::new("data").exists() {
+        env::set_current_dir("..").unwrap();
+    }
+
+    let game_info = server::game_info(server::DEFAULT_MAP).unwrap();
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = stop.clone();
+    let (sender, receiver) = channel();
+
+    // The server is not Send, so it needs to be created on its thread
+    let thread = thread::spawn(move || {
+        let transport = MemoryTransport::new();
+        sender.send(transport.address()).unwrap();
+
+        let mut server = Server::start(&game_info, 1, None, Box::new(transport)).unwrap();
+        server.run_until(&thread_stop);
+    });
+
+    let mut transport = MemoryTransport::new();
+    let server_peer = transport.connect(&receiver.recv().unwrap()).unwrap();
+
+    send(&mut transport, server_peer, &ClientMessage::WishConnect {
+        name: "test".to_string(),
+        color: [0.0, 0.0, 1.0],
+    });
+    let (channel, data) = receive(&mut transport);
+    assert_eq!(channel, net::Channel::Messages as u8);
+    let my_id = match decode(&data) {
+        Ok(ServerMessage::AcceptConnect { your_id, .. }) => your_id,
+        _ => panic!("expected AcceptConnect"),
+    };
+
+    // The first tick is sent in full and tells us about ourselves
+    let (delta_tick, first_tick) = receive_tick(&mut transport);
+    assert_eq!(delta_tick, None);
+    assert!(first_tick.events.iter().any(|event| {
+        match *event {
+            GameEvent::InitialPlayerList(ref players) => players.iter().any(|p| p.0 == my_id),
+            _ => false,
+        }
+    }));
+
+    // After we play, the next tick is a delta to the one that we have
+    send(&mut transport, server_peer, &ClientMessage::PlayerInput(TimedPlayerInput {
+        duration_s: 0.1,
+        input: PlayerInput::new(),
+    }));
+    let (delta_tick, tick) = receive_tick(&mut transport);
+    assert_eq!(delta_tick, Some(first_tick.tick_number));
+    assert!(tick.tick_number > first_tick.tick_number);
+
+    stop.store(true, Ordering::Relaxed);
+    thread.join().unwrap();
+}
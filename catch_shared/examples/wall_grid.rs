@@ -0,0 +1,91 @@
+//! Compares testing moving circles against every wall of a map with testing only the walls found
+//! in a uniform grid. Run with `cargo run --release --example wall_grid`.
+
+extern crate time;
+extern crate nalgebra as na;
+extern crate catch_shared as shared;
+
+use na::Vec2;
+
+use shared::Map;
+use shared::math;
+use shared::grid::{Grid, WALL_GRID_CELL_SIZE};
+
+const MAP: &'static str = "../data/maps/desert.tmx";
+const NUM_MOVEMENTS: usize = 1000;
+const NUM_ITERATIONS: usize = 100;
+const RADIUS: f32 = 6.0;
+
+type Line = (Vec2<f32>, Vec2<f32>);
+
+/// Deterministic pseudo-random movements spread over the map
+fn movements(map: &Map) -> Vec<(Vec2<f32>, Vec2<f32>)> {
+    let w = map.width_pixels() as f32;
+    let h = map.height_pixels() as f32;
+    let mut x: u32 = 12345;
+    let mut next = || {
+        x = x.wrapping_mul(1103515245).wrapping_add(12345);
+        (x >> 8) as f32 / (1 << 24) as f32
+    };
+
+    (0..NUM_MOVEMENTS).map(|_| {
+        let a = Vec2::new(next() * w, next() * h);
+        let delta = Vec2::new(next() * 20.0 - 10.0, next() * 20.0 - 10.0);
+        (a, delta)
+    }).collect()
+}
+
+fn closest(lines: &[&Line], a: Vec2<f32>, delta: Vec2<f32>) -> Option<f32> {
+    let mut closest_t = None;
+    for &&(p, q) in lines {
+        let t = math::line_segment_moving_circle_intersection_time(p, q, a, delta, RADIUS);
+        closest_t = math::min_option(closest_t, t);
+    }
+    closest_t
+}
+
+/// Runs `f` for all movements `NUM_ITERATIONS` times and prints the average time it took. The
+/// number of hits is printed as well, which keeps the tests from being optimized away and shows
+/// that both ways find the same walls.
+fn measure<F>(name: &str, movements: &[(Vec2<f32>, Vec2<f32>)], f: F)
+    where F: Fn(Vec2<f32>, Vec2<f32>) -> Option<f32>
+{
+    let mut num_hits = 0;
+    let start_ns = time::precise_time_ns();
+
+    for _ in 0..NUM_ITERATIONS {
+        for &(a, delta) in movements {
+            if f(a, delta).is_some() {
+                num_hits += 1;
+            }
+        }
+    }
+
+    let duration_ns = time::precise_time_ns() - start_ns;
+    println!("{}: {:.3}ms, {} hits", name,
+             duration_ns as f64 / NUM_ITERATIONS as f64 / 1000000.0,
+             num_hits / NUM_ITERATIONS);
+}
+
+fn main() {
+    let map = Map::load(MAP).unwrap();
+    let movements = movements(&map);
+
+    let lines = map.lines.iter().map(|line| (line.a, line.b)).collect::<Vec<_>>();
+    let lines = lines.iter().collect::<Vec<_>>();
+    measure("brute force", &movements, |a, delta| closest(&lines, a, delta));
+
+    let mut grid = Grid::new(WALL_GRID_CELL_SIZE);
+    for (i, line) in map.lines.iter().enumerate() {
+        grid.insert_line(i, line.a, line.b, || (line.a, line.b));
+    }
+    measure("grid", &movements, |a, delta| {
+        let c = a + delta;
+        let min = Vec2::new(a.x.min(c.x) - RADIUS, a.y.min(c.y) - RADIUS);
+        let max = Vec2::new(a.x.max(c.x) + RADIUS, a.y.max(c.y) + RADIUS);
+        let lines = grid.query_aabb(min, max).into_iter()
+                        .map(|(_, line)| line)
+                        .collect::<Vec<_>>();
+        closest(&lines, a, delta)
+    });
+}
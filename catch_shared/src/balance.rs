@@ -0,0 +1,238 @@
+//! Numbers for tuning the game, loaded by the server and replicated to the clients.
+
+use std::path::Path;
+
+use config::{self, Line, parse_number, parse_count};
+use player::{Item, ITEM_NAMES};
+use super::NUM_ITEM_SLOTS;
+
+/// Where the balance table is loaded from
+pub const BALANCE_PATH: &'static str = "data/balance.cfg";
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct ItemBalance {
+    // Charges of the item when it is picked up
+    pub charges: usize,
+
+    // For items that last for some time instead of having charges
+    pub duration_s: f32,
+
+    // Time until the item can be used again
+    pub cooldown_s: Option<f32>,
+
+    // Relative chance of item spawns picking this item
+    pub spawn_weight: f32,
+}
+
+/// What happens when a player touches an item
+#[derive(Debug, Clone, Copy, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum PickupRule {
+    // The item is picked up. An item that the player was carrying is left in its place.
+    Swap,
+
+    // Players that already carry an item don't pick up another one
+    IgnoreWhenFull,
+
+    // The item is equipped right away if the player has an empty slot, otherwise like `Swap`
+    AutoEquip,
+}
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Balance {
+    // Has an entry for each of `ITEM_NAMES`, in the same order
+    pub items: Vec<(String, ItemBalance)>,
+
+    pub projectile_speed: f32,
+    pub frag_lifetime_s: f32,
+    pub shrapnel_count: usize,
+    pub shrapnel_min_speed: f32,
+    pub shrapnel_max_speed: f32,
+
+    // Items that players get when spawning, by name and charges, one per slot
+    pub loadout: Vec<(String, usize)>,
+
+    pub pickup_rule: PickupRule,
+}
+
+impl Default for Balance {
+    fn default() -> Balance {
+        let item = |charges, cooldown_s, spawn_weight| {
+            ItemBalance {
+                charges: charges,
+                duration_s: 0.0,
+                cooldown_s: cooldown_s,
+                spawn_weight: spawn_weight,
+            }
+        };
+
+        Balance {
+            items: vec![("weapon".to_string(), item(10, Some(0.5), 1.0)),
+                        ("frag_weapon".to_string(), item(5, Some(1.5), 1.0)),
+                        ("speed_boost".to_string(), ItemBalance {
+                             duration_s: 5.0,
+                             .. item(0, None, 0.0)
+                         }),
+                        ("block_placer".to_string(), item(3, Some(5.0), 0.0)),
+                        ("ball_spawner".to_string(), item(3, Some(2.5), 1.0)),
+                        ("shield".to_string(), item(0, None, 1.0))],
+            projectile_speed: 200.0,
+            frag_lifetime_s: 1.5,
+            shrapnel_count: 15,
+            shrapnel_min_speed: 50.0,
+            shrapnel_max_speed: 150.0,
+            loadout: vec![("weapon".to_string(), 20),
+                          ("frag_weapon".to_string(), 2),
+                          ("ball_spawner".to_string(), 3)],
+            pickup_rule: PickupRule::Swap,
+        }
+    }
+}
+
+impl Balance {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Balance, String> {
+        config::load(path, "balance", Balance::parse)
+    }
+
+    /// Parses `key = value` lines. Keys after an `[item_name]` line set the values of that
+    /// item. Anything that is not given keeps its default value.
+    pub fn parse(text: &str) -> Result<Balance, String> {
+        let mut balance = Balance::default();
+        let mut item_index = None;
+
+        try!(config::parse_lines(text, |line| {
+            match line {
+                Line::Section(name) => {
+                    match ITEM_NAMES.iter().position(|&item_name| item_name == name) {
+                        Some(index) => item_index = Some(index),
+                        None => return Err(format!("unknown item {}", name)),
+                    }
+                    Ok(())
+                }
+                Line::KeyValue(key, value) => {
+                    match item_index {
+                        Some(index) => parse_item_key(&mut balance.items[index].1, key, value),
+                        None => balance.parse_key(key, value),
+                    }
+                }
+            }
+        }));
+
+        Ok(balance)
+    }
+
+    fn parse_key(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "projectile_speed" => self.projectile_speed = try!(parse_number(value)),
+            "frag_lifetime" => self.frag_lifetime_s = try!(parse_number(value)),
+            "shrapnel_count" => self.shrapnel_count = try!(parse_count(value)),
+            "shrapnel_min_speed" => self.shrapnel_min_speed = try!(parse_number(value)),
+            "shrapnel_max_speed" => self.shrapnel_max_speed = try!(parse_number(value)),
+            "pickup_rule" => {
+                self.pickup_rule = match value {
+                    "swap" => PickupRule::Swap,
+                    "ignore_when_full" => PickupRule::IgnoreWhenFull,
+                    "auto_equip" => PickupRule::AutoEquip,
+                    _ => return Err(format!("expected swap, ignore_when_full or auto_equip, \
+                                             got {}", value)),
+                };
+            }
+            "loadout" => {
+                let mut loadout = Vec::new();
+                for entry in value.split(',').map(|entry| entry.trim())
+                                  .filter(|entry| !entry.is_empty()) {
+                    let words = entry.split_whitespace().collect::<Vec<_>>();
+                    if words.len() != 2 || !ITEM_NAMES.contains(&words[0]) {
+                        return Err(format!("expected `item_name charges`, got {}", entry));
+                    }
+                    loadout.push((words[0].to_string(), try!(parse_count(words[1]))));
+                }
+                if loadout.len() > NUM_ITEM_SLOTS as usize {
+                    return Err(format!("the loadout can have at most {} items",
+                                       NUM_ITEM_SLOTS));
+                }
+                self.loadout = loadout;
+            }
+            _ => return Err(format!("unknown key: {}", key)),
+        }
+
+        Ok(())
+    }
+
+    pub fn item(&self, name: &str) -> &ItemBalance {
+        &self.items.iter().find(|&&(ref item_name, _)| item_name == name).unwrap().1
+    }
+
+    /// Creates an item with the charges it has when being picked up
+    pub fn new_item(&self, name: &str) -> Option<Item> {
+        if !ITEM_NAMES.contains(&name) {
+            return None;
+        }
+
+        let item_balance = self.item(name);
+        Item::new(name, item_balance.charges, item_balance.duration_s)
+    }
+
+    pub fn cooldown_s(&self, item: &Item) -> Option<f32> {
+        self.item(item.name()).cooldown_s
+    }
+
+    pub fn loadout(&self) -> Vec<Item> {
+        self.loadout.iter()
+                    .filter_map(|&(ref name, charges)| {
+                        Item::new(name, charges, self.item(name).duration_s)
+                    })
+                    .collect()
+    }
+
+    /// Picks an item for a spawn according to the spawn weights, where `r` is random in [0, 1)
+    pub fn choose_spawn_item(&self, r: f32) -> Option<Item> {
+        let pool = self.items.iter()
+                             .map(|&(ref name, ref item)| (name.clone(), item.spawn_weight))
+                             .collect::<Vec<_>>();
+        self.choose_pool_item(&pool, r)
+    }
+
+    /// Picks an item from a pool of item names and weights, where `r` is random in [0, 1).
+    /// Returns None if no item has a positive weight.
+    pub fn choose_pool_item(&self, pool: &[(String, f32)], r: f32) -> Option<Item> {
+        let total_weight = pool.iter().map(|&(_, weight)| weight).fold(0.0, |a, b| a + b);
+        if total_weight <= 0.0 {
+            return None;
+        }
+
+        let mut x = r * total_weight;
+        for &(ref name, weight) in pool.iter() {
+            if weight <= 0.0 {
+                continue;
+            }
+            if x < weight {
+                return self.new_item(name);
+            }
+            x -= weight;
+        }
+
+        // Rounding errors
+        pool.iter().rev()
+            .find(|&&(_, weight)| weight > 0.0)
+            .and_then(|&(ref name, _)| self.new_item(name))
+    }
+}
+
+fn parse_item_key(item: &mut ItemBalance, key: &str, value: &str) -> Result<(), String> {
+    match key {
+        "charges" => item.charges = try!(parse_count(value)),
+        "duration" => item.duration_s = try!(parse_number(value)),
+        "cooldown" => {
+            item.cooldown_s = if value == "none" { None } else { Some(try!(parse_number(value))) };
+        }
+        "spawn_weight" => {
+            item.spawn_weight = try!(parse_number(value));
+            if item.spawn_weight < 0.0 {
+                return Err("spawn weights can't be negative".to_string());
+            }
+        }
+        _ => return Err(format!("unknown item key: {}", key)),
+    }
+
+    Ok(())
+}
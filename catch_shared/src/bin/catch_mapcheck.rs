@@ -0,0 +1,95 @@
+//! Loads maps and reports problems with them.
+//!
+//! Usage: catch_mapcheck [MAP...]
+//! Without arguments, all maps in data/maps are checked. Generated maps can be checked by giving
+//! `random:SEED[:WIDTHxHEIGHT]`. Object types are checked against the entity types in
+//! data/entities.cfg. Exits with status 1 if any map has problems.
+
+extern crate catch_shared as shared;
+
+use std::env;
+use std::fs;
+use std::process;
+
+use shared::map::Map;
+use shared::entities::{self, ENTITY_TYPES_PATH};
+use shared::mapgen::{self, MapGenParams};
+
+const MAP_DIR: &'static str = "data/maps";
+
+fn map_paths() -> Result<Vec<String>, String> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        return Ok(args);
+    }
+
+    let entries = match fs::read_dir(MAP_DIR) {
+        Ok(entries) => entries,
+        Err(error) => return Err(format!("could not read {}: {}", MAP_DIR, error)),
+    };
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        match entry {
+            Ok(entry) => {
+                let path = entry.path();
+                if path.extension().map(|e| e == "tmx").unwrap_or(false) {
+                    paths.push(path.to_string_lossy().into_owned());
+                }
+            }
+            Err(error) => return Err(format!("could not read {}: {}", MAP_DIR, error)),
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn main() {
+    let paths = match map_paths() {
+        Ok(paths) => paths,
+        Err(error) => {
+            println!("{}", error);
+            process::exit(2);
+        }
+    };
+    let entity_types = match entities::load_entity_types(ENTITY_TYPES_PATH) {
+        Ok(entity_types) => entity_types,
+        Err(error) => {
+            println!("{}", error);
+            process::exit(2);
+        }
+    };
+
+    let mut num_bad = 0;
+    for path in paths.iter() {
+        let map = match MapGenParams::parse(path) {
+            Some(Ok(params)) => Ok(mapgen::generate(&params)),
+            Some(Err(error)) => Err(error),
+            None => Map::load(path).map_err(|error| error.to_string()),
+        };
+
+        match map {
+            Ok(map) => {
+                let problems = map.check(&entity_types);
+                if problems.is_empty() {
+                    println!("{}: ok", path);
+                } else {
+                    num_bad += 1;
+                    for problem in problems.iter() {
+                        println!("{}: {}", path, problem);
+                    }
+                }
+            }
+            Err(error) => {
+                num_bad += 1;
+                println!("{}: {}", path, error);
+            }
+        }
+    }
+
+    println!("checked {} maps, {} with problems", paths.len(), num_bad);
+
+    if num_bad > 0 {
+        process::exit(1);
+    }
+}
@@ -1,7 +1,7 @@
 use na::Vec2;
 use ecs::{ComponentManager, ComponentList};
 
-use super::{EntityId, EntityTypeId, PlayerId};
+use super::{EntityId, EntityTypeId, PlayerId, TickNumber};
 pub use player::{PlayerState, FullPlayerState};
 
 /// Every entity that wants its component state synchronized needs to have this component
@@ -94,7 +94,7 @@ impl Projectile {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 pub enum WallType {
     Iron,
     Wood
@@ -106,7 +106,7 @@ impl Default for WallType {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, RustcEncodable, RustcDecodable)]
 pub struct Wall {
     pub wall_type: WallType,
     pub width: f32,
@@ -127,6 +127,16 @@ impl Default for WallPosition {
     }
 }
 
+/// Lets clients show when an item spawn will spawn its next item
+#[derive(PartialEq, Debug, Clone, Default, RustcEncodable, RustcDecodable)]
+pub struct ItemSpawnState {
+    // Time between an item being taken and the next one being spawned
+    pub respawn_time_s: f32,
+
+    // Tick in which the next item is spawned, if the spawn is empty
+    pub respawn_tick: Option<TickNumber>,
+}
+
 // Some boilerplate code for each net component type follows...
 
 pub trait HasPosition: Sized + ComponentManager {
@@ -169,6 +179,11 @@ pub trait HasWallPosition: Sized + ComponentManager {
     fn wall_position_mut(&mut self) -> &mut ComponentList<Self, WallPosition>;
 }
 
+pub trait HasItemSpawnState: Sized + ComponentManager {
+    fn item_spawn_state(&self) -> &ComponentList<Self, ItemSpawnState>;
+    fn item_spawn_state_mut(&mut self) -> &mut ComponentList<Self, ItemSpawnState>;
+}
+
 pub trait HasWall: Sized + ComponentManager {
     fn wall(&self) -> &ComponentList<Self, Wall>;
     fn wall_mut(&mut self) -> &mut ComponentList<Self, Wall>;
@@ -0,0 +1,64 @@
+//! Reading of the config files in `data/`, which define the entity types, the balance and the
+//! tuning. They consist of `key = value` lines that can be grouped into sections by `[name]`
+//! lines. Empty lines and lines starting with `#` are ignored.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+pub enum Line<'a> {
+    Section(&'a str),
+    KeyValue(&'a str, &'a str),
+}
+
+/// Reads and parses a config file. `what` names its contents in error messages, and parse
+/// errors are prefixed with the path.
+pub fn load<T, P, F>(path: P, what: &str, parse: F) -> Result<T, String>
+    where P: AsRef<Path>,
+          F: FnOnce(&str) -> Result<T, String>
+{
+    let path = path.as_ref();
+
+    let mut text = String::new();
+    if let Err(error) = File::open(path).and_then(|mut file| file.read_to_string(&mut text)) {
+        return Err(format!("could not read {} {:?}: {}", what, path, error));
+    }
+
+    parse(&text).map_err(|error| format!("{:?}: {}", path, error))
+}
+
+/// Calls `f` for each section and `key = value` line. Errors are prefixed with the line number.
+pub fn parse_lines<F>(text: &str, mut f: F) -> Result<(), String>
+    where F: FnMut(Line) -> Result<(), String>
+{
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let result = if line.starts_with('[') && line.ends_with(']') {
+            f(Line::Section(line[1..line.len() - 1].trim()))
+        } else {
+            let parts = line.splitn(2, '=').collect::<Vec<_>>();
+            if parts.len() != 2 {
+                return Err(format!("line {}: expected `[name]` or `key = value`", i + 1));
+            }
+            f(Line::KeyValue(parts[0].trim(), parts[1].trim()))
+        };
+
+        if let Err(error) = result {
+            return Err(format!("line {}: {}", i + 1, error));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn parse_number(s: &str) -> Result<f32, String> {
+    s.parse().map_err(|_| format!("expected a number, got {}", s))
+}
+
+pub fn parse_count(s: &str) -> Result<usize, String> {
+    s.parse().map_err(|_| format!("expected a whole number, got {}", s))
+}
@@ -0,0 +1,135 @@
+//! Finding servers on the local network.
+//!
+//! Clients broadcast a query over UDP to `DISCOVERY_PORT`. Every server that hears it replies
+//! with a `ServerAnnouncement` describing its game.
+
+use std::io;
+use std::thread;
+use std::net::{UdpSocket, SocketAddr, Ipv4Addr};
+
+use time;
+use bincode::SizeLimit;
+use bincode::rustc_serialize::{encode, decode};
+
+pub const DISCOVERY_PORT: u16 = 9989;
+
+// Packets start with these, so that we can ignore other traffic on the port
+const QUERY_MAGIC: &'static [u8] = b"catch?";
+const REPLY_MAGIC: &'static [u8] = b"catch!";
+
+/// What a server tells clients that are looking for games
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+pub struct ServerAnnouncement {
+    pub name: String,
+    pub motd: String,
+    pub map_name: String,
+    pub mode: String,
+    pub num_players: u32,
+    pub max_players: u32,
+
+    // Port to connect to for playing
+    pub port: u16,
+}
+
+/// A server that replied to our query
+#[derive(Debug, Clone)]
+pub struct DiscoveredServer {
+    // Address to connect to for playing
+    pub address: SocketAddr,
+
+    pub announcement: ServerAnnouncement,
+    pub ping_ms: u32,
+}
+
+/// Answers discovery queries on behalf of a server
+pub struct DiscoveryResponder {
+    socket: UdpSocket,
+}
+
+impl DiscoveryResponder {
+    pub fn bind() -> Result<DiscoveryResponder, String> {
+        let socket = try!(UdpSocket::bind(("0.0.0.0", DISCOVERY_PORT)).map_err(|error| {
+            format!("could not listen for discovery on port {}: {}", DISCOVERY_PORT, error)
+        }));
+        try!(socket.set_nonblocking(true).map_err(|error| error.to_string()));
+
+        Ok(DiscoveryResponder {
+            socket: socket,
+        })
+    }
+
+    /// Replies to all queries that have arrived since the last call. Does not block.
+    pub fn respond(&self, announcement: &ServerAnnouncement) {
+        let mut buffer = [0; 64];
+        loop {
+            let (size, from) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => return,
+                Err(error) => {
+                    warn!("error receiving discovery query: {}", error);
+                    return;
+                }
+            };
+
+            if &buffer[..size] != QUERY_MAGIC {
+                continue;
+            }
+
+            debug!("answering discovery query from {}", from);
+
+            let mut reply = REPLY_MAGIC.to_vec();
+            reply.extend(encode(announcement, SizeLimit::Infinite).unwrap());
+            if let Err(error) = self.socket.send_to(&reply, from) {
+                warn!("could not answer discovery query from {}: {}", from, error);
+            }
+        }
+    }
+}
+
+/// Broadcasts a query on the local network and collects the replies that arrive within the
+/// given time. Servers are sorted by ping.
+pub fn discover(timeout_ms: u32) -> Result<Vec<DiscoveredServer>, String> {
+    let socket = try!(UdpSocket::bind(("0.0.0.0", 0)).map_err(|error| error.to_string()));
+    try!(socket.set_broadcast(true).map_err(|error| error.to_string()));
+    try!(socket.set_nonblocking(true).map_err(|error| error.to_string()));
+
+    let start_ns = time::precise_time_ns();
+    try!(socket.send_to(QUERY_MAGIC, (Ipv4Addr::new(255, 255, 255, 255), DISCOVERY_PORT))
+               .map_err(|error| format!("could not send discovery query: {}", error)));
+
+    let mut servers = Vec::new();
+    let mut buffer = [0; 4096];
+
+    while time::precise_time_ns() - start_ns < timeout_ms as u64 * 1000000 {
+        let (size, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep_ms(5);
+                continue;
+            }
+            Err(error) => return Err(format!("error receiving discovery reply: {}", error)),
+        };
+        let ping_ms = ((time::precise_time_ns() - start_ns) / 1000000) as u32;
+
+        let data = &buffer[..size];
+        if !data.starts_with(REPLY_MAGIC) {
+            continue;
+        }
+        let announcement: ServerAnnouncement = match decode(&data[REPLY_MAGIC.len()..]) {
+            Ok(announcement) => announcement,
+            Err(_) => {
+                warn!("received invalid discovery reply from {}", from);
+                continue;
+            }
+        };
+
+        servers.push(DiscoveredServer {
+            address: SocketAddr::new(from.ip(), announcement.port),
+            announcement: announcement,
+            ping_ms: ping_ms,
+        });
+    }
+
+    servers.sort_by(|a, b| a.ping_ms.cmp(&b.ping_ms));
+    Ok(servers)
+}
@@ -1,11 +1,46 @@
 use std::collections::{HashMap, hash_map};
 use std::ops::Index;
+use std::path::Path;
 
 use ecs::{self, ComponentManager, BuildData};
 
-use super::{PlayerId, EntityId, EntityTypeId};
+use super::{PlayerId, EntityId};
+use config::{self, Line, parse_number};
 use components::{HasShape, Shape, HasWall, Wall, WallType, Projectile, HasProjectile};
-use net_components::ComponentType;
+use net_components::{ComponentType, COMPONENT_TYPES};
+
+/// Where the entity types are defined
+pub const ENTITY_TYPES_PATH: &'static str = "data/entities.cfg";
+
+/// Server-side behavior of an entity, apart from replicating its net components
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum Behavior {
+    // Controlled by the input of its owner
+    PlayerController,
+
+    // Moves with a linear velocity
+    Movement,
+
+    // Spins with the given angular velocity in radians per second
+    Rotate(f32),
+
+    // Bounces off walls and follows or flees from players
+    BouncyEnemy,
+
+    // Spawns items from time to time
+    ItemSpawn,
+}
+
+/// How the client draws an entity
+#[derive(Debug, Clone, Copy, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum Appearance {
+    Player,
+    BouncyEnemy,
+    Item,
+    Projectile,
+    Wall,
+    ItemSpawn,
+}
 
 #[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 pub struct EntityType {
@@ -16,6 +51,28 @@ pub struct EntityType {
     // Components that should be sent only to the owner of the object
     // Example: the full state of a player including cooldowns etc. is only needed by the owner
     pub owner_component_types: Vec<ComponentType>,
+
+    // Shared components that are not synchronized over the net
+    pub shape: Option<Shape>,
+    pub projectile: Option<Projectile>,
+    pub wall: Option<Wall>,
+
+    pub behaviors: Vec<Behavior>,
+    pub appearance: Option<Appearance>,
+}
+
+impl EntityType {
+    fn new() -> EntityType {
+        EntityType {
+            component_types: Vec::new(),
+            owner_component_types: Vec::new(),
+            shape: None,
+            projectile: None,
+            wall: None,
+            behaviors: Vec::new(),
+            appearance: None,
+        }
+    }
 }
 
 /// Adds shared components that are not synchronized over the net to an entity
@@ -23,44 +80,23 @@ pub fn build_shared<T: ComponentManager +
                        HasShape +
                        HasWall +
                        HasProjectile>
-                   (type_name: &str,
+                   (entity_type: &EntityType,
                     entity: BuildData<T>,
                     data: &mut T) {
-    if type_name == "player" {
-        data.shape_mut().add(&entity, Shape::Circle { radius: 6.0 });
-    } else if type_name == "bouncy_enemy" {
-        data.shape_mut().add(&entity, Shape::Circle { radius: 10.0 });
-    } else if type_name == "player_ball" {
-        data.shape_mut().add(&entity, Shape::Circle { radius: 7.0 });
-    } else if type_name == "item" {
-        data.shape_mut().add(&entity, Shape::Square { size: 5.0 });
-    } else if type_name == "item_spawn" {
-    } else if type_name == "bullet" {
-        data.shape_mut().add(&entity, Shape::Rect { width: 8.0, height: 4.0 });
-        data.projectile_mut().add(&entity, Projectile::Bullet);
-    } else if type_name == "frag" {
-        data.shape_mut().add(&entity, Shape::Rect { width: 5.0, height: 5.0 });
-        data.projectile_mut().add(&entity, Projectile::Frag(1.5));
-    } else if type_name == "shrapnel" {
-        data.projectile_mut().add(&entity, Projectile::Shrapnel);
-    } else if type_name == "wall_wood" {
-        data.wall_mut().add(&entity, Wall { 
-            wall_type: WallType::Wood,
-            width: 1.0,
-        });
-    } else if type_name == "wall_iron" {
-        data.wall_mut().add(&entity, Wall { 
-            wall_type: WallType::Iron,
-            width: 1.0,
-        });
-    } else {
-        panic!("unknown entity type: {}", type_name);
+    if let Some(ref shape) = entity_type.shape {
+        data.shape_mut().add(&entity, shape.clone());
+    }
+    if let Some(ref projectile) = entity_type.projectile {
+        data.projectile_mut().add(&entity, projectile.clone());
+    }
+    if let Some(ref wall) = entity_type.wall {
+        data.wall_mut().add(&entity, wall.clone());
     }
 }
 
 /// Maps from net entity ids to the local ecs::Entity handles
+#[derive(Default)]
 pub struct NetEntities {
-    entity_types: EntityTypes,
     entities: HashMap<EntityId, ecs::Entity>,
 
     // Stores each player's currently controlled entity
@@ -68,16 +104,6 @@ pub struct NetEntities {
 
 }
 
-impl Default for NetEntities {
-    fn default() -> NetEntities {
-        NetEntities {
-            entity_types: all_entity_types(),
-            entities: HashMap::new(),
-            player_entities: HashMap::new(),
-        }
-    }
-}
-
 impl NetEntities {
     pub fn get(&self, id: EntityId) -> Option<ecs::Entity> {
         self.entities.get(&id).map(|e| e.clone())
@@ -92,13 +118,13 @@ impl NetEntities {
     }
 
     pub fn on_build(&mut self,
-                    id: EntityId, type_id: EntityTypeId, owner: PlayerId,
+                    id: EntityId, entity_type: &EntityType, owner: PlayerId,
                     entity: ecs::Entity) {
         assert!(self.entities.get(&id).is_none());
         self.entities.insert(id, entity);
 
-        // HACK: detection of player entities
-        if self.entity_types[type_id as usize].0 == "player" {
+        // Entities that are controlled by their owner are the player's
+        if entity_type.behaviors.contains(&Behavior::PlayerController) {
             assert!(self.player_entities.get(&owner).is_none());
             self.player_entities.insert(owner, entity);
         }
@@ -137,56 +163,142 @@ impl Index<EntityId> for NetEntities {
 
 pub type EntityTypes = Vec<(String, EntityType)>;
 
-pub fn all_entity_types() -> EntityTypes {
-    vec![("player".to_string(), EntityType {
-              component_types: vec![ComponentType::Position,
-                                    ComponentType::Orientation,
-                                    ComponentType::LinearVelocity,
-                                    ComponentType::PlayerState],
-              owner_component_types: vec![ComponentType::FullPlayerState],
-         }),
-         ("bouncy_enemy".to_string(), EntityType {
-              component_types: vec![ComponentType::Position,
-                                    ComponentType::Orientation],
-              owner_component_types: vec![],
-         }),
-         ("player_ball".to_string(), EntityType {
-              component_types: vec![ComponentType::Position,
-                                    ComponentType::Orientation],
-              owner_component_types: vec![],
-         }),
-         ("item_spawn".to_string(), EntityType {
-              component_types: vec![ComponentType::Position],
-              owner_component_types: vec![],
-         }),
-         ("item".to_string(), EntityType {
-              component_types: vec![ComponentType::Position,
-                                    ComponentType::Orientation],
-              owner_component_types: vec![],
-         }),
-         ("bullet".to_string(), EntityType {
-              component_types: vec![ComponentType::Position,
-                                    ComponentType::Orientation],
-              owner_component_types: vec![],
-         }),
-         ("frag".to_string(), EntityType {
-              component_types: vec![ComponentType::Position,
-                                    ComponentType::Orientation],
-              owner_component_types: vec![],
-         }),
-         ("shrapnel".to_string(), EntityType {
-              component_types: vec![ComponentType::Position,
-                                    ComponentType::Orientation,
-                                    ComponentType::Shape],
-              owner_component_types: vec![],
-         }),
-         ("wall_wood".to_string(), EntityType {
-              component_types: vec![ComponentType::WallPosition],
-              owner_component_types: vec![],
-         }),
-         ("wall_iron".to_string(), EntityType {
-              component_types: vec![ComponentType::WallPosition],
-              owner_component_types: vec![],
-         }),
-        ]
+// The game creates entities of these types by name, so every definition file needs them
+const REQUIRED_ENTITY_TYPES: &'static [&'static str] =
+    &["player", "player_ball", "item", "bullet", "frag", "shrapnel", "wall_wood", "wall_iron"];
+
+/// Loads entity type definitions from a file. See `parse_entity_types` for the format.
+pub fn load_entity_types<P: AsRef<Path>>(path: P) -> Result<EntityTypes, String> {
+    config::load(path, "entity types", parse_entity_types)
+}
+
+/// Parses entity type definitions. Each type starts with `[name]`, followed by `key = value`
+/// lines.
+pub fn parse_entity_types(text: &str) -> Result<EntityTypes, String> {
+    let mut entity_types: EntityTypes = Vec::new();
+
+    try!(config::parse_lines(text, |line| {
+        match line {
+            Line::Section(name) => {
+                if entity_types.iter().any(|&(ref other, _)| other == name) {
+                    return Err(format!("entity type {} is defined twice", name));
+                }
+                entity_types.push((name.to_string(), EntityType::new()));
+                Ok(())
+            }
+            Line::KeyValue(key, value) => {
+                match entity_types.last_mut() {
+                    Some(&mut (_, ref mut entity_type)) =>
+                        parse_entity_type_key(entity_type, key, value),
+                    None => Err("expected `[name]` before the first key".to_string()),
+                }
+            }
+        }
+    }));
+
+    for &name in REQUIRED_ENTITY_TYPES {
+        if !entity_types.iter().any(|&(ref other, _)| other == name) {
+            return Err(format!("missing entity type {}", name));
+        }
+    }
+
+    for &(ref name, ref entity_type) in entity_types.iter() {
+        let is_item_spawn = entity_type.behaviors.contains(&Behavior::ItemSpawn) ||
+                            entity_type.appearance == Some(Appearance::ItemSpawn);
+        if is_item_spawn && !entity_type.component_types.contains(&ComponentType::ItemSpawnState) {
+            return Err(format!("item spawn type {} needs the ItemSpawnState net component",
+                               name));
+        }
+    }
+
+    Ok(entity_types)
+}
+
+fn parse_entity_type_key(entity_type: &mut EntityType, key: &str, value: &str)
+                         -> Result<(), String> {
+    let words = value.split_whitespace().collect::<Vec<_>>();
+
+    match key {
+        "net" => {
+            entity_type.component_types = try!(words.iter().map(|w| parse_component_type(w))
+                                                    .collect());
+        }
+        "owner_net" => {
+            entity_type.owner_component_types = try!(words.iter()
+                                                          .map(|w| parse_component_type(w))
+                                                          .collect());
+        }
+        "shape" => {
+            entity_type.shape = Some(match (words.get(0).map(|w| *w), words.len()) {
+                (Some("circle"), 2) => Shape::Circle { radius: try!(parse_number(words[1])) },
+                (Some("square"), 2) => Shape::Square { size: try!(parse_number(words[1])) },
+                (Some("rect"), 3) => Shape::Rect {
+                    width: try!(parse_number(words[1])),
+                    height: try!(parse_number(words[2])),
+                },
+                _ => return Err(format!("expected `circle R`, `square S` or `rect W H`, got {}",
+                                        value)),
+            });
+        }
+        "projectile" => {
+            entity_type.projectile = Some(match (words.get(0).map(|w| *w), words.len()) {
+                (Some("bullet"), 1) => Projectile::Bullet,
+                (Some("frag"), 2) => Projectile::Frag(try!(parse_number(words[1]))),
+                (Some("shrapnel"), 1) => Projectile::Shrapnel,
+                _ => return Err(format!("expected `bullet`, `frag LIFETIME` or `shrapnel`, got {}",
+                                        value)),
+            });
+        }
+        "wall" => {
+            let wall_type = match (words.get(0).map(|w| *w), words.len()) {
+                (Some("wood"), 2) => WallType::Wood,
+                (Some("iron"), 2) => WallType::Iron,
+                _ => return Err(format!("expected `wood WIDTH` or `iron WIDTH`, got {}", value)),
+            };
+            entity_type.wall = Some(Wall {
+                wall_type: wall_type,
+                width: try!(parse_number(words[1])),
+            });
+        }
+        "behaviors" => {
+            entity_type.behaviors = try!(value.split(',')
+                                              .map(|b| b.trim())
+                                              .filter(|b| !b.is_empty())
+                                              .map(parse_behavior)
+                                              .collect());
+        }
+        "appearance" => {
+            entity_type.appearance = Some(match value {
+                "player" => Appearance::Player,
+                "bouncy_enemy" => Appearance::BouncyEnemy,
+                "item" => Appearance::Item,
+                "projectile" => Appearance::Projectile,
+                "wall" => Appearance::Wall,
+                "item_spawn" => Appearance::ItemSpawn,
+                _ => return Err(format!("unknown appearance: {}", value)),
+            });
+        }
+        _ => return Err(format!("unknown key: {}", key)),
+    }
+
+    Ok(())
+}
+
+fn parse_component_type(s: &str) -> Result<ComponentType, String> {
+    COMPONENT_TYPES.iter()
+                   .find(|c| format!("{:?}", c) == s)
+                   .cloned()
+                   .ok_or(format!("unknown net component type: {}", s))
+}
+
+fn parse_behavior(s: &str) -> Result<Behavior, String> {
+    let words = s.split_whitespace().collect::<Vec<_>>();
+    match (words[0], words.len()) {
+        ("player_controller", 1) => Ok(Behavior::PlayerController),
+        ("movement", 1) => Ok(Behavior::Movement),
+        ("rotate", 2) => Ok(Behavior::Rotate(try!(parse_number(words[1])))),
+        ("bouncy_enemy", 1) => Ok(Behavior::BouncyEnemy),
+        ("item_spawn", 1) => Ok(Behavior::ItemSpawn),
+        _ => Err(format!("unknown behavior: {}", s)),
+    }
 }
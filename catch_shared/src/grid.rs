@@ -0,0 +1,221 @@
+use std::f32;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use ecs;
+use ecs::entity::IndexedEntity;
+use ecs::{Aspect, EntityData, EntityIter, ComponentManager};
+use na::Vec2;
+
+use components::HasWallPosition;
+use util::FixedHashState;
+
+pub type Cell = (i32, i32);
+
+/// Uniform grid for quickly finding the values that are close to some area.
+/// Every value is stored in each cell it overlaps with, using its key for deduplication.
+pub struct Grid<K: Hash + Eq + Copy, V> {
+    cell_size: f32,
+    cells: HashMap<Cell, HashMap<K, V, FixedHashState>>,
+    key_cells: HashMap<K, Vec<Cell>>,
+}
+
+impl<K: Hash + Eq + Copy, V> Grid<K, V> {
+    pub fn new(cell_size: f32) -> Grid<K, V> {
+        assert!(cell_size > 0.0);
+
+        Grid {
+            cell_size: cell_size,
+            cells: HashMap::new(),
+            key_cells: HashMap::new(),
+        }
+    }
+
+    pub fn cell(&self, p: Vec2<f32>) -> Cell {
+        ((p.x / self.cell_size).floor() as i32,
+         (p.y / self.cell_size).floor() as i32)
+    }
+
+    pub fn len(&self) -> usize {
+        self.key_cells.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.key_cells.clear();
+    }
+
+    /// Inserts a value into all cells overlapping with the axis-aligned box [min, max].
+    /// Since a value is stored once per cell, `value` is called for each cell.
+    pub fn insert_aabb<F: Fn() -> V>(&mut self, key: K, min: Vec2<f32>, max: Vec2<f32>,
+                                     value: F) {
+        let (x1, y1) = self.cell(min);
+        let (x2, y2) = self.cell(max);
+
+        let mut cells = Vec::new();
+        for x in x1..x2+1 {
+            for y in y1..y2+1 {
+                cells.push((x, y));
+            }
+        }
+
+        self.insert_cells(key, cells, value);
+    }
+
+    /// Inserts a value into all cells that the line segment from `a` to `b` passes through
+    pub fn insert_line<F: Fn() -> V>(&mut self, key: K, a: Vec2<f32>, b: Vec2<f32>, value: F) {
+        let cells = self.line_cells(a, b);
+        self.insert_cells(key, cells, value);
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        if let Some(cells) = self.key_cells.remove(key) {
+            for cell in cells.iter() {
+                let now_empty = {
+                    let values = self.cells.get_mut(cell).unwrap();
+                    values.remove(key);
+                    values.is_empty()
+                };
+                if now_empty {
+                    self.cells.remove(cell);
+                }
+            }
+        }
+    }
+
+    /// Returns the contents of all non-empty cells overlapping with [min, max].
+    /// Values that span multiple cells can be contained more than once.
+    pub fn cells_in_aabb<'a>(&'a self, min: Vec2<f32>, max: Vec2<f32>)
+                             -> Vec<&'a HashMap<K, V, FixedHashState>> {
+        let (x1, y1) = self.cell(min);
+        let (x2, y2) = self.cell(max);
+
+        let mut result = Vec::new();
+        for x in x1..x2+1 {
+            for y in y1..y2+1 {
+                if let Some(values) = self.cells.get(&(x, y)) {
+                    result.push(values);
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns the values in all cells overlapping with [min, max], without duplicates
+    pub fn query_aabb<'a>(&'a self, min: Vec2<f32>, max: Vec2<f32>) -> Vec<(K, &'a V)> {
+        let mut seen = HashSet::new();
+        let mut result = Vec::new();
+        for values in self.cells_in_aabb(min, max) {
+            for (key, value) in values.iter() {
+                if seen.insert(*key) {
+                    result.push((*key, value));
+                }
+            }
+        }
+        result
+    }
+
+    fn insert_cells<F: Fn() -> V>(&mut self, key: K, cells: Vec<Cell>, value: F) {
+        self.remove(&key);
+
+        for cell in cells.iter() {
+            self.cells.entry(*cell)
+                .or_insert_with(|| HashMap::with_hasher(FixedHashState::default()))
+                .insert(key, value());
+        }
+        self.key_cells.insert(key, cells);
+    }
+
+    /// Cells visited by a line segment, using the traversal of Amanatides and Woo
+    fn line_cells(&self, a: Vec2<f32>, b: Vec2<f32>) -> Vec<Cell> {
+        let (mut x, mut y) = self.cell(a);
+        let (end_x, end_y) = self.cell(b);
+        let d = b - a;
+
+        let step_x = if d.x > 0.0 { 1 } else { -1 };
+        let step_y = if d.y > 0.0 { 1 } else { -1 };
+
+        // Distance along the segment (in units of t) to the next cell boundary on each axis
+        let next_boundary = |c: i32, step: i32| {
+            (if step > 0 { c + 1 } else { c }) as f32 * self.cell_size
+        };
+        let mut t_max_x = if d.x != 0.0 { (next_boundary(x, step_x) - a.x) / d.x }
+                          else { f32::INFINITY };
+        let mut t_max_y = if d.y != 0.0 { (next_boundary(y, step_y) - a.y) / d.y }
+                          else { f32::INFINITY };
+        let t_delta_x = if d.x != 0.0 { self.cell_size / d.x.abs() } else { f32::INFINITY };
+        let t_delta_y = if d.y != 0.0 { self.cell_size / d.y.abs() } else { f32::INFINITY };
+
+        let mut cells = vec![(x, y)];
+        let max_steps = (end_x - x).abs() + (end_y - y).abs();
+        for _ in 0..max_steps {
+            if t_max_x < t_max_y {
+                x += step_x;
+                t_max_x += t_delta_x;
+            } else {
+                y += step_y;
+                t_max_y += t_delta_y;
+            }
+            cells.push((x, y));
+        }
+        cells
+    }
+}
+
+/// Keeps the walls matching an aspect in a uniform grid, so that movement code only needs to
+/// check the walls close to a moving entity. Walls are expected not to move after creation.
+pub struct WallGrid<T: ComponentManager> {
+    pub aspect: Aspect<T>,
+    grid: Grid<ecs::Entity, IndexedEntity<T>>,
+}
+
+pub const WALL_GRID_CELL_SIZE: f32 = 64.0;
+
+impl<T: ComponentManager + HasWallPosition> WallGrid<T> {
+    pub fn new(aspect: Aspect<T>) -> WallGrid<T> {
+        WallGrid {
+            aspect: aspect,
+            grid: Grid::new(WALL_GRID_CELL_SIZE),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.grid.len()
+    }
+
+    /// Returns the walls that a shape with radius `r` can possibly hit when moving from `a` by
+    /// `delta`
+    pub fn near_movement<'a>(&'a self, a: Vec2<f32>, delta: Vec2<f32>, r: f32)
+                             -> Vec<EntityData<'a, T>> {
+        let b = a + delta;
+        let min = Vec2::new(a.x.min(b.x) - r, a.y.min(b.y) - r);
+        let max = Vec2::new(a.x.max(b.x) + r, a.y.max(b.y) + r);
+
+        let mut seen = HashSet::new();
+        let mut result = Vec::new();
+        for values in self.grid.cells_in_aabb(min, max) {
+            for wall in EntityIter::Map(values.values()) {
+                if seen.insert(**wall) {
+                    result.push(wall);
+                }
+            }
+        }
+        result
+    }
+
+    pub fn activated(&mut self, entity: &EntityData<T>, components: &T) {
+        if self.aspect.check(entity, components) {
+            let p = components.wall_position()[*entity].clone();
+            self.grid.insert_line(***entity, p.pos_a, p.pos_b, || (**entity).__clone());
+        }
+    }
+
+    pub fn reactivated(&mut self, entity: &EntityData<T>, components: &T) {
+        self.grid.remove(&***entity);
+        self.activated(entity, components);
+    }
+
+    pub fn deactivated(&mut self, entity: &EntityData<T>, _: &T) {
+        self.grid.remove(&***entity);
+    }
+}
@@ -4,8 +4,12 @@ extern crate rustc_serialize;
 extern crate bincode;
 #[macro_use] extern crate ecs;
 extern crate tiled;
+extern crate xml;
+extern crate flate2;
 extern crate vecmath as vecmath_lib;
 extern crate nalgebra as na;
+extern crate renet as enet;
+extern crate rand;
 
 pub mod net;
 pub mod components;
@@ -13,16 +17,26 @@ pub mod tick;
 pub mod player;
 #[macro_use] pub mod util;
 pub mod map;
+pub mod mapgen;
 pub mod math;
+pub mod config;
 pub mod entities;
+pub mod balance;
+pub mod tuning;
 pub mod movement;
 pub mod services;
 pub mod net_components;
+pub mod grid;
+pub mod transport;
+pub mod discovery;
 
-pub use map::Map;
+pub use map::{Map, MapError};
+pub use mapgen::MapGenParams;
 pub use tick::{TickState, Tick};
 pub use player::{Item, PlayerInputKey, PlayerInput, PlayerInfo, PlayerStats};
 pub use entities::{EntityType, EntityTypes};
+pub use balance::Balance;
+pub use tuning::Tuning;
 
 pub type EntityId = u32;
 pub type EntityTypeId = u32;
@@ -39,11 +53,30 @@ pub type TickNumber = u32;
 /// Sent to the clients by the server after connecting
 #[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 pub struct GameInfo {
+    // Shown to players when connecting and in the server list
+    pub server_name: String,
+    pub motd: String,
+
     pub map_name: String,
+
+    // If given, the map is generated from these parameters instead of being loaded from a file
+    pub map_gen: Option<MapGenParams>,
+
     pub entity_types: EntityTypes,
+    pub balance: Balance,
+    pub tuning: Tuning,
     pub ticks_per_second: u32,
 }
 
+impl GameInfo {
+    pub fn load_map(&self) -> Result<Map, MapError> {
+        match self.map_gen {
+            Some(ref params) => Ok(mapgen::generate(params)),
+            None => Map::load(&self.map_name),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, RustcEncodable, RustcDecodable)]
 pub enum DeathReason {
     Projectile,
@@ -59,6 +92,17 @@ pub enum GameEvent {
     PlayerLeave(PlayerId),
     UpdatePlayerStats(Vec<(PlayerId, PlayerStats)>),
 
+    // Files that were reloaded on the server
+    UpdateBalance(Balance),
+    UpdateTuning(Tuning),
+    ReloadMap,
+
+    Chat {
+        player_id: PlayerId,
+        text: String,
+        team_only: bool,
+    },
+
     PlayerDied {
         player_id: PlayerId,
         position: na::Vec2<f32>, 
@@ -100,3 +144,28 @@ pub enum GameEvent {
         strength: f32,
     },
 }
+
+impl GameEvent {
+    /// Name of the variant, e.g. for counting events
+    pub fn name(&self) -> &'static str {
+        match *self {
+            GameEvent::InitialPlayerList(..) => "InitialPlayerList",
+            GameEvent::PlayerJoin(..) => "PlayerJoin",
+            GameEvent::PlayerLeave(..) => "PlayerLeave",
+            GameEvent::UpdatePlayerStats(..) => "UpdatePlayerStats",
+            GameEvent::UpdateBalance(..) => "UpdateBalance",
+            GameEvent::UpdateTuning(..) => "UpdateTuning",
+            GameEvent::ReloadMap => "ReloadMap",
+            GameEvent::Chat { .. } => "Chat",
+            GameEvent::PlayerDied { .. } => "PlayerDied",
+            GameEvent::CreateEntity(..) => "CreateEntity",
+            GameEvent::RemoveEntity(..) => "RemoveEntity",
+            GameEvent::PlayerDash { .. } => "PlayerDash",
+            GameEvent::PlayerFlip { .. } => "PlayerFlip",
+            GameEvent::PlayerTakeItem { .. } => "PlayerTakeItem",
+            GameEvent::PlayerEquipItem { .. } => "PlayerEquipItem",
+            GameEvent::EnemyDied { .. } => "EnemyDied",
+            GameEvent::ProjectileImpact { .. } => "ProjectileImpact",
+        }
+    }
+}
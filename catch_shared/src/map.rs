@@ -1,9 +1,21 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::error::Error;
+use std::f32;
 use std::fs::File;
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 use na::Vec2;
 
 use tiled;
+use xml::reader::{EventReader, XmlEvent};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use rustc_serialize::base64::{ToBase64, STANDARD};
+
+use player::ITEM_NAMES;
+use entities::{EntityTypes, Behavior};
 
 #[derive(Copy, Clone)]
 pub struct Tile {
@@ -13,24 +25,343 @@ pub struct Tile {
     pub y: usize
 }
 
-struct Layer {
+// Tiled stores flipping flags in the upper bits of tile numbers
+const TILE_FLAGS_MASK: u32 = 0xE0000000;
+
+pub struct Layer {
+    pub name: String,
+
+    // Indexed by [y][x]
     pub tiles: Vec<Vec<Option<Tile>>>
 }
 
+/// A tileset image that is cut into tiles of equal size
+pub struct Tileset {
+    pub name: String,
+
+    // Number of the first tile of the tileset in the map file
+    pub first_gid: u32,
+
+    // Relative to the working directory, not to the map file
+    pub image_path: PathBuf,
+
+    pub image_width: usize,
+    pub image_height: usize,
+    pub tile_width: usize,
+    pub tile_height: usize,
+}
+
+impl Tileset {
+    pub fn num_tiles_x(&self) -> usize {
+        self.image_width / self.tile_width
+    }
+
+    pub fn num_tiles_y(&self) -> usize {
+        self.image_height / self.tile_height
+    }
+}
+
 pub struct Map {
-    map: tiled::Map,
-    //layers: Vec<Layer>,
+    // Size in tiles
+    width: usize,
+    height: usize,
+
+    tile_width: usize,
+    tile_height: usize,
+
+    layers: Vec<Layer>,
+    tilesets: Vec<Tileset>,
     pub objects: Vec<MapObject>,
-    pub lines: Vec<(Vec2<f32>, Vec2<f32>)>,
+    pub lines: Vec<MapLine>,
+}
+
+/// Whether the server knows how to create objects of the given type from a map. Apart from
+/// player spawns, this is any entity type that is an item spawn or a bouncy enemy.
+pub fn is_object_type(type_str: &str, entity_types: &EntityTypes) -> bool {
+    type_str == "player_spawn" ||
+    entity_types.iter().any(|&(ref name, ref entity_type)| {
+        name == type_str &&
+        (entity_type.behaviors.contains(&Behavior::ItemSpawn) ||
+         entity_type.behaviors.contains(&Behavior::BouncyEnemy))
+    })
+}
+
+/// Identifies an object in a map file for diagnostics. Tiled objects don't need to have unique
+/// names, so we also keep the index in the object group.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ObjectSource {
+    pub group: String,
+    pub index: usize,
+
+    // As given in Tiled. The name can be empty, and objects from old map files, generated maps
+    // or the editor have no id.
+    pub name: String,
+    pub id: Option<u32>,
+}
+
+impl fmt::Display for ObjectSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = if self.name.is_empty() {
+            "unnamed object".to_string()
+        } else {
+            format!("object \"{}\"", self.name)
+        };
+
+        match self.id {
+            Some(id) => write!(f, "{} (id {}) in group \"{}\"", name, id, self.group),
+            None => write!(f, "{} #{} in group \"{}\"", name, self.index, self.group),
+        }
+    }
+}
+
+/// Custom properties of a Tiled object
+pub type Properties = BTreeMap<String, String>;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ObjectShape {
+    Rect,
+    Ellipse,
 }
 
 /// Information about an entity on a map
 pub struct MapObject {
+    // Top left corner of the bounding box
     pub x: f32,
     pub y: f32,
     pub width: f32,
     pub height: f32,
+    pub shape: ObjectShape,
     pub type_str: String,
+    pub properties: Properties,
+    pub source: ObjectSource,
+}
+
+impl MapObject {
+    pub fn property(&self, name: &str) -> Option<&str> {
+        self.properties.get(name).map(|value| &value[..])
+    }
+
+    /// Maps two values in [0, 1] to a point in the object's area. Using uniformly distributed
+    /// values gives uniformly distributed points.
+    pub fn point_in_area(&self, u: f32, v: f32) -> Vec2<f32> {
+        match self.shape {
+            ObjectShape::Rect =>
+                Vec2::new(self.x + u * self.width, self.y + v * self.height),
+            ObjectShape::Ellipse => {
+                let r = u.sqrt();
+                let phi = v * f32::consts::PI * 2.0;
+                Vec2::new(self.x + self.width / 2.0 * (1.0 + r * phi.cos()),
+                          self.y + self.height / 2.0 * (1.0 + r * phi.sin()))
+            }
+        }
+    }
+}
+
+/// A wall segment, created from polylines and polygons
+pub struct MapLine {
+    pub a: Vec2<f32>,
+    pub b: Vec2<f32>,
+    pub properties: Properties,
+    pub source: ObjectSource,
+}
+
+impl MapLine {
+    /// Name of the wall entity type to create for the line
+    pub fn wall_type(&self) -> Option<String> {
+        match self.properties.get("wall_type") {
+            Some(wall_type) => WALL_TYPES.iter()
+                                         .find(|&&(name, _)| name == *wall_type)
+                                         .map(|&(_, entity_type)| entity_type.to_string()),
+            None => Some("wall_wood".to_string()),
+        }
+    }
+}
+
+/// Possible values of the `wall_type` property and the entity types they create
+pub const WALL_TYPES: &'static [(&'static str, &'static str)] =
+    &[("wood", "wall_wood"), ("iron", "wall_iron")];
+
+/// Seconds until an item spawn spawns a new item after its item was taken, if the map doesn't
+/// give a `respawn_time`
+pub const DEFAULT_ITEM_RESPAWN_S: f32 = 5.0;
+
+/// Parses a comma-separated list of `item_name[:weight]` entries, as used in the `items` property
+/// of item spawns. Items without a weight have a weight of 1.
+pub fn parse_item_pool(s: &str) -> Result<Vec<(String, f32)>, String> {
+    s.split(',')
+     .map(|entry| entry.trim())
+     .filter(|entry| !entry.is_empty())
+     .map(|entry| {
+         let parts = entry.splitn(2, ':').map(|part| part.trim()).collect::<Vec<_>>();
+         let name = parts[0];
+         if !ITEM_NAMES.contains(&name) {
+             return Err(format!("unknown item: {}", name));
+         }
+
+         let weight = match parts.get(1) {
+             Some(weight) => try!(parse_non_negative(weight)),
+             None => 1.0,
+         };
+         Ok((name.to_string(), weight))
+     })
+     .collect()
+}
+
+/// Escapes a string for use in an XML attribute
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+     .replace('<', "&lt;")
+     .replace('>', "&gt;")
+     .replace('"', "&quot;")
+}
+
+fn parse_bool(s: &str) -> Result<bool, String> {
+    match s {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("expected true or false, got {}", s)),
+    }
+}
+
+fn parse_non_negative(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(x) if x >= 0.0 => Ok(x),
+        _ => Err(format!("expected a non-negative number, got {}", s)),
+    }
+}
+
+/// Parses the `respawn_time` property of item spawns
+pub fn respawn_time_property(object: &MapObject) -> Option<Result<f32, String>> {
+    object.property("respawn_time").map(parse_non_negative)
+}
+
+/// Parses the `attract` property of bouncy enemies
+pub fn attract_property(object: &MapObject) -> Option<Result<bool, String>> {
+    object.property("attract").map(parse_bool)
+}
+
+#[derive(Debug)]
+pub enum MapError {
+    Io(PathBuf, io::Error),
+    Write(PathBuf, io::Error),
+    Parse(PathBuf, String),
+    Tileset {
+        name: String,
+        reason: String,
+    },
+    TooFewPoints {
+        source: ObjectSource,
+        shape: &'static str,
+        x: f32,
+        y: f32,
+    },
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MapError::Io(ref path, ref error) =>
+                write!(f, "could not open {:?}: {}", path, error),
+            MapError::Write(ref path, ref error) =>
+                write!(f, "could not write {:?}: {}", path, error),
+            MapError::Parse(ref path, ref error) =>
+                write!(f, "invalid map {:?}: {}", path, error),
+            MapError::Tileset { ref name, ref reason } =>
+                write!(f, "tileset \"{}\": {}", name, reason),
+            MapError::TooFewPoints { ref source, shape, x, y } =>
+                write!(f, "{} at ({}, {}) is a {} with too few points", source, x, y, shape),
+        }
+    }
+}
+
+impl Error for MapError {
+    fn description(&self) -> &str {
+        match *self {
+            MapError::Io(_, _) => "could not open map",
+            MapError::Write(_, _) => "could not write map",
+            MapError::Parse(_, _) => "invalid map",
+            MapError::Tileset { .. } => "invalid tileset",
+            MapError::TooFewPoints { .. } => "too few points",
+        }
+    }
+}
+
+/// Something that is written as one Tiled object when saving
+enum SavedObject<'a> {
+    Object(&'a MapObject),
+
+    // Connected lines that were loaded from, or are to be saved as, one polyline or polygon
+    Lines(Vec<&'a MapLine>),
+}
+
+impl<'a> SavedObject<'a> {
+    fn source(&self) -> &'a ObjectSource {
+        match *self {
+            SavedObject::Object(object) => &object.source,
+            SavedObject::Lines(ref lines) => &lines[0].source,
+        }
+    }
+}
+
+/// Object information that we read from the map file ourselves
+struct ObjectAttributes {
+    name: String,
+    id: Option<u32>,
+    type_str: String,
+    properties: Properties,
+}
+
+/// Things that are wrong with a map that loaded fine, as found by `Map::check`
+#[derive(Clone, Debug)]
+pub enum MapProblem {
+    UnknownObjectType {
+        source: ObjectSource,
+        type_str: String,
+        x: f32,
+        y: f32,
+    },
+    InvalidProperty {
+        source: ObjectSource,
+        name: String,
+        reason: String,
+    },
+    NoPlayerSpawn,
+    DegenerateLine {
+        source: ObjectSource,
+        p: Vec2<f32>,
+    },
+    ObjectOutOfBounds {
+        source: ObjectSource,
+        type_str: String,
+        x: f32,
+        y: f32,
+    },
+    LineOutOfBounds {
+        source: ObjectSource,
+        a: Vec2<f32>,
+        b: Vec2<f32>,
+    },
+}
+
+impl fmt::Display for MapProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MapProblem::UnknownObjectType { ref source, ref type_str, x, y } =>
+                write!(f, "{} at ({}, {}) has unknown type \"{}\"", source, x, y, type_str),
+            MapProblem::InvalidProperty { ref source, ref name, ref reason } =>
+                write!(f, "{} has invalid property {}: {}", source, name, reason),
+            MapProblem::NoPlayerSpawn =>
+                write!(f, "there are no player_spawn objects"),
+            MapProblem::DegenerateLine { ref source, p } =>
+                write!(f, "line of {} at ({}, {}) has zero length", source, p.x, p.y),
+            MapProblem::ObjectOutOfBounds { ref source, ref type_str, x, y } =>
+                write!(f, "{} of type \"{}\" at ({}, {}) is outside of the map",
+                       source, type_str, x, y),
+            MapProblem::LineOutOfBounds { ref source, a, b } =>
+                write!(f, "line of {} from ({}, {}) to ({}, {}) is outside of the map",
+                       source, a.x, a.y, b.x, b.y),
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -49,20 +380,35 @@ impl LayerId {
 }
 
 impl Map {
+    /// Creates a map without any tile layers, e.g. for generated maps
+    pub fn new(width: usize, height: usize, tile_width: usize, tile_height: usize,
+               objects: Vec<MapObject>, lines: Vec<MapLine>) -> Map {
+        Map {
+            width: width,
+            height: height,
+            tile_width: tile_width,
+            tile_height: tile_height,
+            layers: Vec::new(),
+            tilesets: Vec::new(),
+            objects: objects,
+            lines: lines,
+        }
+    }
+
     pub fn width(&self) -> usize {
-        self.map.width as usize
+        self.width
     }
 
     pub fn height(&self) -> usize {
-        self.map.height as usize
+        self.height
     }
 
     pub fn tile_width(&self) -> usize {
-        self.map.tile_width as usize
+        self.tile_width
     }
 
     pub fn tile_height(&self) -> usize {
-        self.map.tile_height as usize
+        self.tile_height
     }
 
     pub fn width_pixels(&self) -> usize {
@@ -74,57 +420,379 @@ impl Map {
     }
     
     pub fn is_pos_valid(&self, x: usize, y: usize) -> bool {
-        x < self.map.width as usize && y < self.map.height as usize
+        x < self.width && y < self.height
+    }
+
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
     }
 
-    /*pub fn get_tile(&self, layer_id: LayerId, x: usize, y: usize) -> Option<Tile> {
-        self.layers[layer_id.to_index()].tiles[y][x]
+    pub fn tilesets(&self) -> &[Tileset] {
+        &self.tilesets
     }
 
-    pub fn tileset_image_paths(&self) -> Vec<String> {
-        self.map.tilesets.iter()
-            .map(|tileset| tileset.images[0].source.clone())
-            .collect()
-    }*/
+    /// Returns the tile at the given position, or None if the tile is empty, the position is
+    /// outside of the map or the map does not have the layer
+    pub fn get_tile(&self, layer_id: LayerId, x: usize, y: usize) -> Option<Tile> {
+        self.layers.get(layer_id.to_index())
+                   .and_then(|layer| layer.tiles.get(y))
+                   .and_then(|row| row.get(x))
+                   .and_then(|tile| *tile)
+    }
 
     /// Loads a tiled map from the given path. If the file is not found or has an invalid format,
     /// Err is returned.
-    pub fn load<P: AsRef<Path>>(path: P) -> Result<Map, String> {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Map, MapError> {
+        Map::load_with_text(path).map(|(map, _)| map)
+    }
+
+    /// Loads a map and also returns the contents of its file, so that the exact same map can
+    /// be parsed again later with `Map::parse`, e.g. when replaying a game
+    pub fn load_with_text<P: AsRef<Path>>(path: P) -> Result<(Map, String), MapError> {
         info!("loading map {}", path.as_ref().to_str().unwrap());
 
-        let file = match File::open(path) {
+        let mut text = String::new();
+        if let Err(error) = File::open(path.as_ref())
+                                .and_then(|mut file| file.read_to_string(&mut text)) {
+            return Err(MapError::Io(path.as_ref().to_path_buf(), error));
+        }
+
+        let map = try!(Map::parse(&text, path));
+        Ok((map, text))
+    }
+
+    /// Parses the contents of a .tmx file. The path is only used for errors and to find the
+    /// tileset images, which are given relative to the map file.
+    pub fn parse<P: AsRef<Path>>(text: &str, path: P) -> Result<Map, MapError> {
+        let dir = path.as_ref().parent().unwrap_or(Path::new("")).to_path_buf();
+
+        let map = match tiled::parse(text.as_bytes()) {
+            Ok(map) => map,
+            Err(error) => return Err(MapError::Parse(path.as_ref().to_path_buf(),
+                                                     format!("{:?}", error)))
+        };
+
+        let attributes = try!(Map::read_object_attributes(text, path.as_ref()));
+        if attributes.len() != map.object_groups.len() ||
+           attributes.iter().zip(map.object_groups.iter())
+                     .any(|(a, group)| a.len() != group.objects.len()) {
+            return Err(MapError::Parse(path.as_ref().to_path_buf(),
+                                       "could not match objects with their properties"
+                                       .to_string()));
+        }
+
+        Map::from_tiled(map, attributes, &dir)
+    }
+
+    /// Looks for things that make the map unplayable or that are probably mistakes
+    pub fn check(&self, entity_types: &EntityTypes) -> Vec<MapProblem> {
+        let mut problems = Vec::new();
+
+        let width = self.width_pixels() as f32;
+        let height = self.height_pixels() as f32;
+        let in_bounds = |p: Vec2<f32>| p.x >= 0.0 && p.y >= 0.0 && p.x <= width && p.y <= height;
+
+        for object in self.objects.iter() {
+            let invalid_property = |name: &str, reason: String| MapProblem::InvalidProperty {
+                source: object.source.clone(),
+                name: name.to_string(),
+                reason: reason,
+            };
+            if let Some(Err(error)) = attract_property(object) {
+                problems.push(invalid_property("attract", error));
+            }
+            if let Some(items) = object.property("items") {
+                if let Err(error) = parse_item_pool(items) {
+                    problems.push(invalid_property("items", error));
+                }
+            }
+            if let Some(Err(error)) = respawn_time_property(object) {
+                problems.push(invalid_property("respawn_time", error));
+            }
+
+            if !is_object_type(&object.type_str, entity_types) {
+                problems.push(MapProblem::UnknownObjectType {
+                    source: object.source.clone(),
+                    type_str: object.type_str.clone(),
+                    x: object.x,
+                    y: object.y,
+                });
+            }
+
+            let min = Vec2::new(object.x, object.y);
+            let max = Vec2::new(object.x + object.width, object.y + object.height);
+            if !in_bounds(min) || !in_bounds(max) {
+                problems.push(MapProblem::ObjectOutOfBounds {
+                    source: object.source.clone(),
+                    type_str: object.type_str.clone(),
+                    x: object.x,
+                    y: object.y,
+                });
+            }
+        }
+
+        if !self.objects.iter().any(|object| &object.type_str == "player_spawn") {
+            problems.push(MapProblem::NoPlayerSpawn);
+        }
+
+        for line in self.lines.iter() {
+            if line.a == line.b {
+                problems.push(MapProblem::DegenerateLine {
+                    source: line.source.clone(),
+                    p: line.a,
+                });
+            }
+            if !in_bounds(line.a) || !in_bounds(line.b) {
+                problems.push(MapProblem::LineOutOfBounds {
+                    source: line.source.clone(),
+                    a: line.a,
+                    b: line.b,
+                });
+            }
+            if line.wall_type().is_none() {
+                problems.push(MapProblem::InvalidProperty {
+                    source: line.source.clone(),
+                    name: "wall_type".to_string(),
+                    reason: format!("unknown wall type {}", line.properties["wall_type"]),
+                });
+            }
+        }
+
+        problems
+    }
+
+    /// Writes the map to a .tmx file that can be loaded again with `Map::load` or opened in
+    /// Tiled. Tileset images are referenced relative to the directory of the given path.
+    /// Flipping of tiles is not kept.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), MapError> {
+        info!("saving map {}", path.as_ref().to_str().unwrap());
+
+        let xml = self.to_text(path.as_ref());
+
+        let mut file = match File::create(path.as_ref()) {
             Ok(file) => file,
-            Err(_) => return Err("Failed to open file".to_string())
+            Err(error) => return Err(MapError::Write(path.as_ref().to_path_buf(), error)),
         };
+        match file.write_all(xml.as_bytes()) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(MapError::Write(path.as_ref().to_path_buf(), error)),
+        }
+    }
+
+    /// The text that `save` writes to the given path, which `Map::parse` turns back into the map
+    pub fn to_text<P: AsRef<Path>>(&self, path: P) -> String {
+        self.to_xml(path.as_ref().parent().unwrap_or(Path::new("")))
+    }
 
-        match tiled::parse(file) {
-            Ok(map) => Map::from_tiled(map),
-            Err(_) => Err("Invalid map".to_string())
+    fn to_xml(&self, dir: &Path) -> String {
+        let mut xml = String::new();
+
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!("<map version=\"1.0\" orientation=\"orthogonal\" width=\"{}\" \
+                               height=\"{}\" tilewidth=\"{}\" tileheight=\"{}\">\n",
+                              self.width, self.height, self.tile_width, self.tile_height));
+
+        for tileset in self.tilesets.iter() {
+            let source = tileset.image_path.strip_prefix(dir).unwrap_or(&tileset.image_path);
+            xml.push_str(&format!(" <tileset firstgid=\"{}\" name=\"{}\" tilewidth=\"{}\" \
+                                   tileheight=\"{}\">\n",
+                                  tileset.first_gid, escape(&tileset.name),
+                                  tileset.tile_width, tileset.tile_height));
+            xml.push_str(&format!("  <image source=\"{}\" width=\"{}\" height=\"{}\"/>\n",
+                                  escape(&source.to_string_lossy()),
+                                  tileset.image_width, tileset.image_height));
+            xml.push_str(" </tileset>\n");
+        }
+
+        for layer in self.layers.iter() {
+            xml.push_str(&format!(" <layer name=\"{}\" width=\"{}\" height=\"{}\">\n",
+                                  escape(&layer.name), self.width, self.height));
+            xml.push_str("  <data encoding=\"base64\" compression=\"zlib\">\n");
+            xml.push_str(&format!("   {}\n", self.encode_layer(layer)));
+            xml.push_str("  </data>\n");
+            xml.push_str(" </layer>\n");
         }
+
+        for (group, saved_objects) in self.saved_object_groups() {
+            xml.push_str(&format!(" <objectgroup name=\"{}\">\n", escape(&group)));
+            for saved_object in saved_objects.iter() {
+                xml.push_str(&Map::object_to_xml(saved_object));
+            }
+            xml.push_str(" </objectgroup>\n");
+        }
+
+        xml.push_str("</map>\n");
+        xml
+    }
+
+    /// Encodes tile numbers the same way Tiled does by default
+    fn encode_layer(&self, layer: &Layer) -> String {
+        let mut bytes = Vec::new();
+        for row in layer.tiles.iter() {
+            for tile in row.iter() {
+                let number = match *tile {
+                    Some(tile) => {
+                        let tileset = &self.tilesets[tile.tileset];
+                        tileset.first_gid +
+                            (tile.y * tileset.num_tiles_x() + tile.x) as u32
+                    }
+                    None => 0,
+                };
+                bytes.extend([number as u8, (number >> 8) as u8,
+                              (number >> 16) as u8, (number >> 24) as u8].iter().cloned());
+            }
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(&bytes).unwrap();
+        encoder.finish().unwrap().to_base64(STANDARD)
     }
 
-    fn from_tiled(map: tiled::Map) -> Result<Map, String> {
-        /*if map.layers.len() != 2 {
-            return Err("Too many layers in the map".to_string());
+    /// Groups objects and lines by the object group they came from, keeping the order of the
+    /// file they were loaded from
+    fn saved_object_groups(&self) -> Vec<(String, Vec<SavedObject>)> {
+        let mut saved_objects: Vec<SavedObject> =
+            self.objects.iter().map(|object| SavedObject::Object(object)).collect();
+
+        for line in self.lines.iter() {
+            if let Some(&mut SavedObject::Lines(ref mut lines)) = saved_objects.last_mut() {
+                let last = lines[lines.len() - 1];
+                if last.source == line.source && last.b == line.a {
+                    lines.push(line);
+                    continue;
+                }
+            }
+            saved_objects.push(SavedObject::Lines(vec![line]));
         }
 
+        let mut groups: Vec<(String, Vec<SavedObject>)> = Vec::new();
+        for saved_object in saved_objects.into_iter() {
+            let group = saved_object.source().group.clone();
+            match groups.iter().position(|&(ref name, _)| *name == group) {
+                Some(i) => groups[i].1.push(saved_object),
+                None => groups.push((group, vec![saved_object])),
+            }
+        }
+
+        for &mut (_, ref mut saved_objects) in groups.iter_mut() {
+            saved_objects.sort_by(|a, b| a.source().index.cmp(&b.source().index));
+        }
+
+        groups
+    }
+
+    fn object_to_xml(saved_object: &SavedObject) -> String {
+        let properties_xml = |properties: &Properties| {
+            if properties.is_empty() {
+                return String::new();
+            }
+            let mut xml = "   <properties>\n".to_string();
+            for (name, value) in properties.iter() {
+                xml.push_str(&format!("    <property name=\"{}\" value=\"{}\"/>\n",
+                                      escape(name), escape(value)));
+            }
+            xml.push_str("   </properties>\n");
+            xml
+        };
+
+        // Keeps the name and id, so that problems found later still point at the same object
+        let source_xml = |source: &ObjectSource| {
+            let mut xml = String::new();
+            if let Some(id) = source.id {
+                xml.push_str(&format!(" id=\"{}\"", id));
+            }
+            if !source.name.is_empty() {
+                xml.push_str(&format!(" name=\"{}\"", escape(&source.name)));
+            }
+            xml
+        };
+
+        match *saved_object {
+            SavedObject::Object(object) => {
+                let shape = match object.shape {
+                    ObjectShape::Rect => "",
+                    ObjectShape::Ellipse => "   <ellipse/>\n",
+                };
+                format!("  <object{} type=\"{}\" x=\"{}\" y=\"{}\" width=\"{}\" \
+                         height=\"{}\">\n{}{}  </object>\n",
+                        source_xml(&object.source), escape(&object.type_str), object.x, object.y,
+                        object.width, object.height,
+                        properties_xml(&object.properties), shape)
+            }
+            SavedObject::Lines(ref lines) => {
+                let origin = lines[0].a;
+                let mut points = vec![origin];
+                points.extend(lines.iter().map(|line| line.b));
+
+                // A loop of lines is what we get from loading a polygon
+                let closed = lines.len() >= 3 && lines[lines.len() - 1].b == origin;
+                if closed {
+                    points.pop();
+                }
+
+                let points_str = points.iter()
+                                       .map(|p| format!("{},{}", p.x - origin.x, p.y - origin.y))
+                                       .collect::<Vec<_>>()
+                                       .join(" ");
+                format!("  <object{} x=\"{}\" y=\"{}\">\n{}   <{} points=\"{}\"/>\n  \
+                         </object>\n",
+                        source_xml(&lines[0].source), origin.x, origin.y,
+                        properties_xml(&lines[0].properties),
+                        if closed { "polygon" } else { "polyline" }, points_str)
+            }
+        }
+    }
+
+    fn from_tiled(map: tiled::Map, attributes: Vec<Vec<ObjectAttributes>>, dir: &Path)
+                  -> Result<Map, MapError> {
+        let tilesets = try!(Map::convert_tilesets(&map.tilesets, dir));
+
         let layers = map.layers.iter().map(|layer| {
             Map::convert_layer(&map.tilesets, &layer)
-        }).collect();*/
+        }).collect();
 
-        let (objects, lines) = try!(Map::convert_objects(&map.object_groups));
+        let (objects, lines) = try!(Map::convert_objects(&map.object_groups, attributes));
 
         Ok(Map {
-            map: map,
-            //layers: layers,
+            width: map.width as usize,
+            height: map.height as usize,
+            tile_width: map.tile_width as usize,
+            tile_height: map.tile_height as usize,
+            layers: layers,
+            tilesets: tilesets,
             objects: objects,
             lines: lines,
         })
     }
 
-    /*fn tile_from_number(tilesets: &Vec<tiled::Tileset>,
+    fn convert_tilesets(tilesets: &Vec<tiled::Tileset>, dir: &Path)
+                        -> Result<Vec<Tileset>, MapError> {
+        tilesets.iter().map(|tileset| {
+            if tileset.images.len() != 1 {
+                return Err(MapError::Tileset {
+                    name: tileset.name.clone(),
+                    reason: "needs to have exactly one image".to_string(),
+                });
+            }
+            let image = &tileset.images[0];
+
+            Ok(Tileset {
+                name: tileset.name.clone(),
+                first_gid: tileset.first_gid,
+                image_path: dir.join(&image.source),
+                image_width: image.width as usize,
+                image_height: image.height as usize,
+                tile_width: tileset.tile_width as usize,
+                tile_height: tileset.tile_height as usize,
+            })
+        }).collect()
+    }
+
+    fn tile_from_number(tilesets: &Vec<tiled::Tileset>,
                         number: u32) 
                         -> Option<Tile> {
+        let number = number & !TILE_FLAGS_MASK;
+
         for (i, tileset) in tilesets.iter().enumerate() {
             let num_tiles_w = tileset.images[0].width as usize /
                               tileset.tile_width as usize;
@@ -136,7 +804,7 @@ impl Map {
                number < tileset.first_gid + num_tiles as u32 {
                 let number_rel = number as usize -
                                  tileset.first_gid as usize;
-                let x = number_rel % num_tiles_h;
+                let x = number_rel % num_tiles_w;
                 let y = number_rel / num_tiles_w;
 
                 return Some(Tile { tileset: i,
@@ -158,39 +826,132 @@ impl Map {
         }).collect();
 
         Layer {
+            name: layer.name.clone(),
             tiles: tiles,
         }
-    }*/
+    }
+
+    /// Reads the type and custom properties of every object, grouped by object group.
+    /// The tiled crate only gives us the geometry of objects, so we parse the file again.
+    fn read_object_attributes(text: &str, path: &Path)
+                              -> Result<Vec<Vec<ObjectAttributes>>, MapError> {
+        let parse_error = |reason: String| MapError::Parse(path.to_path_buf(), reason);
+
+        let mut groups: Vec<Vec<ObjectAttributes>> = Vec::new();
+        let mut depth = 0;
+        let mut in_object = false;
+
+        for event in EventReader::new(text.as_bytes()) {
+            match event {
+                Ok(XmlEvent::StartElement { name, attributes, .. }) => {
+                    depth += 1;
+                    let attribute = |key: &str| {
+                        attributes.iter()
+                                  .find(|attribute| attribute.name.local_name == key)
+                                  .map(|attribute| attribute.value.clone())
+                    };
+
+                    // Tilesets can contain object groups too, so only look at the top level
+                    if name.local_name == "objectgroup" && depth == 2 {
+                        groups.push(Vec::new());
+                    } else if name.local_name == "object" && depth == 3 {
+                        groups.last_mut().unwrap().push(ObjectAttributes {
+                            name: attribute("name").unwrap_or(String::new()),
+                            id: attribute("id").and_then(|id| id.parse().ok()),
+                            type_str: attribute("type").unwrap_or(String::new()),
+                            properties: Properties::new(),
+                        });
+                        in_object = true;
+                    } else if name.local_name == "property" && in_object {
+                        let key = try!(attribute("name").ok_or(
+                            parse_error("object property without name".to_string())));
+                        let value = attribute("value").unwrap_or(String::new());
+                        groups.last_mut().unwrap().last_mut().unwrap()
+                              .properties.insert(key, value);
+                    }
+                }
+                Ok(XmlEvent::EndElement { name }) => {
+                    depth -= 1;
+                    if name.local_name == "object" && depth == 2 {
+                        in_object = false;
+                    }
+                }
+                Ok(_) => (),
+                Err(error) => return Err(parse_error(format!("{}", error))),
+            }
+        }
+
+        Ok(groups)
+    }
 
     /// Converts from tiled's MapObject to ours
-    fn convert_objects(object_groups: &Vec<tiled::ObjectGroup>) 
-                       -> Result<(Vec<MapObject>, Vec<(Vec2<f32>, Vec2<f32>)>), String> {
+    fn convert_objects(object_groups: &Vec<tiled::ObjectGroup>,
+                       attributes: Vec<Vec<ObjectAttributes>>) 
+                       -> Result<(Vec<MapObject>, Vec<MapLine>), MapError> {
         let mut objects = Vec::new();
         let mut lines = Vec::new();
-        for object_group in object_groups.iter() {
-            for object in object_group.objects.iter() {
-                match object {
-                    &tiled::Object::Rect { ref x, ref y, ref width, ref height,
-                                           ref type_str, visible: _ } => {
+        for (object_group, group_attributes) in object_groups.iter().zip(attributes.into_iter()) {
+            for (index, (object, attributes)) in object_group.objects.iter()
+                                                             .zip(group_attributes.into_iter())
+                                                             .enumerate() {
+                let source = ObjectSource {
+                    group: object_group.name.clone(),
+                    index: index,
+                    name: attributes.name.clone(),
+                    id: attributes.id,
+                };
+
+                // Polylines are open, polygons are closed loops of walls
+                let (x, y, points, closed) = match object {
+                    &tiled::Object::Rect { x, y, width, height, .. } => {
                         objects.push(MapObject {
-                            x: *x as f32,
-                            y: *y as f32,
-                            width: *width as f32,
-                            height: *height as f32,
-                            type_str: type_str.clone(),
+                            x: x,
+                            y: y,
+                            width: width,
+                            height: height,
+                            shape: ObjectShape::Rect,
+                            type_str: attributes.type_str,
+                            properties: attributes.properties,
+                            source: source,
                         });
+                        continue;
                     }
-                    &tiled::Object::Polyline { ref x, ref y, ref points, visible: _ } => {
-                        for i in 0..points.len()-1 {
-                            let ax = x + points[i].0;
-                            let ay = y + points[i].1;
-                            let bx = x + points[i+1].0;
-                            let by = y + points[i+1].1;
-                            lines.push((Vec2::new(ax, ay), Vec2::new(bx, by)));
-                        }
+                    &tiled::Object::Ellipse { x, y, width, height, .. } => {
+                        objects.push(MapObject {
+                            x: x,
+                            y: y,
+                            width: width,
+                            height: height,
+                            shape: ObjectShape::Ellipse,
+                            type_str: attributes.type_str,
+                            properties: attributes.properties,
+                            source: source,
+                        });
+                        continue;
                     }
-                    _ =>
-                        return Err("Only rectangle objects can be used".to_string())
+                    &tiled::Object::Polyline { x, y, ref points, .. } => (x, y, points, false),
+                    &tiled::Object::Polygon { x, y, ref points, .. } => (x, y, points, true),
+                };
+
+                let min_points = if closed { 3 } else { 2 };
+                if points.len() < min_points {
+                    return Err(MapError::TooFewPoints {
+                        source: source,
+                        shape: if closed { "polygon" } else { "polyline" },
+                        x: x,
+                        y: y,
+                    });
+                }
+
+                let num_lines = if closed { points.len() } else { points.len() - 1 };
+                for i in 0..num_lines {
+                    let j = (i + 1) % points.len();
+                    lines.push(MapLine {
+                        a: Vec2::new(x + points[i].0, y + points[i].1),
+                        b: Vec2::new(x + points[j].0, y + points[j].1),
+                        properties: attributes.properties.clone(),
+                        source: source.clone(),
+                    });
                 }
             }
         }
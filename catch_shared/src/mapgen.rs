@@ -0,0 +1,392 @@
+//! Procedural generation of arena maps.
+//!
+//! The arena is divided into square cells. A randomized depth-first search opens passages
+//! between cells, which gives a spanning tree and thus guarantees that every cell can be
+//! reached. Some of the remaining walls are then removed to create loops. Spawns are placed
+//! in cells that are far apart from each other in walking distance.
+//!
+//! Clients generate the same map from the parameters in `GameInfo`, so everything here needs
+//! to be deterministic across platforms: we only use u32 random numbers and no hash maps.
+
+use std::collections::VecDeque;
+
+use na::Vec2;
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use map::{Map, MapObject, MapLine, ObjectShape, ObjectSource, Properties};
+
+// Size of one cell in tiles
+const CELL_TILES: usize = 4;
+const TILE_SIZE: usize = 32;
+
+// Probability of removing a wall that is not needed for connectivity
+const LOOP_PROBABILITY: f32 = 0.35;
+
+// Distance of player spawn areas to the walls of their cell
+const SPAWN_MARGIN: f32 = 16.0;
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct MapGenParams {
+    pub seed: u64,
+
+    // Size of the arena in cells
+    pub width_cells: u32,
+    pub height_cells: u32,
+
+    pub num_player_spawns: u32,
+    pub num_item_spawns: u32,
+    pub num_bouncy_enemies: u32,
+}
+
+impl MapGenParams {
+    pub fn new(seed: u64) -> MapGenParams {
+        MapGenParams {
+            seed: seed,
+            width_cells: 12,
+            height_cells: 10,
+            num_player_spawns: 4,
+            num_item_spawns: 6,
+            num_bouncy_enemies: 8,
+        }
+    }
+
+    /// Parses map names of the form `random:SEED` or `random:SEED:WIDTHxHEIGHT`, with the size
+    /// given in cells. Returns None for other names, which are map files.
+    pub fn parse(name: &str) -> Option<Result<MapGenParams, String>> {
+        if !name.starts_with("random:") {
+            return None;
+        }
+
+        let parts = name["random:".len()..].split(':').collect::<Vec<_>>();
+        let seed = match parts[0].parse() {
+            Ok(seed) => seed,
+            Err(_) => return Some(Err(format!("invalid map seed: {}", parts[0]))),
+        };
+        let mut params = MapGenParams::new(seed);
+
+        if parts.len() == 2 {
+            let size = parts[1].split('x').map(|n| n.parse::<u32>().ok()).collect::<Vec<_>>();
+            match (size.len(), size[0], size.get(1).cloned().unwrap_or(None)) {
+                (2, Some(w), Some(h)) if w >= 2 && h >= 2 => {
+                    params.width_cells = w;
+                    params.height_cells = h;
+                }
+                _ => return Some(Err(format!("invalid map size (WIDTHxHEIGHT): {}", parts[1]))),
+            }
+        } else if parts.len() > 2 {
+            return Some(Err(format!("invalid random map: {}", name)));
+        }
+
+        Some(Ok(params))
+    }
+}
+
+struct Cells {
+    width: usize,
+    height: usize,
+
+    // Is there a passage to the cell on the right/below?
+    open_right: Vec<bool>,
+    open_down: Vec<bool>,
+}
+
+impl Cells {
+    fn index(&self, x: usize, y: usize) -> usize {
+        y * self.width + x
+    }
+
+    fn neighbors(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+        let mut result = Vec::new();
+        if x > 0 { result.push((x - 1, y)); }
+        if y > 0 { result.push((x, y - 1)); }
+        if x + 1 < self.width { result.push((x + 1, y)); }
+        if y + 1 < self.height { result.push((x, y + 1)); }
+        result
+    }
+
+    fn is_open(&self, a: (usize, usize), b: (usize, usize)) -> bool {
+        let (min, max) = if (a.1, a.0) < (b.1, b.0) { (a, b) } else { (b, a) };
+        if min.1 == max.1 {
+            self.open_right[self.index(min.0, min.1)]
+        } else {
+            self.open_down[self.index(min.0, min.1)]
+        }
+    }
+
+    fn open(&mut self, a: (usize, usize), b: (usize, usize)) {
+        let (min, max) = if (a.1, a.0) < (b.1, b.0) { (a, b) } else { (b, a) };
+        let i = self.index(min.0, min.1);
+        if min.1 == max.1 {
+            self.open_right[i] = true;
+        } else {
+            self.open_down[i] = true;
+        }
+    }
+
+    /// Walking distance in cells from the given cell to every cell
+    fn distances(&self, from: (usize, usize)) -> Vec<usize> {
+        let mut distances = vec![usize::max_value(); self.width * self.height];
+        let mut queue = VecDeque::new();
+
+        distances[self.index(from.0, from.1)] = 0;
+        queue.push_back(from);
+
+        while let Some(cell) = queue.pop_front() {
+            let d = distances[self.index(cell.0, cell.1)];
+            for next in self.neighbors(cell.0, cell.1) {
+                let i = self.index(next.0, next.1);
+                if self.is_open(cell, next) && distances[i] == usize::max_value() {
+                    distances[i] = d + 1;
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        distances
+    }
+}
+
+fn gen_index<R: Rng>(rng: &mut R, n: usize) -> usize {
+    (rng.gen::<u32>() % n as u32) as usize
+}
+
+fn shuffle<R: Rng, T>(rng: &mut R, values: &mut [T]) {
+    for i in (1..values.len()).rev() {
+        let j = gen_index(rng, i + 1);
+        values.swap(i, j);
+    }
+}
+
+fn carve<R: Rng>(rng: &mut R, width: usize, height: usize) -> Cells {
+    let mut cells = Cells {
+        width: width,
+        height: height,
+        open_right: vec![false; width * height],
+        open_down: vec![false; width * height],
+    };
+
+    // Randomized depth-first search
+    let mut visited = vec![false; width * height];
+    let start = (gen_index(rng, width), gen_index(rng, height));
+    let mut stack = vec![start];
+    visited[cells.index(start.0, start.1)] = true;
+
+    while let Some(&cell) = stack.last() {
+        let unvisited = cells.neighbors(cell.0, cell.1).into_iter()
+                             .filter(|&(x, y)| !visited[cells.index(x, y)])
+                             .collect::<Vec<_>>();
+        if unvisited.is_empty() {
+            stack.pop();
+            continue;
+        }
+
+        let next = unvisited[gen_index(rng, unvisited.len())];
+        cells.open(cell, next);
+        visited[cells.index(next.0, next.1)] = true;
+        stack.push(next);
+    }
+
+    // Remove some more walls, so that there are multiple ways around
+    for y in 0..height {
+        for x in 0..width {
+            if x + 1 < width && !cells.is_open((x, y), (x + 1, y)) &&
+               rng.gen::<f32>() < LOOP_PROBABILITY {
+                cells.open((x, y), (x + 1, y));
+            }
+            if y + 1 < height && !cells.is_open((x, y), (x, y + 1)) &&
+               rng.gen::<f32>() < LOOP_PROBABILITY {
+                cells.open((x, y), (x, y + 1));
+            }
+        }
+    }
+
+    cells
+}
+
+fn wall(a: Vec2<f32>, b: Vec2<f32>, wall_type: &str, index: usize) -> MapLine {
+    let mut properties = Properties::new();
+    properties.insert("wall_type".to_string(), wall_type.to_string());
+
+    MapLine {
+        a: a,
+        b: b,
+        properties: properties,
+        source: ObjectSource {
+            group: "generated walls".to_string(),
+            index: index,
+            name: String::new(),
+            id: None,
+        },
+    }
+}
+
+/// Creates wall lines for the closed sides of the cells. Walls that continue in a straight line
+/// are merged into one.
+fn walls(cells: &Cells) -> Vec<MapLine> {
+    let c = (CELL_TILES * TILE_SIZE) as f32;
+    let w = cells.width as f32 * c;
+    let h = cells.height as f32 * c;
+
+    let mut lines = Vec::new();
+
+    // Vertical walls between columns x and x + 1
+    for x in 0..cells.width-1 {
+        let mut start = None;
+        for y in 0..cells.height+1 {
+            let closed = y < cells.height && !cells.is_open((x, y), (x + 1, y));
+            match (start, closed) {
+                (None, true) => start = Some(y),
+                (Some(y1), false) => {
+                    let px = (x + 1) as f32 * c;
+                    let index = lines.len();
+                    lines.push(wall(Vec2::new(px, y1 as f32 * c), Vec2::new(px, y as f32 * c),
+                                    "wood", index));
+                    start = None;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // Horizontal walls between rows y and y + 1
+    for y in 0..cells.height-1 {
+        let mut start = None;
+        for x in 0..cells.width+1 {
+            let closed = x < cells.width && !cells.is_open((x, y), (x, y + 1));
+            match (start, closed) {
+                (None, true) => start = Some(x),
+                (Some(x1), false) => {
+                    let py = (y + 1) as f32 * c;
+                    let index = lines.len();
+                    lines.push(wall(Vec2::new(x1 as f32 * c, py), Vec2::new(x as f32 * c, py),
+                                    "wood", index));
+                    start = None;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    let corners = [Vec2::new(0.0, 0.0), Vec2::new(w, 0.0), Vec2::new(w, h), Vec2::new(0.0, h)];
+    for i in 0..4 {
+        let index = lines.len();
+        lines.push(wall(corners[i], corners[(i + 1) % 4], "iron", index));
+    }
+
+    lines
+}
+
+/// Picks `n` cells from `candidates` such that the walking distance between any two picked
+/// cells, including those in `picked`, is as large as we can manage. The required distance is
+/// lowered until enough cells are found.
+fn pick_spaced(cells: &Cells, candidates: &[(usize, usize)], n: usize,
+               picked: &mut Vec<(usize, usize)>, distances: &mut Vec<Vec<usize>>)
+               -> Vec<(usize, usize)> {
+    let mut result = Vec::new();
+    let mut spacing = cells.width.max(cells.height) / 2 + 1;
+
+    while result.len() < n {
+        for &cell in candidates.iter() {
+            if result.len() == n {
+                break;
+            }
+            if picked.contains(&cell) {
+                continue;
+            }
+
+            let i = cells.index(cell.0, cell.1);
+            if distances.iter().all(|d| d[i] >= spacing) {
+                result.push(cell);
+                picked.push(cell);
+                distances.push(cells.distances(cell));
+            }
+        }
+
+        if spacing == 0 {
+            // There are not enough cells at all
+            break;
+        }
+        spacing -= 1;
+    }
+
+    result
+}
+
+fn object(type_str: &str, shape: ObjectShape, p: Vec2<f32>, size: Vec2<f32>, index: usize)
+          -> MapObject {
+    MapObject {
+        x: p.x,
+        y: p.y,
+        width: size.x,
+        height: size.y,
+        shape: shape,
+        type_str: type_str.to_string(),
+        properties: Properties::new(),
+        source: ObjectSource {
+            group: "generated objects".to_string(),
+            index: index,
+            name: String::new(),
+            id: None,
+        },
+    }
+}
+
+pub fn generate(params: &MapGenParams) -> Map {
+    info!("generating map with {:?}", params);
+
+    let mut rng = XorShiftRng::from_seed([0x2545f491, params.seed as u32,
+                                          (params.seed >> 32) as u32, 0x9e3779b9]);
+
+    let width = params.width_cells as usize;
+    let height = params.height_cells as usize;
+    let cells = carve(&mut rng, width, height);
+    let lines = walls(&cells);
+
+    let mut candidates = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            candidates.push((x, y));
+        }
+    }
+    shuffle(&mut rng, &mut candidates);
+
+    // Player and item spawns are kept apart from each other
+    let mut picked = Vec::new();
+    let mut distances = Vec::new();
+    let player_cells = pick_spaced(&cells, &candidates, params.num_player_spawns as usize,
+                                   &mut picked, &mut distances);
+    let item_cells = pick_spaced(&cells, &candidates, params.num_item_spawns as usize,
+                                 &mut picked, &mut distances);
+
+    // Enemies can be anywhere, except where players spawn
+    shuffle(&mut rng, &mut candidates);
+    let enemy_cells = candidates.iter()
+                                .filter(|&cell| !player_cells.contains(cell))
+                                .take(params.num_bouncy_enemies as usize)
+                                .cloned()
+                                .collect::<Vec<_>>();
+
+    let c = (CELL_TILES * TILE_SIZE) as f32;
+    let corner = |cell: (usize, usize)| Vec2::new(cell.0 as f32 * c, cell.1 as f32 * c);
+    let center = |cell: (usize, usize)| corner(cell) + Vec2::new(c / 2.0, c / 2.0);
+
+    let mut objects = Vec::new();
+    for &cell in player_cells.iter() {
+        let index = objects.len();
+        objects.push(object("player_spawn", ObjectShape::Ellipse,
+                            corner(cell) + Vec2::new(SPAWN_MARGIN, SPAWN_MARGIN),
+                            Vec2::new(c - 2.0 * SPAWN_MARGIN, c - 2.0 * SPAWN_MARGIN), index));
+    }
+    for &cell in item_cells.iter() {
+        let index = objects.len();
+        objects.push(object("item_spawn", ObjectShape::Rect, center(cell), Vec2::new(0.0, 0.0),
+                            index));
+    }
+    for &cell in enemy_cells.iter() {
+        let index = objects.len();
+        objects.push(object("bouncy_enemy", ObjectShape::Rect, center(cell),
+                            Vec2::new(0.0, 0.0), index));
+    }
+
+    Map::new(width * CELL_TILES, height * CELL_TILES, TILE_SIZE, TILE_SIZE, objects, lines)
+}
@@ -4,10 +4,11 @@ use ecs::{EntityData, DataHelper, ComponentManager, ServiceManager};
 use na::{Vec2, Norm, Dot};
 
 use super::{math, GameEvent, PlayerId};
-use util::CachedAspect;
+use grid::WallGrid;
 use net::TimedPlayerInput;
 use player::PlayerInputKey;
 use services::HasEvents;
+use tuning::Tuning;
 use components::{Shape, HasPosition, HasLinearVelocity, HasOrientation, HasAngularVelocity,
                  HasPlayerState, HasFullPlayerState, HasShape, HasWallPosition, WallPosition};
 
@@ -64,7 +65,7 @@ pub fn move_entity<Components: ComponentManager,
                   (e: EntityData<Components>,
                    delta: Vec2<f32>,
                    interaction: &WallInteraction<Components, Services>,
-                   wall_aspect: &CachedAspect<Components>,
+                   walls: &WallGrid<Components>,
                    c: &mut DataHelper<Components, Services>)
         where Components: HasPosition + HasLinearVelocity + HasShape +
                           HasOrientation + HasWallPosition {
@@ -78,7 +79,7 @@ pub fn move_entity<Components: ComponentManager,
 
     //println!("moving a {:?} by {:?}", shape, delta);
 
-    let intersection = moving_shape_walls_intersection_time(a, delta, &shape, wall_aspect, c);
+    let intersection = moving_shape_walls_intersection_time(a, delta, &shape, walls, c);
     c.position_mut()[e].p = match intersection {
         Some((t, _, wall)) if t <= 1.0 => {
             // We hit a wall, ask `interaction` what to do
@@ -92,7 +93,7 @@ pub fn move_entity<Components: ComponentManager,
 
                     // Move into parallel and orthogonal directions individually
                     let intersection = moving_shape_walls_intersection_time(a, u, &shape,
-                                                                            wall_aspect, c);
+                                                                            walls, c);
                     let new_a = match intersection {
                         Some((t, _, wall)) if t <= 1.0 => {
                             let xx = a + u * stepback(t, r);
@@ -110,7 +111,7 @@ pub fn move_entity<Components: ComponentManager,
                     };
 
                     let intersection = moving_shape_walls_intersection_time(new_a, v, &shape,
-                                                                            wall_aspect, c);
+                                                                            walls, c);
                     let new_a = match intersection {
                         Some((t, _, wall)) if t <= 1.0 => {
                             let xx = new_a + v * stepback(t, r);
@@ -228,23 +229,14 @@ pub fn run_player_movement_input<Components: ComponentManager,
                                 (e: EntityData<Components>,
                                  owner: PlayerId,
                                  timed_input: &TimedPlayerInput,
-                                 wall_aspect: &CachedAspect<Components>,
+                                 tuning: &Tuning,
+                                 walls: &WallGrid<Components>,
                                  c: &mut DataHelper<Components, Services>) 
     where Components: HasPosition + HasLinearVelocity + 
                       HasOrientation + HasAngularVelocity + 
                       HasPlayerState + HasFullPlayerState +
                       HasShape + HasWallPosition,
           Services: HasEvents {
-    const TURN_ACCEL: f32 = 1.25;
-    const TURN_FRICTION: f32 = 0.25;
-    const MOVE_ACCEL: f32 = 1000.0;
-    const MOVE_FRICTION: f32 = 10.0;
-    const BACK_ACCEL: f32 = 500.0;
-    const STRAFE_ACCEL: f32 = 900.0;
-    const MIN_SPEED: f32 = 5.0;
-    const DASH_SPEED: f32 = 600.0;
-    const DASH_DURATION_S: f32 = 0.3;
-
     let dur_s = timed_input.duration_s;
     let input = &timed_input.input;
 
@@ -267,7 +259,7 @@ pub fn run_player_movement_input<Components: ComponentManager,
     // Before changing velocities, move
     let interaction = PlayerWallInteraction(owner);
     let delta = c.linear_velocity()[e].v * dur_s;
-    move_entity(e, delta, &interaction, wall_aspect, c);
+    move_entity(e, delta, &interaction, walls, c);
 
     let angle = c.orientation()[e].angle;
     let direction = Vec2::new(angle.cos(), angle.sin());
@@ -275,12 +267,12 @@ pub fn run_player_movement_input<Components: ComponentManager,
     if let Some(dashing) = c.player_state()[e].dashing {
         // While dashing, movement input is ignored
 
-        //let t = dashing / DASH_DURATION_S;
+        //let t = dashing / tuning.dash_duration_s;
         let scale = 1.0; //(t*f32::consts::PI/2.0).cos()*(1.0-(1.0-t).powi(10));
-        c.linear_velocity_mut()[e].v = direction * DASH_SPEED * scale;
+        c.linear_velocity_mut()[e].v = direction * tuning.dash_speed * scale;
 
         c.player_state_mut()[e].dashing =
-            if dashing + dur_s <= DASH_DURATION_S {
+            if dashing + dur_s <= tuning.dash_duration_s {
                 Some(dashing + dur_s)
             } else {
                 None
@@ -288,25 +280,25 @@ pub fn run_player_movement_input<Components: ComponentManager,
     } else {
         c.orientation_mut()[e].angle += c.angular_velocity()[e].v;
 
-        let mut accel = c.linear_velocity_mut()[e].v * -MOVE_FRICTION;
+        let mut accel = c.linear_velocity_mut()[e].v * -tuning.move_friction;
 
         if input.has(PlayerInputKey::StrafeLeft) {
             c.angular_velocity_mut()[e].v = 0.0;
             let strafe_direction = Vec2::new(direction[1], -direction[0]);
-            accel = -strafe_direction * STRAFE_ACCEL + accel;
+            accel = -strafe_direction * tuning.strafe_accel + accel;
         } else if input.has(PlayerInputKey::StrafeRight) {
             c.angular_velocity_mut()[e].v = 0.0;
             let strafe_direction = Vec2::new(direction[1], -direction[0]);
-            accel = strafe_direction * STRAFE_ACCEL + accel;
+            accel = strafe_direction * tuning.strafe_accel + accel;
         } else {
             // Turn left/right
-            let mut ang_accel = c.angular_velocity()[e].v * -TURN_FRICTION;
+            let mut ang_accel = c.angular_velocity()[e].v * -tuning.turn_friction;
 
             if input.has(PlayerInputKey::Left) {
-                ang_accel += TURN_ACCEL * dur_s;
+                ang_accel += tuning.turn_accel * dur_s;
             }
             if input.has(PlayerInputKey::Right) {
-                ang_accel -= TURN_ACCEL * dur_s;
+                ang_accel -= tuning.turn_accel * dur_s;
             }
 
             c.angular_velocity_mut()[e].v += ang_accel;
@@ -314,16 +306,16 @@ pub fn run_player_movement_input<Components: ComponentManager,
 
         // Move forward/backward
         if input.has(PlayerInputKey::Forward) {
-            accel = direction * MOVE_ACCEL + accel;
+            accel = direction * tuning.move_accel + accel;
         }
         if input.has(PlayerInputKey::Back) {
-            accel = -direction * BACK_ACCEL + accel;
+            accel = -direction * tuning.back_accel + accel;
         }
 
         c.linear_velocity_mut()[e].v = c.linear_velocity()[e].v + accel * dur_s;
 
         // If velocity is below some limit, set to zero
-        if c.linear_velocity()[e].v.norm() <= MIN_SPEED {
+        if c.linear_velocity()[e].v.norm() <= tuning.min_speed {
             c.linear_velocity_mut()[e].v = Vec2::new(0.0, 0.0);
         }
 
@@ -331,7 +323,7 @@ pub fn run_player_movement_input<Components: ComponentManager,
         if input.has(PlayerInputKey::Dash) && 
            c.full_player_state()[e].dash_cooldown_s.is_none() {
             c.player_state_mut()[e].dashing = Some(0.0);
-            c.full_player_state_mut()[e].dash_cooldown_s = Some(5.0);
+            c.full_player_state_mut()[e].dash_cooldown_s = Some(tuning.dash_cooldown_s);
             c.angular_velocity_mut()[e].v = 0.0;
 
             let event = GameEvent::PlayerDash {
@@ -348,7 +340,7 @@ pub fn moving_shape_walls_intersection_time<'a,
                                             Components: ComponentManager,
                                             Services: ServiceManager>
                                            (a: Vec2<f32>, delta: Vec2<f32>, shape: &Shape,
-                                            wall_aspect: &'a CachedAspect<Components>,
+                                            walls: &'a WallGrid<Components>,
                                             data: &DataHelper<Components, Services>)
                                            -> Option<(f32, f32, EntityData<'a, Components>)>
     where Components: HasWallPosition {
@@ -356,7 +348,7 @@ pub fn moving_shape_walls_intersection_time<'a,
         Shape::Circle { radius } => {
             let mut closest_i = None;
 
-            for wall in wall_aspect.iter::<'a>() {
+            for wall in walls.near_movement(a, delta, radius) {
                 let p = data.wall_position()[wall].clone();
                 let i = math::line_segment_moving_circle_intersection_time(p.pos_a, p.pos_b,
                                                                            a, delta, radius);
@@ -374,7 +366,7 @@ pub fn moving_shape_walls_intersection_time<'a,
         }
         _ => {
             // TODO
-            ray_walls_intersection(a, delta, wall_aspect, data)
+            ray_walls_intersection(a, delta, walls, data)
         }
     }
 }
@@ -383,13 +375,13 @@ pub fn ray_walls_intersection<'a,
                               Components: ComponentManager,
                               Services: ServiceManager>
                              (a: Vec2<f32>, delta: Vec2<f32>,
-                              wall_aspect: &'a CachedAspect<Components>,
+                              walls: &'a WallGrid<Components>,
                               data: &DataHelper<Components, Services>)
                               -> Option<(f32, f32, EntityData<'a, Components>)>
     where Components: HasWallPosition {
     let mut closest_i = None;
 
-    for wall in wall_aspect.iter::<'a>() {
+    for wall in walls.near_movement(a, delta, 0.0) {
         let p = data.wall_position()[wall].clone();
         let i = math::ray_line_segment_intersection(a, delta, p.pos_a, p.pos_b);
 
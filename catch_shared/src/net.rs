@@ -7,6 +7,9 @@ pub enum Channel {
 } 
 pub const NUM_CHANNELS: usize = 2;
 
+// Longer chat messages are cut off by the server
+pub const MAX_CHAT_LEN: usize = 160;
+
 #[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 pub struct TimedPlayerInput {
     pub duration_s: f32,
@@ -18,8 +21,15 @@ pub enum ClientMessage {
     Pong,
     WishConnect {
         name: String,
+        color: [f32; 3],
     },
     PlayerInput(TimedPlayerInput),
+    Chat {
+        text: String,
+
+        // Only send to players with the same color
+        team_only: bool,
+    },
     StartingTick {
         tick: TickNumber,
     }
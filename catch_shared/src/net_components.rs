@@ -78,6 +78,21 @@ macro_rules! net_components {
                 Ok(())
             }
 
+            /// Encodes only the given component, which needs to be present. The bytes are the
+            /// same as in `encode` and `delta_encode`, so they can be cached.
+            pub fn encode_component<S: Encoder>
+                                   (&self,
+                                    component: $EnumName,
+                                    s: &mut S)
+                                   -> Result<(), S::Error> {
+                match component {
+                    $(
+                        $EnumName::$field_ty =>
+                            self.$field_name.as_ref().unwrap().encode(s),
+                    )+
+                }
+            }
+
             #[allow(unused_assignments)] 
             pub fn decode<D: Decoder>
                          (d: &mut D)
@@ -187,6 +202,7 @@ net_components! {
         player_state, player_state_mut: PlayerState, HasPlayerState,
         full_player_state, full_player_state_mut: FullPlayerState, HasFullPlayerState,
         wall_position, wall_position_mut: WallPosition, HasWallPosition,
+        item_spawn_state, item_spawn_state_mut: ItemSpawnState, HasItemSpawnState,
     }
     enum ComponentType;
     const COMPONENT_TYPES;
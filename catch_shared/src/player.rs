@@ -22,15 +22,33 @@ pub enum Item {
     Shield
 }
 
+/// Names of the items as used in maps and the balance table
+pub const ITEM_NAMES: &'static [&'static str] =
+    &["weapon", "frag_weapon", "speed_boost", "block_placer", "ball_spawner", "shield"];
+
 impl Item {
-    pub fn cooldown_s(&self) -> Option<f32> {
+    /// Creates an item from its name. Depending on the item, either `charges` or `duration_s`
+    /// is used.
+    pub fn new(name: &str, charges: usize, duration_s: f32) -> Option<Item> {
+        match name {
+            "weapon" => Some(Item::Weapon { charges: charges }),
+            "frag_weapon" => Some(Item::FragWeapon { charges: charges }),
+            "speed_boost" => Some(Item::SpeedBoost { duration_s: duration_s }),
+            "block_placer" => Some(Item::BlockPlacer { charges: charges }),
+            "ball_spawner" => Some(Item::BallSpawner { charges: charges }),
+            "shield" => Some(Item::Shield),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
         match *self {
-            Item::Weapon { charges: _ } => Some(0.5),
-            Item::FragWeapon { charges: _ } => Some(1.5),
-            Item::SpeedBoost { duration_s: _ } => None,
-            Item::BlockPlacer { charges: _ } => Some(5.0),
-            Item::BallSpawner { charges: _ } => Some(2.5),
-            Item::Shield => None,
+            Item::Weapon { .. } => "weapon",
+            Item::FragWeapon { .. } => "frag_weapon",
+            Item::SpeedBoost { .. } => "speed_boost",
+            Item::BlockPlacer { .. } => "block_placer",
+            Item::BallSpawner { .. } => "ball_spawner",
+            Item::Shield => "shield",
         }
     }
 }
@@ -167,9 +185,35 @@ impl PlayerInput {
     }
 }
 
+/// Colors that players can choose from. The server only accepts these.
+pub const PLAYER_COLORS: &'static [(&'static str, [f32; 3])] = &[
+    ("blue", [0.0, 0.0, 1.0]),
+    ("red", [1.0, 0.2, 0.2]),
+    ("purple", [0.6, 0.2, 1.0]),
+    ("cyan", [0.0, 0.8, 0.8]),
+    ("pink", [1.0, 0.4, 0.7]),
+    ("white", [0.9, 0.9, 0.9]),
+];
+
+/// Returns the palette color closest to the given one
+pub fn nearest_player_color(color: [f32; 3]) -> [f32; 3] {
+    let distance = |other: &[f32; 3]| {
+        (0..3).map(|i| (color[i] - other[i]) * (color[i] - other[i])).fold(0.0, |a, b| a + b)
+    };
+
+    let mut best = PLAYER_COLORS[0].1;
+    for &(_, other) in PLAYER_COLORS.iter() {
+        if distance(&other) < distance(&best) {
+            best = other;
+        }
+    }
+    best
+}
+
 #[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 pub struct PlayerInfo {
     pub name: String,
+    pub color: [f32; 3],
     pub stats: PlayerStats,
 }
 
@@ -181,9 +225,10 @@ pub struct PlayerStats {
 }
 
 impl PlayerInfo {
-    pub fn new(name: String) -> PlayerInfo {
+    pub fn new(name: String, color: [f32; 3]) -> PlayerInfo {
         PlayerInfo {
             name: name,
+            color: color,
             stats: PlayerStats::default(),
         }
     }
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use enet;
+
+use super::{Transport, TransportEvent, PeerId, Reliability};
+
+/// Transport over UDP using enet
+pub struct EnetTransport {
+    host: enet::Host,
+    peers: HashMap<PeerId, enet::Peer>,
+    peer_id_counter: PeerId,
+}
+
+impl EnetTransport {
+    /// Listens for connections on the given port
+    pub fn server(port: u16, peer_count: u32, num_channels: usize)
+                  -> Result<EnetTransport, String> {
+        let host = try!(enet::Host::new_server(port, peer_count, num_channels as u32, 0, 0));
+
+        Ok(EnetTransport {
+            host: host,
+            peers: HashMap::new(),
+            peer_id_counter: 0,
+        })
+    }
+
+    /// Connects to a server, returning the transport together with the id of the server
+    pub fn connect(timeout_ms: u32, host_name: String, port: u16, num_channels: usize)
+                   -> Result<(EnetTransport, PeerId), String> {
+        let (host, peer) = try!(enet::Host::connect(timeout_ms, host_name, port,
+                                                    num_channels as u32, 0, 0));
+
+        let mut transport = EnetTransport {
+            host: host,
+            peers: HashMap::new(),
+            peer_id_counter: 0,
+        };
+        let peer_id = transport.add_peer(peer);
+
+        Ok((transport, peer_id))
+    }
+
+    fn add_peer(&mut self, peer: enet::Peer) -> PeerId {
+        // Start at 1, so that peers without user data can be told apart
+        self.peer_id_counter += 1;
+        let peer_id = self.peer_id_counter;
+
+        peer.set_user_data(peer_id as *mut _);
+        self.peers.insert(peer_id, peer);
+        peer_id
+    }
+}
+
+impl Transport for EnetTransport {
+    fn service(&mut self, timeout_ms: u32) -> Result<Option<TransportEvent>, String> {
+        match try!(self.host.service(timeout_ms)) {
+            enet::Event::Connect(peer) => {
+                let peer_id = self.add_peer(peer);
+                Ok(Some(TransportEvent::Connect(peer_id)))
+            }
+            enet::Event::Disconnect(peer) => {
+                let peer_id = peer.get_user_data() as PeerId;
+                self.peers.remove(&peer_id);
+                Ok(Some(TransportEvent::Disconnect(peer_id)))
+            }
+            enet::Event::Receive(peer, channel_id, packet) => {
+                let peer_id = peer.get_user_data() as PeerId;
+                Ok(Some(TransportEvent::Receive(peer_id, channel_id, packet.data().to_vec())))
+            }
+            enet::Event::None => Ok(None),
+        }
+    }
+
+    fn send(&mut self, peer: PeerId, channel: u8, reliability: Reliability, data: &[u8]) {
+        let flags = match reliability {
+            Reliability::Reliable => enet::ffi::ENET_PACKET_FLAG_RELIABLE,
+            Reliability::Unreliable => enet::ffi::ENET_PACKET_FLAG_UNSEQUENCED,
+        };
+
+        match self.peers.get(&peer) {
+            Some(enet_peer) => enet_peer.send(data, flags, channel),
+            None => warn!("trying to send to unknown peer {}", peer),
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::thread;
+use std::collections::{HashMap, VecDeque};
+
+use time;
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use super::{Transport, TransportEvent, PeerId, Reliability};
+
+/// How badly a `LossyTransport` treats its packets
+#[derive(Clone, Debug)]
+pub struct LossSettings {
+    // Every packet is delayed by the latency plus a random amount of up to the jitter, both
+    // when sending and when receiving
+    pub latency_ms: u32,
+    pub jitter_ms: u32,
+
+    // Probability of dropping an unreliable packet
+    pub loss: f32,
+
+    // Probability of delaying an unreliable packet by another latency, so that packets sent
+    // after it overtake it
+    pub reorder: f32,
+
+    pub seed: u64,
+}
+
+impl LossSettings {
+    pub fn new() -> LossSettings {
+        LossSettings {
+            latency_ms: 0,
+            jitter_ms: 0,
+            loss: 0.0,
+            reorder: 0.0,
+            seed: 0,
+        }
+    }
+
+    /// Parses settings of the form `latency=100,jitter=20,loss=0.05,reorder=0.01,seed=1`.
+    /// Settings that are not given are zero.
+    pub fn parse(s: &str) -> Result<LossSettings, String> {
+        let mut settings = LossSettings::new();
+
+        for setting in s.split(',').filter(|setting| !setting.is_empty()) {
+            let parts = setting.splitn(2, '=').collect::<Vec<_>>();
+            if parts.len() != 2 {
+                return Err(format!("expected NAME=VALUE, got {}", setting));
+            }
+            let invalid = || format!("invalid value for {}: {}", parts[0], parts[1]);
+
+            match parts[0] {
+                "latency" => settings.latency_ms = try!(parts[1].parse().map_err(|_| invalid())),
+                "jitter" => settings.jitter_ms = try!(parts[1].parse().map_err(|_| invalid())),
+                "loss" => settings.loss = try!(parts[1].parse().map_err(|_| invalid())),
+                "reorder" => settings.reorder = try!(parts[1].parse().map_err(|_| invalid())),
+                "seed" => settings.seed = try!(parts[1].parse().map_err(|_| invalid())),
+                _ => return Err(format!("unknown network setting: {}", parts[0])),
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+struct DelayedPacket {
+    send_time_ms: u64,
+    peer: PeerId,
+    channel: u8,
+    reliability: Reliability,
+    data: Vec<u8>,
+}
+
+/// Wraps another transport, simulating a bad network. Packets are delayed in both directions.
+/// Unreliable packets that we send are also dropped and reordered, while reliable ones are
+/// never lost or reordered. Since the game sends all of its messages and ticks reliably, loss
+/// and reordering only show up in traffic that opts into being unreliable.
+///
+/// Received events keep their order, as we can't tell which packets were sent reliably.
+///
+/// With a manual clock, time only passes by calling `advance`, which together with the seed
+/// makes the behaviour deterministic.
+pub struct LossyTransport<T: Transport> {
+    inner: T,
+    settings: LossSettings,
+    rng: XorShiftRng,
+
+    // Sorted by send time
+    queue: Vec<DelayedPacket>,
+
+    // Send time of the last reliable packet for each peer and channel
+    last_reliable_ms: HashMap<(PeerId, u8), u64>,
+
+    // Received events with the time at which they are handed out, which is never earlier than
+    // for the event before
+    incoming: VecDeque<(u64, TransportEvent)>,
+
+    start_ns: u64,
+    manual_time_ms: Option<u64>,
+}
+
+impl<T: Transport> LossyTransport<T> {
+    pub fn new(inner: T, settings: LossSettings) -> LossyTransport<T> {
+        let seed = settings.seed;
+
+        LossyTransport {
+            inner: inner,
+            settings: settings,
+            rng: XorShiftRng::from_seed([0x193a6754, seed as u32, (seed >> 32) as u32,
+                                         0xa8a2d6d5]),
+            queue: Vec::new(),
+            last_reliable_ms: HashMap::new(),
+            incoming: VecDeque::new(),
+            start_ns: time::precise_time_ns(),
+            manual_time_ms: None,
+        }
+    }
+
+    pub fn with_manual_clock(inner: T, settings: LossSettings) -> LossyTransport<T> {
+        let mut transport = LossyTransport::new(inner, settings);
+        transport.manual_time_ms = Some(0);
+        transport
+    }
+
+    /// Lets time pass on a manual clock and sends the packets that are due. Received events
+    /// that are due are handed out by `service`.
+    pub fn advance(&mut self, ms: u64) {
+        let time_ms = self.manual_time_ms.expect("advance needs a manual clock");
+        self.manual_time_ms = Some(time_ms + ms);
+        self.send_due();
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Number of packets that have not been passed on yet
+    pub fn num_queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn time_ms(&self) -> u64 {
+        match self.manual_time_ms {
+            Some(time_ms) => time_ms,
+            None => (time::precise_time_ns() - self.start_ns) / 1000000,
+        }
+    }
+
+    /// Latency plus a random amount of jitter
+    fn delay_ms(&mut self) -> u64 {
+        let mut delay_ms = self.settings.latency_ms as u64;
+        if self.settings.jitter_ms > 0 {
+            delay_ms += (self.rng.next_u32() % (self.settings.jitter_ms + 1)) as u64;
+        }
+        delay_ms
+    }
+
+    /// Takes the events that have arrived at the inner transport and delays them
+    fn receive_all(&mut self) -> Result<(), String> {
+        while let Some(event) = try!(self.inner.service(0)) {
+            let mut receive_time_ms = self.time_ms() + self.delay_ms();
+            if let Some(&(last_ms, _)) = self.incoming.back() {
+                receive_time_ms = receive_time_ms.max(last_ms);
+            }
+            self.incoming.push_back((receive_time_ms, event));
+        }
+        Ok(())
+    }
+
+    fn send_due(&mut self) {
+        let time_ms = self.time_ms();
+        let num_due = self.queue.iter()
+                                .take_while(|packet| packet.send_time_ms <= time_ms)
+                                .count();
+
+        for packet in self.queue.drain(0..num_due) {
+            self.inner.send(packet.peer, packet.channel, packet.reliability, &packet.data);
+        }
+    }
+}
+
+impl<T: Transport> Transport for LossyTransport<T> {
+    fn service(&mut self, timeout_ms: u32) -> Result<Option<TransportEvent>, String> {
+        let deadline_ns = time::precise_time_ns() + timeout_ms as u64 * 1000000;
+
+        loop {
+            self.send_due();
+            try!(self.receive_all());
+
+            let time_ms = self.time_ms();
+            if self.incoming.front().map_or(false, |&(receive_ms, _)| receive_ms <= time_ms) {
+                return Ok(self.incoming.pop_front().map(|(_, event)| event));
+            }
+
+            // Waiting makes no sense when time only passes by calling `advance`
+            if self.manual_time_ms.is_some() || time::precise_time_ns() >= deadline_ns {
+                return Ok(None);
+            }
+            thread::sleep_ms(1);
+        }
+    }
+
+    fn send(&mut self, peer: PeerId, channel: u8, reliability: Reliability, data: &[u8]) {
+        let mut send_time_ms = self.time_ms() + self.delay_ms();
+
+        match reliability {
+            Reliability::Reliable => {
+                // Reliable packets on the same channel must not overtake each other
+                let last_ms = self.last_reliable_ms.entry((peer, channel)).or_insert(0);
+                send_time_ms = send_time_ms.max(*last_ms);
+                *last_ms = send_time_ms;
+            }
+            Reliability::Unreliable => {
+                if self.rng.next_f32() < self.settings.loss {
+                    return;
+                }
+                if self.rng.next_f32() < self.settings.reorder {
+                    send_time_ms += self.settings.latency_ms as u64;
+                }
+            }
+        }
+
+        // Keep the queue sorted, with packets that have the same send time in sending order
+        let index = self.queue.iter()
+                              .position(|packet| packet.send_time_ms > send_time_ms)
+                              .unwrap_or(self.queue.len());
+        self.queue.insert(index, DelayedPacket {
+            send_time_ms: send_time_ms,
+            peer: peer,
+            channel: channel,
+            reliability: reliability,
+            data: data.to_vec(),
+        });
+
+        self.send_due();
+    }
+}
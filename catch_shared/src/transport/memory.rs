@@ -0,0 +1,132 @@
+use std::thread;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
+
+use time;
+
+use super::{Transport, TransportEvent, PeerId, Reliability};
+
+// Every memory transport gets a unique id, which its peers use as its PeerId
+static NEXT_TRANSPORT_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+enum Packet {
+    Connect(Sender<(PeerId, Packet)>),
+    Disconnect,
+    Data(u8, Vec<u8>),
+}
+
+/// What is needed to connect to a `MemoryTransport`. Unlike the transport itself, this can be
+/// sent to other threads.
+#[derive(Clone)]
+pub struct MemoryAddress {
+    id: PeerId,
+    sender: Sender<(PeerId, Packet)>,
+}
+
+/// Transport over channels between threads of the same process. Packets are never lost and
+/// always arrive in order.
+pub struct MemoryTransport {
+    id: PeerId,
+
+    // Packets from all peers arrive here, tagged with the id of the sending transport
+    sender: Sender<(PeerId, Packet)>,
+    receiver: Receiver<(PeerId, Packet)>,
+
+    peers: HashMap<PeerId, Sender<(PeerId, Packet)>>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> MemoryTransport {
+        let (sender, receiver) = channel();
+
+        MemoryTransport {
+            id: NEXT_TRANSPORT_ID.fetch_add(1, Ordering::SeqCst) as PeerId,
+            sender: sender,
+            receiver: receiver,
+            peers: HashMap::new(),
+        }
+    }
+
+    pub fn address(&self) -> MemoryAddress {
+        MemoryAddress {
+            id: self.id,
+            sender: self.sender.clone(),
+        }
+    }
+
+    /// Connects to the transport with the given address, which gets a `Connect` event the next
+    /// time it is serviced. Returns the id under which we know the other transport.
+    pub fn connect(&mut self, address: &MemoryAddress) -> Result<PeerId, String> {
+        if address.sender.send((self.id, Packet::Connect(self.sender.clone()))).is_err() {
+            return Err("the transport to connect to is gone".to_string());
+        }
+
+        self.peers.insert(address.id, address.sender.clone());
+        Ok(address.id)
+    }
+
+    fn try_receive(&mut self) -> Result<Option<TransportEvent>, String> {
+        loop {
+            let (peer, packet) = match self.receiver.try_recv() {
+                Ok(received) => received,
+                Err(TryRecvError::Empty) => return Ok(None),
+
+                // We hold a sender ourselves, so this can't happen
+                Err(TryRecvError::Disconnected) => unreachable!(),
+            };
+
+            match packet {
+                Packet::Connect(sender) => {
+                    self.peers.insert(peer, sender);
+                    return Ok(Some(TransportEvent::Connect(peer)));
+                }
+                Packet::Disconnect => {
+                    if self.peers.remove(&peer).is_some() {
+                        return Ok(Some(TransportEvent::Disconnect(peer)));
+                    }
+                }
+                Packet::Data(channel, data) => {
+                    if self.peers.contains_key(&peer) {
+                        return Ok(Some(TransportEvent::Receive(peer, channel, data)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn service(&mut self, timeout_ms: u32) -> Result<Option<TransportEvent>, String> {
+        let deadline_ns = time::precise_time_ns() + timeout_ms as u64 * 1000000;
+
+        loop {
+            if let Some(event) = try!(self.try_receive()) {
+                return Ok(Some(event));
+            }
+            if time::precise_time_ns() >= deadline_ns {
+                return Ok(None);
+            }
+            thread::sleep_ms(1);
+        }
+    }
+
+    fn send(&mut self, peer: PeerId, channel: u8, _reliability: Reliability, data: &[u8]) {
+        match self.peers.get(&peer) {
+            Some(sender) => {
+                // If the peer is gone, its Disconnect packet is waiting for us already
+                let _ = sender.send((self.id, Packet::Data(channel, data.to_vec())));
+            }
+            None => warn!("trying to send to unknown peer {}", peer),
+        }
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        for (_, sender) in self.peers.iter() {
+            // Peers that are gone already don't need to know
+            let _ = sender.send((self.id, Packet::Disconnect));
+        }
+    }
+}
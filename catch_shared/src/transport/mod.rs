@@ -0,0 +1,54 @@
+//! Sending packets between the server and its clients.
+//!
+//! The game only talks to the `Transport` trait, so that it can run over enet, over channels
+//! inside of one process, or over a transport that simulates a bad network. The simulation
+//! delays all packets, but only loses and reorders those sent as `Unreliable`.
+
+mod enet_transport;
+mod memory;
+mod lossy;
+
+#[cfg(test)]
+mod tests;
+
+pub use self::enet_transport::EnetTransport;
+pub use self::memory::{MemoryTransport, MemoryAddress};
+pub use self::lossy::{LossyTransport, LossSettings};
+
+/// Identifies a connection of a transport. Ids are local to the transport, i.e. the two ends
+/// of a connection can know each other under different ids.
+pub type PeerId = u32;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Reliability {
+    // Delivered exactly once, in the order of sending on the same channel
+    Reliable,
+
+    // May be lost, duplicated or arrive in any order
+    Unreliable,
+}
+
+#[derive(Clone, Debug)]
+pub enum TransportEvent {
+    Connect(PeerId),
+    Disconnect(PeerId),
+    Receive(PeerId, u8, Vec<u8>),
+}
+
+pub trait Transport {
+    /// Returns the next event, waiting for at most the given time if there is none yet
+    fn service(&mut self, timeout_ms: u32) -> Result<Option<TransportEvent>, String>;
+
+    /// Queues a packet to be sent to the peer on the given channel
+    fn send(&mut self, peer: PeerId, channel: u8, reliability: Reliability, data: &[u8]);
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn service(&mut self, timeout_ms: u32) -> Result<Option<TransportEvent>, String> {
+        (**self).service(timeout_ms)
+    }
+
+    fn send(&mut self, peer: PeerId, channel: u8, reliability: Reliability, data: &[u8]) {
+        (**self).send(peer, channel, reliability, data)
+    }
+}
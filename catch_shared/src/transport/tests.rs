@@ -0,0 +1,118 @@
+use super::{Transport, TransportEvent, PeerId, Reliability, MemoryTransport, LossyTransport,
+            LossSettings};
+
+/// Returns a server and a client transport that are connected to each other, together with
+/// the id of the client on the server and the id of the server on the client
+fn connected_pair() -> (MemoryTransport, MemoryTransport, PeerId, PeerId) {
+    let mut server = MemoryTransport::new();
+    let mut client = MemoryTransport::new();
+    let server_peer = client.connect(&server.address()).unwrap();
+
+    let client_peer = match server.service(0) {
+        Ok(Some(TransportEvent::Connect(peer))) => peer,
+        event => panic!("expected a connect event, got {:?}", event),
+    };
+
+    (server, client, client_peer, server_peer)
+}
+
+/// Returns the data of all packets that have arrived
+fn receive_all<T: Transport>(transport: &mut T) -> Vec<Vec<u8>> {
+    let mut packets = Vec::new();
+    loop {
+        match transport.service(0) {
+            Ok(Some(TransportEvent::Receive(_, _, data))) => packets.push(data),
+            Ok(None) => return packets,
+            event => panic!("expected a packet, got {:?}", event),
+        }
+    }
+}
+
+#[test]
+fn reliable_packets_keep_order_under_jitter() {
+    let (mut server, client, _, server_peer) = connected_pair();
+    let settings = LossSettings {
+        latency_ms: 50,
+        jitter_ms: 100,
+        loss: 0.5,
+        reorder: 0.5,
+        seed: 1,
+    };
+    let mut client = LossyTransport::with_manual_clock(client, settings);
+
+    for i in 0..100 {
+        client.send(server_peer, 0, Reliability::Reliable, &[i]);
+        if i == 0 {
+            assert!(receive_all(&mut server).is_empty());
+        }
+        client.advance(5);
+    }
+    client.advance(1000);
+
+    assert_eq!(client.num_queued(), 0);
+    assert_eq!(receive_all(&mut server), (0..100).map(|i| vec![i]).collect::<Vec<_>>());
+}
+
+#[test]
+fn unreliable_packets_are_lost_and_reordered() {
+    let (mut server, client, _, server_peer) = connected_pair();
+    let settings = LossSettings {
+        latency_ms: 50,
+        jitter_ms: 0,
+        loss: 0.2,
+        reorder: 0.2,
+        seed: 2,
+    };
+    let mut client = LossyTransport::with_manual_clock(client, settings);
+
+    for i in 0..200 {
+        client.send(server_peer, 0, Reliability::Unreliable, &[i]);
+        client.advance(1);
+    }
+    client.advance(1000);
+
+    let received = receive_all(&mut server).into_iter().map(|data| data[0]).collect::<Vec<_>>();
+    assert!(received.len() > 100 && received.len() < 200,
+            "received {} of 200 packets", received.len());
+    assert!(received.windows(2).any(|pair| pair[0] > pair[1]), "no packet was reordered");
+
+    let mut sorted = received.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(sorted.len(), received.len());
+}
+
+#[test]
+fn dropping_a_transport_disconnects_its_peers() {
+    let (mut server, client, client_peer, _) = connected_pair();
+    let client = LossyTransport::with_manual_clock(client, LossSettings::new());
+
+    drop(client);
+
+    match server.service(0) {
+        Ok(Some(TransportEvent::Disconnect(peer))) => assert_eq!(peer, client_peer),
+        event => panic!("expected a disconnect event, got {:?}", event),
+    }
+}
+
+#[test]
+fn received_packets_are_delayed_in_order() {
+    let (mut server, client, client_peer, _) = connected_pair();
+    let settings = LossSettings {
+        latency_ms: 50,
+        jitter_ms: 100,
+        .. LossSettings::new()
+    };
+    let mut client = LossyTransport::with_manual_clock(client, settings);
+
+    for i in 0..20 {
+        server.send(client_peer, 0, Reliability::Reliable, &[i]);
+    }
+    assert!(receive_all(&mut client).is_empty());
+
+    client.advance(49);
+    assert!(receive_all(&mut client).is_empty());
+
+    client.advance(1000);
+    assert_eq!(receive_all(&mut client), (0..20).map(|i| vec![i]).collect::<Vec<_>>());
+}
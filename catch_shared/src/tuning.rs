@@ -0,0 +1,94 @@
+//! Constants of player movement and the game rules. Loaded by the server and replicated to the
+//! clients, which need the movement constants for prediction.
+
+use std::path::Path;
+
+use config::{self, Line};
+
+/// Where the tuning is loaded from
+pub const TUNING_PATH: &'static str = "data/tuning.cfg";
+
+#[derive(Debug, Clone, Copy, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Tuning {
+    // Player movement
+    pub turn_accel: f32,
+    pub turn_friction: f32,
+    pub move_accel: f32,
+    pub move_friction: f32,
+    pub back_accel: f32,
+    pub strafe_accel: f32,
+    pub min_speed: f32,
+    pub dash_speed: f32,
+    pub dash_duration_s: f32,
+    pub dash_cooldown_s: f32,
+
+    // Time until dead players are respawned
+    pub respawn_time_s: f32,
+
+    // Players can't be killed for this long after spawning
+    pub spawn_invulnerable_s: f32,
+}
+
+impl Default for Tuning {
+    fn default() -> Tuning {
+        Tuning {
+            turn_accel: 1.25,
+            turn_friction: 0.25,
+            move_accel: 1000.0,
+            move_friction: 10.0,
+            back_accel: 500.0,
+            strafe_accel: 900.0,
+            min_speed: 5.0,
+            dash_speed: 600.0,
+            dash_duration_s: 0.3,
+            dash_cooldown_s: 5.0,
+            respawn_time_s: 5.0,
+            spawn_invulnerable_s: 2.5,
+        }
+    }
+}
+
+impl Tuning {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Tuning, String> {
+        config::load(path, "tuning", Tuning::parse)
+    }
+
+    /// Parses `key = value` lines. Anything that is not given keeps its default value.
+    pub fn parse(text: &str) -> Result<Tuning, String> {
+        let mut tuning = Tuning::default();
+
+        try!(config::parse_lines(text, |line| {
+            match line {
+                Line::Section(name) => Err(format!("unexpected section {}", name)),
+                Line::KeyValue(key, value) => {
+                    let value = try!(config::parse_number(value));
+                    match tuning.field_mut(key) {
+                        Some(field) => *field = value,
+                        None => return Err(format!("unknown key: {}", key)),
+                    }
+                    Ok(())
+                }
+            }
+        }));
+
+        Ok(tuning)
+    }
+
+    fn field_mut(&mut self, key: &str) -> Option<&mut f32> {
+        match key {
+            "turn_accel" => Some(&mut self.turn_accel),
+            "turn_friction" => Some(&mut self.turn_friction),
+            "move_accel" => Some(&mut self.move_accel),
+            "move_friction" => Some(&mut self.move_friction),
+            "back_accel" => Some(&mut self.back_accel),
+            "strafe_accel" => Some(&mut self.strafe_accel),
+            "min_speed" => Some(&mut self.min_speed),
+            "dash_speed" => Some(&mut self.dash_speed),
+            "dash_duration" => Some(&mut self.dash_duration_s),
+            "dash_cooldown" => Some(&mut self.dash_cooldown_s),
+            "respawn_time" => Some(&mut self.respawn_time_s),
+            "spawn_invulnerable" => Some(&mut self.spawn_invulnerable_s),
+            _ => None,
+        }
+    }
+}
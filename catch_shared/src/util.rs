@@ -1,9 +1,16 @@
 use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::BuildHasherDefault;
 
 use ecs;
 use ecs::entity::IndexedEntity;
 use ecs::{Aspect, EntityData, EntityIter, ComponentManager};
 
+/// Hash state with fixed keys, since `DefaultHasher::new` does not pick random ones. Hash maps
+/// using this iterate in the same order in every run of the program, which we need for the
+/// simulation to be reproducible.
+pub type FixedHashState = BuildHasherDefault<DefaultHasher>;
+
 pub struct PeriodicTimer {
     period_s: f32,
     accum_s: f32
@@ -47,14 +54,14 @@ impl PeriodicTimer {
 
 pub struct CachedAspect<T: ComponentManager> {
     pub aspect: Aspect<T>,
-    interested: HashMap<ecs::Entity, ecs::IndexedEntity<T>>,
+    interested: HashMap<ecs::Entity, ecs::IndexedEntity<T>, FixedHashState>,
 }
 
 impl<T: ComponentManager> CachedAspect<T> {
     pub fn new(aspect: Aspect<T>) -> CachedAspect<T> {
         CachedAspect {
             aspect: aspect,
-            interested: HashMap::new(),
+            interested: HashMap::with_hasher(FixedHashState::default()),
         }
     }
 